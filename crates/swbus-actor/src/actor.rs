@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::Duration;
+use swbus_edge::simple_client::{IncomingMessage, MessageBody, OutgoingMessage};
+use swbus_proto::message_flags::SwbusMessageFlags;
+use swbus_proto::result::*;
+use swbus_proto::swbus::{DataRequest, RequestResponse, ServicePath};
+use tokio::time::Instant;
+
+/// A state machine handling the messages sent to its service path, see the [crate] documentation.
+///
+/// The handlers are called one at a time. Returning an error, or panicking, makes the supervisor restart the actor
+/// according to its [`RestartPolicy`]: the actor is recreated by its factory, loses its timers, and is started again.
+pub trait Actor: Send + 'static {
+    /// Called when the actor starts, and after each restart.
+    fn started(&mut self, ctx: &mut ActorContext) -> impl Future<Output = Result<()>> + Send {
+        let _ = ctx;
+        async { Ok(()) }
+    }
+
+    /// Handle a message sent to the service path of the actor.
+    fn handle_message(
+        &mut self,
+        ctx: &mut ActorContext,
+        message: IncomingMessage,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Handle a timer set with [`ActorContext::set_timer`] when it fires.
+    fn handle_timer(&mut self, ctx: &mut ActorContext, timer: String) -> impl Future<Output = Result<()>> + Send {
+        let _ = (ctx, timer);
+        async { Ok(()) }
+    }
+}
+
+/// How the supervisor of an actor restarts it when it fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartPolicy {
+    /// Maximum number of restarts within `within`. The actor is stopped when it fails once more.
+    pub max_restarts: usize,
+    /// Window of the restarts counted. The restarts at the same instant always count, even with a zero window.
+    pub within: Duration,
+}
+
+impl RestartPolicy {
+    /// Never restart the actor: it stops on its first failure.
+    pub fn never() -> Self {
+        Self {
+            max_restarts: 0,
+            within: Duration::ZERO,
+        }
+    }
+}
+
+impl Default for RestartPolicy {
+    /// Up to 3 restarts per minute.
+    fn default() -> Self {
+        Self {
+            max_restarts: 3,
+            within: Duration::from_secs(60),
+        }
+    }
+}
+
+/// The interface of an actor to its mailbox, its timers and the other actors.
+///
+/// The messages sent through the context are sent once the handler returns, in order.
+pub struct ActorContext {
+    sp: ServicePath,
+    now: Instant,
+    outbox: Vec<OutgoingMessage>,
+    timers: HashMap<String, Instant>,
+    stop_requested: bool,
+}
+
+impl ActorContext {
+    pub(crate) fn new(sp: ServicePath, now: Instant) -> Self {
+        Self {
+            sp,
+            now,
+            outbox: Vec::new(),
+            timers: HashMap::new(),
+            stop_requested: false,
+        }
+    }
+
+    /// The service path of the actor.
+    pub fn sp(&self) -> &ServicePath {
+        &self.sp
+    }
+
+    /// The current time, as seen by the actor. It is virtual in [`ActorTestHarness`](crate::ActorTestHarness).
+    pub fn now(&self) -> Instant {
+        self.now
+    }
+
+    /// Send a message.
+    pub fn send(&mut self, message: OutgoingMessage) {
+        self.outbox.push(message);
+    }
+
+    /// Send a data request that expects no response.
+    pub fn tell(&mut self, destination: ServicePath, payload: Vec<u8>) {
+        self.send(OutgoingMessage {
+            destination,
+            flags: SwbusMessageFlags::empty(),
+            timeout: None,
+            body: MessageBody::Request(DataRequest::new(payload)),
+        });
+    }
+
+    /// Answer a request, e.g. one sent with [`ActorRef::ask`](crate::ActorRef::ask), with a payload.
+    pub fn reply(&mut self, request: &IncomingMessage, payload: Vec<u8>) {
+        self.send(OutgoingMessage {
+            destination: request.source.clone(),
+            flags: SwbusMessageFlags::empty(),
+            timeout: None,
+            body: MessageBody::Response(RequestResponse::ok_with_payload(request.id, payload)),
+        });
+    }
+
+    /// Answer a request with an error.
+    pub fn reply_error(&mut self, request: &IncomingMessage, error: &SwbusError) {
+        self.send(OutgoingMessage {
+            destination: request.source.clone(),
+            flags: SwbusMessageFlags::empty(),
+            timeout: None,
+            body: MessageBody::Response(RequestResponse::from_error(request.id, error)),
+        });
+    }
+
+    /// Fire the timer `name` after `delay`, replacing the timer of the same name if any.
+    pub fn set_timer(&mut self, name: impl Into<String>, delay: Duration) {
+        self.timers.insert(name.into(), self.now + delay);
+    }
+
+    /// Cancel the timer `name`. Returns whether it was set.
+    pub fn cancel_timer(&mut self, name: &str) -> bool {
+        self.timers.remove(name).is_some()
+    }
+
+    /// Stop the actor once the handler returns. Its mailbox is closed and its timers are dropped.
+    pub fn stop(&mut self) {
+        self.stop_requested = true;
+    }
+
+    pub(crate) fn set_now(&mut self, now: Instant) {
+        self.now = now;
+    }
+
+    pub(crate) fn take_outbox(&mut self) -> Vec<OutgoingMessage> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    pub(crate) fn is_stop_requested(&self) -> bool {
+        self.stop_requested
+    }
+
+    /// Clear the state of the previous incarnation of the actor. The messages it sent before failing are kept.
+    pub(crate) fn reset(&mut self) {
+        self.timers.clear();
+        self.stop_requested = false;
+    }
+
+    /// The earliest timer, as the deadline and name.
+    pub(crate) fn next_timer(&self) -> Option<(Instant, &str)> {
+        self.timers
+            .iter()
+            .map(|(name, deadline)| (*deadline, name.as_str()))
+            .min()
+    }
+
+    /// The timers due at `now`, earliest first, as the deadline and name.
+    pub(crate) fn due_timers(&self, now: Instant) -> Vec<(Instant, String)> {
+        let mut due: Vec<(Instant, String)> = self
+            .timers
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(name, deadline)| (*deadline, name.clone()))
+            .collect();
+        due.sort();
+        due
+    }
+
+    /// Remove the timer `name` if it is still set for `deadline`. Returns whether it was removed.
+    pub(crate) fn take_timer(&mut self, name: &str, deadline: Instant) -> bool {
+        match self.timers.get(name) {
+            Some(set) if *set == deadline => self.timers.remove(name).is_some(),
+            _ => false,
+        }
+    }
+}
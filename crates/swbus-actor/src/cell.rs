@@ -0,0 +1,160 @@
+use crate::actor::{Actor, ActorContext, RestartPolicy};
+use futures_util::FutureExt;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use swbus_edge::simple_client::{IncomingMessage, OutgoingMessage};
+use swbus_proto::result::*;
+use swbus_proto::swbus::{ServicePath, SwbusErrorCode};
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+
+/// An actor with its context and supervisor, independent of how messages and time are fed to it. It is driven by the
+/// task spawned by [`spawn_actor`](crate::spawn_actor), and by [`ActorTestHarness`](crate::ActorTestHarness).
+pub(crate) struct ActorCell<A, F> {
+    actor: A,
+    factory: F,
+    ctx: ActorContext,
+    policy: RestartPolicy,
+    /// Times of the recent restarts, within the window of the policy.
+    restarts: VecDeque<Instant>,
+    restart_count: usize,
+    stopped: bool,
+}
+
+impl<A: Actor, F: Fn() -> A + Send + 'static> ActorCell<A, F> {
+    /// Create the actor and start it.
+    pub async fn start(sp: ServicePath, factory: F, policy: RestartPolicy, now: Instant) -> Self {
+        let mut cell = Self {
+            actor: factory(),
+            factory,
+            ctx: ActorContext::new(sp, now),
+            policy,
+            restarts: VecDeque::new(),
+            restart_count: 0,
+            stopped: false,
+        };
+        cell.run_started(now).await;
+        cell
+    }
+
+    pub fn actor(&self) -> &A {
+        &self.actor
+    }
+
+    pub fn sp(&self) -> &ServicePath {
+        self.ctx.sp()
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Number of times the actor was restarted since it was created.
+    pub fn restart_count(&self) -> usize {
+        self.restart_count
+    }
+
+    pub fn next_timer(&self) -> Option<Instant> {
+        match self.stopped {
+            true => None,
+            false => self.ctx.next_timer().map(|(deadline, _)| deadline),
+        }
+    }
+
+    pub fn take_outbox(&mut self) -> Vec<OutgoingMessage> {
+        self.ctx.take_outbox()
+    }
+
+    pub async fn deliver(&mut self, message: IncomingMessage, now: Instant) {
+        if self.stopped {
+            return;
+        }
+        self.ctx.set_now(now);
+        let result = guarded(self.actor.handle_message(&mut self.ctx, message)).await;
+        self.after_handler(result, now).await;
+    }
+
+    /// Fire the timers due at `now`, earliest first. Only the timers due when the call starts are fired: a timer set
+    /// again by its handler without delay is due at once, and fires on the next call, so the call always returns.
+    pub async fn fire_due_timers(&mut self, now: Instant) {
+        for (deadline, timer) in self.ctx.due_timers(now) {
+            if self.stopped {
+                break;
+            }
+            // An earlier handler may have cancelled or moved the timer, or the actor restarted without it.
+            if !self.ctx.take_timer(&timer, deadline) {
+                continue;
+            }
+            // The actor sees the time its timer was due, so timers fired late behave as if they were on time.
+            self.ctx.set_now(deadline);
+            let result = guarded(self.actor.handle_timer(&mut self.ctx, timer)).await;
+            self.after_handler(result, deadline).await;
+        }
+        self.ctx.set_now(now);
+    }
+
+    async fn run_started(&mut self, now: Instant) {
+        self.ctx.set_now(now);
+        let result = guarded(self.actor.started(&mut self.ctx)).await;
+        self.after_handler(result, now).await;
+    }
+
+    async fn after_handler(&mut self, result: Result<()>, now: Instant) {
+        match result {
+            Ok(_) if self.ctx.is_stop_requested() => {
+                info!(actor = %self.ctx.sp(), "Actor stopped");
+                self.stop();
+            }
+            Ok(_) => {}
+            Err(e) => Box::pin(self.restart(e, now)).await,
+        }
+    }
+
+    /// Restart the actor after it failed with `error`, unless it failed too often. The restarts at the same instant
+    /// always count, so an actor failing as it starts is stopped even with a zero window.
+    async fn restart(&mut self, error: SwbusError, now: Instant) {
+        while self
+            .restarts
+            .front()
+            .is_some_and(|restart| now > *restart && now.duration_since(*restart) >= self.policy.within)
+        {
+            self.restarts.pop_front();
+        }
+        if self.restarts.len() >= self.policy.max_restarts {
+            error!(actor = %self.ctx.sp(), "Actor failed too often, stopping it: {}", error);
+            self.stop();
+            return;
+        }
+
+        warn!(actor = %self.ctx.sp(), "Actor failed, restarting it: {}", error);
+        self.restarts.push_back(now);
+        self.restart_count += 1;
+        self.actor = (self.factory)();
+        self.ctx.reset();
+        self.run_started(now).await;
+    }
+
+    fn stop(&mut self) {
+        self.stopped = true;
+        self.ctx.reset();
+    }
+}
+
+/// Run a handler, turning a panic into an error.
+async fn guarded(handler: impl Future<Output = Result<()>>) -> Result<()> {
+    match AssertUnwindSafe(handler).catch_unwind().await {
+        Ok(result) => result,
+        Err(panic) => {
+            let detail = panic
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            Err(SwbusError::internal(
+                SwbusErrorCode::Fail,
+                format!("Actor panicked: {}", detail),
+            ))
+        }
+    }
+}
@@ -0,0 +1,317 @@
+use crate::actor::{Actor, RestartPolicy};
+use crate::cell::ActorCell;
+use std::time::Duration;
+use swbus_edge::simple_client::{IncomingMessage, MessageBody, MessageId, OutgoingMessage};
+use swbus_proto::message_flags::SwbusMessageFlags;
+use swbus_proto::swbus::{DataRequest, ServicePath};
+use tokio::time::Instant;
+
+/// Drives an actor deterministically, without swbusd: the test delivers the messages, moves the virtual time forward
+/// to fire the timers, and checks the messages the actor sent.
+///
+/// The actor runs with the same supervisor as with [`spawn_actor`](crate::spawn_actor), so restarts can be tested
+/// too.
+pub struct ActorTestHarness<A, F> {
+    cell: ActorCell<A, F>,
+    now: Instant,
+    sent: Vec<OutgoingMessage>,
+    next_message_id: MessageId,
+}
+
+impl<A: Actor, F: Fn() -> A + Send + 'static> ActorTestHarness<A, F> {
+    /// Create the actor with `factory` and start it, with the default restart policy.
+    pub async fn new(sp: ServicePath, factory: F) -> Self {
+        Self::with_restart_policy(sp, factory, RestartPolicy::default()).await
+    }
+
+    pub async fn with_restart_policy(sp: ServicePath, factory: F, policy: RestartPolicy) -> Self {
+        let now = Instant::now();
+        let cell = ActorCell::start(sp, factory, policy, now).await;
+        let mut harness = Self {
+            cell,
+            now,
+            sent: Vec::new(),
+            next_message_id: 1,
+        };
+        harness.collect_sent();
+        harness
+    }
+
+    /// The current incarnation of the actor, e.g. to check its state.
+    pub fn actor(&self) -> &A {
+        self.cell.actor()
+    }
+
+    /// The virtual time of the actor.
+    pub fn now(&self) -> Instant {
+        self.now
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.cell.is_stopped()
+    }
+
+    /// Number of times the actor was restarted.
+    pub fn restart_count(&self) -> usize {
+        self.cell.restart_count()
+    }
+
+    /// Deliver a message to the actor and let it handle it.
+    pub async fn deliver(&mut self, message: IncomingMessage) {
+        self.cell.deliver(message, self.now).await;
+        self.collect_sent();
+    }
+
+    /// Deliver a data request from `source`, and return its message id, e.g. to find the reply.
+    pub async fn tell(&mut self, source: ServicePath, payload: Vec<u8>) -> MessageId {
+        let id = self.next_message_id;
+        self.next_message_id += 1;
+        self.deliver(IncomingMessage {
+            id,
+            source,
+            flags: SwbusMessageFlags::empty(),
+            body: MessageBody::Request(DataRequest::new(payload)),
+        })
+        .await;
+        id
+    }
+
+    /// Move the virtual time forward by `duration`, firing the timers due meanwhile in order, including the ones they
+    /// set again. A timer set again by its handler without delay fires once per call.
+    pub async fn advance(&mut self, duration: Duration) {
+        let target = self.now + duration;
+        let mut fired = None;
+        while let Some(deadline) = self
+            .cell
+            .next_timer()
+            .filter(|deadline| *deadline <= target && Some(*deadline) != fired)
+        {
+            self.cell.fire_due_timers(deadline).await;
+            fired = Some(deadline);
+        }
+        self.now = target;
+        self.collect_sent();
+    }
+
+    /// Take the messages sent by the actor so far, in order.
+    pub fn take_sent(&mut self) -> Vec<OutgoingMessage> {
+        std::mem::take(&mut self.sent)
+    }
+
+    fn collect_sent(&mut self) {
+        let sent = self.cell.take_outbox();
+        self.sent.extend(sent);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ActorContext;
+    use swbus_proto::result::*;
+    use swbus_proto::swbus::{request_response::ResponseBody, RequestResponse, SwbusErrorCode};
+
+    /// Counts the requests it receives, and reports the count to the monitor on every tick.
+    struct Counter {
+        count: u64,
+    }
+
+    fn sp(s: &str) -> ServicePath {
+        ServicePath::from_string(s).unwrap()
+    }
+
+    fn monitor() -> ServicePath {
+        sp("region-a.cluster-a.10.0.0.1-dpu0/monitor/0")
+    }
+
+    impl Actor for Counter {
+        async fn started(&mut self, ctx: &mut ActorContext) -> Result<()> {
+            ctx.set_timer("tick", Duration::from_secs(10));
+            Ok(())
+        }
+
+        async fn handle_message(&mut self, ctx: &mut ActorContext, message: IncomingMessage) -> Result<()> {
+            let MessageBody::Request(request) = &message.body else {
+                return Ok(());
+            };
+            match request.payload.as_slice() {
+                b"fail" => Err(SwbusError::internal(SwbusErrorCode::Fail, "failed".to_string())),
+                b"panic" => panic!("counter panicked"),
+                b"stop" => {
+                    ctx.stop();
+                    Ok(())
+                }
+                _ => {
+                    self.count += 1;
+                    ctx.reply(&message, self.count.to_string().into_bytes());
+                    Ok(())
+                }
+            }
+        }
+
+        async fn handle_timer(&mut self, ctx: &mut ActorContext, timer: String) -> Result<()> {
+            assert_eq!(timer, "tick");
+            ctx.tell(monitor(), self.count.to_string().into_bytes());
+            ctx.set_timer("tick", Duration::from_secs(10));
+            Ok(())
+        }
+    }
+
+    async fn new_counter(policy: RestartPolicy) -> ActorTestHarness<Counter, impl Fn() -> Counter + Send + 'static> {
+        ActorTestHarness::with_restart_policy(
+            sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni0"),
+            || Counter { count: 0 },
+            policy,
+        )
+        .await
+    }
+
+    fn payloads(sent: Vec<OutgoingMessage>) -> Vec<(ServicePath, String)> {
+        sent.into_iter()
+            .map(|message| {
+                let payload = match message.body {
+                    MessageBody::Request(request) => request.payload,
+                    MessageBody::Response(RequestResponse {
+                        response_body: Some(ResponseBody::Payload(payload)),
+                        ..
+                    }) => payload,
+                    body => panic!("Unexpected message {:?}", body),
+                };
+                (message.destination, String::from_utf8(payload).unwrap())
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn actor_replies_and_fires_timers_in_virtual_time() {
+        let mut counter = new_counter(RestartPolicy::default()).await;
+        let client = sp("region-a.cluster-a.10.0.0.1-dpu0/cli/0");
+        counter.tell(client.clone(), b"inc".to_vec()).await;
+        counter.tell(client.clone(), b"inc".to_vec()).await;
+        assert_eq!(
+            payloads(counter.take_sent()),
+            vec![(client.clone(), "1".to_string()), (client, "2".to_string())]
+        );
+
+        counter.advance(Duration::from_secs(9)).await;
+        assert!(counter.take_sent().is_empty());
+        // the timer fires twice within the 25s, and is left due at 30s
+        counter.advance(Duration::from_secs(16)).await;
+        assert_eq!(
+            payloads(counter.take_sent()),
+            vec![(monitor(), "2".to_string()), (monitor(), "2".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn failing_actor_is_restarted_from_a_fresh_state() {
+        let policy = RestartPolicy {
+            max_restarts: 2,
+            within: Duration::from_secs(60),
+        };
+        let mut counter = new_counter(policy).await;
+        let client = sp("region-a.cluster-a.10.0.0.1-dpu0/cli/0");
+        counter.tell(client.clone(), b"inc".to_vec()).await;
+
+        counter.tell(client.clone(), b"fail".to_vec()).await;
+        assert_eq!(counter.restart_count(), 1);
+        assert_eq!(counter.actor().count, 0);
+
+        // panics are failures as well
+        counter.tell(client.clone(), b"panic".to_vec()).await;
+        assert_eq!(counter.restart_count(), 2);
+        assert!(!counter.is_stopped());
+
+        // the restarts age out of the window of the policy
+        counter.advance(Duration::from_secs(60)).await;
+        counter.tell(client.clone(), b"fail".to_vec()).await;
+        assert_eq!(counter.restart_count(), 3);
+        counter.tell(client.clone(), b"fail".to_vec()).await;
+        assert!(!counter.is_stopped());
+        counter.tell(client.clone(), b"fail".to_vec()).await;
+        assert!(counter.is_stopped());
+
+        // a stopped actor doesn't handle anything anymore
+        counter.take_sent();
+        counter.tell(client, b"inc".to_vec()).await;
+        counter.advance(Duration::from_secs(60)).await;
+        assert!(counter.take_sent().is_empty());
+    }
+
+    /// Fails every time it starts.
+    struct Broken;
+
+    impl Actor for Broken {
+        async fn started(&mut self, _ctx: &mut ActorContext) -> Result<()> {
+            Err(SwbusError::internal(SwbusErrorCode::Fail, "broken".to_string()))
+        }
+
+        async fn handle_message(&mut self, _ctx: &mut ActorContext, _message: IncomingMessage) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn actor_failing_as_it_starts_is_stopped_with_a_zero_window() {
+        let policy = RestartPolicy {
+            max_restarts: 3,
+            within: Duration::ZERO,
+        };
+        let broken = tokio::time::timeout(
+            Duration::from_secs(1),
+            ActorTestHarness::with_restart_policy(sp("region-a.cluster-a.10.0.0.1-dpu0/broken/0"), || Broken, policy),
+        )
+        .await
+        .unwrap();
+        assert!(broken.is_stopped());
+        assert_eq!(broken.restart_count(), 3);
+    }
+
+    /// Sets its timer again without delay every time it fires.
+    struct Spinner {
+        fired: u64,
+    }
+
+    impl Actor for Spinner {
+        async fn started(&mut self, ctx: &mut ActorContext) -> Result<()> {
+            ctx.set_timer("spin", Duration::ZERO);
+            Ok(())
+        }
+
+        async fn handle_message(&mut self, _ctx: &mut ActorContext, _message: IncomingMessage) -> Result<()> {
+            Ok(())
+        }
+
+        async fn handle_timer(&mut self, ctx: &mut ActorContext, _timer: String) -> Result<()> {
+            self.fired += 1;
+            ctx.set_timer("spin", Duration::ZERO);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn timer_set_again_without_delay_fires_once_per_advance() {
+        let mut spinner = ActorTestHarness::new(sp("region-a.cluster-a.10.0.0.1-dpu0/spinner/0"), || Spinner {
+            fired: 0,
+        })
+        .await;
+        for fired in 1..=3 {
+            tokio::time::timeout(Duration::from_secs(1), spinner.advance(Duration::from_secs(1)))
+                .await
+                .unwrap();
+            assert_eq!(spinner.actor().fired, fired);
+        }
+    }
+
+    #[tokio::test]
+    async fn actor_stops_itself() {
+        let mut counter = new_counter(RestartPolicy::never()).await;
+        counter
+            .tell(sp("region-a.cluster-a.10.0.0.1-dpu0/cli/0"), b"stop".to_vec())
+            .await;
+        assert!(counter.is_stopped());
+        assert_eq!(counter.restart_count(), 0);
+        counter.advance(Duration::from_secs(60)).await;
+        assert!(counter.take_sent().is_empty());
+    }
+}
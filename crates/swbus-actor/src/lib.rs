@@ -0,0 +1,17 @@
+//! Actors on top of the swbus edge runtime.
+//!
+//! An actor is a long-lived state machine addressed by a [`ServicePath`](swbus_proto::swbus::ServicePath), e.g. one
+//! per DPU, HA set, HA scope or ENI in hamgrd. Each actor has its own mailbox, bound to its service path through a
+//! [`SimpleSwbusEdgeClient`](swbus_edge::simple_client::SimpleSwbusEdgeClient), and handles its messages and timers
+//! one at a time, so its state needs no locking. A failing actor is restarted from a fresh state by its supervisor.
+//!
+//! Actors are run against swbusd with [`spawn_actor`], and driven deterministically in tests with
+//! [`ActorTestHarness`].
+pub mod actor;
+mod cell;
+pub mod harness;
+pub mod runtime;
+
+pub use actor::{Actor, ActorContext, RestartPolicy};
+pub use harness::ActorTestHarness;
+pub use runtime::{spawn_actor, ActorHandle, ActorRef};
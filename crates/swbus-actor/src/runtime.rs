@@ -0,0 +1,253 @@
+use crate::actor::{Actor, RestartPolicy};
+use crate::cell::ActorCell;
+use futures_util::{SinkExt, StreamExt};
+use std::sync::Arc;
+use std::time::Duration;
+use swbus_edge::simple_client::{MessageBody, MessageId, OutgoingMessage, SimpleSwbusEdgeClient};
+use swbus_edge::SwbusEdgeRuntime;
+use swbus_proto::message_flags::SwbusMessageFlags;
+use swbus_proto::result::*;
+use swbus_proto::swbus::{request_response::ResponseBody, DataRequest, ServicePath};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::warn;
+
+/// Start an actor with its mailbox bound to `sp`. The actor is created by `factory`, which is called again each time
+/// the supervisor restarts it.
+///
+/// The actor runs until it stops itself, fails more often than `policy` allows, or is aborted through the returned
+/// handle. Its service path is unregistered from the runtime when it stops. Fails if the mailbox can't be added to the
+/// runtime.
+pub async fn spawn_actor<A, F>(
+    rt: Arc<SwbusEdgeRuntime>,
+    sp: ServicePath,
+    factory: F,
+    policy: RestartPolicy,
+) -> Result<ActorHandle>
+where
+    A: Actor,
+    F: Fn() -> A + Send + 'static,
+{
+    let client = SimpleSwbusEdgeClient::new(rt, sp.clone()).await?;
+    let task = tokio::spawn(run_actor(client, sp.clone(), factory, policy));
+    Ok(ActorHandle { sp, task })
+}
+
+async fn run_actor<A, F>(client: SimpleSwbusEdgeClient, sp: ServicePath, factory: F, policy: RestartPolicy)
+where
+    A: Actor,
+    F: Fn() -> A + Send + 'static,
+{
+    let (mut sink, mut stream) = client.split();
+    let mut cell = ActorCell::start(sp, factory, policy, Instant::now()).await;
+    loop {
+        for message in cell.take_outbox() {
+            if let Err(e) = sink.send(message).await {
+                warn!(actor = %cell.sp(), "Failed to send message: {}", e);
+            }
+        }
+        if cell.is_stopped() {
+            break;
+        }
+
+        let next_timer = cell.next_timer();
+        tokio::select! {
+            message = stream.next() => match message {
+                Some(message) => cell.deliver(message, Instant::now()).await,
+                None => break,
+            },
+            _ = tokio::time::sleep_until(next_timer.unwrap_or_else(Instant::now)), if next_timer.is_some() => {
+                cell.fire_due_timers(Instant::now()).await;
+            }
+        }
+    }
+}
+
+/// Handle of an actor started by [`spawn_actor`]. Dropping it leaves the actor running.
+pub struct ActorHandle {
+    sp: ServicePath,
+    task: JoinHandle<()>,
+}
+
+impl ActorHandle {
+    /// The service path of the actor.
+    pub fn sp(&self) -> &ServicePath {
+        &self.sp
+    }
+
+    /// Stop the actor right away, without waiting for the message it handles.
+    pub fn abort(&self) {
+        self.task.abort();
+    }
+
+    /// Wait until the actor stops.
+    pub async fn join(self) {
+        let _ = self.task.await;
+    }
+}
+
+/// Sends messages to an actor from outside of the actors, through the client of the sender.
+#[derive(Clone)]
+pub struct ActorRef {
+    client: Arc<SimpleSwbusEdgeClient>,
+    sp: ServicePath,
+}
+
+impl ActorRef {
+    pub fn new(client: Arc<SimpleSwbusEdgeClient>, sp: ServicePath) -> Self {
+        Self { client, sp }
+    }
+
+    /// The service path of the actor.
+    pub fn sp(&self) -> &ServicePath {
+        &self.sp
+    }
+
+    /// Send a data request to the actor without waiting for a response.
+    pub async fn tell(&self, payload: Vec<u8>) -> Result<MessageId> {
+        self.client
+            .send(OutgoingMessage {
+                destination: self.sp.clone(),
+                flags: SwbusMessageFlags::empty(),
+                timeout: None,
+                body: MessageBody::Request(DataRequest::new(payload)),
+            })
+            .await
+    }
+
+    /// Send a data request to the actor, and wait for the payload it replies with, see
+    /// [`ActorContext::reply`](crate::ActorContext::reply). Errors replied by the actor, and the ones of swbusd, are
+    /// returned as errors, see [`SimpleSwbusEdgeClient::request`].
+    pub async fn ask(&self, payload: Vec<u8>, timeout: Duration) -> Result<Vec<u8>> {
+        let response = self.client.request(self.sp.clone(), payload, timeout).await?;
+        match response.response_body {
+            Some(ResponseBody::Payload(payload)) => Ok(payload),
+            _ => Ok(Vec::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ActorContext;
+    use swbus_edge::mock::MockSwbus;
+    use swbus_edge::simple_client::IncomingMessage;
+    use swbus_proto::swbus::SwbusErrorCode;
+
+    /// Replies to every request with its payload.
+    struct Echo;
+
+    impl Actor for Echo {
+        async fn handle_message(&mut self, ctx: &mut ActorContext, message: IncomingMessage) -> Result<()> {
+            if let MessageBody::Request(request) = &message.body {
+                ctx.reply(&message, request.payload.clone());
+            }
+            Ok(())
+        }
+    }
+
+    /// Counts the requests told to it, replies with the count to "get", and fails on "fail".
+    struct Counter {
+        count: u64,
+    }
+
+    impl Actor for Counter {
+        async fn handle_message(&mut self, ctx: &mut ActorContext, message: IncomingMessage) -> Result<()> {
+            let MessageBody::Request(request) = &message.body else {
+                return Ok(());
+            };
+            match request.payload.as_slice() {
+                b"get" => ctx.reply(&message, self.count.to_string().into_bytes()),
+                b"fail" => return Err(SwbusError::internal(SwbusErrorCode::Fail, "failed".to_string())),
+                _ => self.count += 1,
+            }
+            Ok(())
+        }
+    }
+
+    fn sp(s: &str) -> ServicePath {
+        ServicePath::from_string(s).unwrap()
+    }
+
+    async fn new_client(mock: &MockSwbus) -> Arc<SimpleSwbusEdgeClient> {
+        Arc::new(
+            SimpleSwbusEdgeClient::new(mock.runtime(), sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/cli/0"))
+                .await
+                .unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn actor_refs_ask_actors_through_the_runtime() {
+        let mock = MockSwbus::new(sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0"));
+        let echo = sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/echo/0");
+        let handle = spawn_actor(mock.runtime(), echo.clone(), || Echo, RestartPolicy::never())
+            .await
+            .unwrap();
+        mock.expect_registered(&echo).await;
+
+        let client = new_client(&mock).await;
+        let actor = ActorRef::new(client.clone(), echo.clone());
+        assert_eq!(
+            actor.ask(b"hello".to_vec(), Duration::from_secs(1)).await.unwrap(),
+            b"hello"
+        );
+
+        // actors on other nodes are unreachable until swbusd has a route to them
+        let remote = ActorRef::new(client, sp("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0/echo/0"));
+        let error = remote.ask(b"hello".to_vec(), Duration::from_secs(1)).await.unwrap_err();
+        assert_eq!(error.code(), SwbusErrorCode::NoRoute);
+
+        handle.abort();
+        handle.join().await;
+        mock.expect_unregistered(&echo).await;
+    }
+
+    #[tokio::test]
+    async fn actor_refs_tell_actors_in_order() {
+        let mock = MockSwbus::new(sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0"));
+        let counter = sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/counter/0");
+        let _handle = spawn_actor(
+            mock.runtime(),
+            counter.clone(),
+            || Counter { count: 0 },
+            RestartPolicy::never(),
+        )
+        .await
+        .unwrap();
+
+        let actor = ActorRef::new(new_client(&mock).await, counter);
+        for _ in 0..3 {
+            actor.tell(b"inc".to_vec()).await.unwrap();
+        }
+        assert_eq!(actor.ask(b"get".to_vec(), Duration::from_secs(1)).await.unwrap(), b"3");
+    }
+
+    #[tokio::test]
+    async fn failing_actor_is_restarted_until_the_policy_gives_up() {
+        let mock = MockSwbus::new(sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0"));
+        let counter = sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/counter/0");
+        let policy = RestartPolicy {
+            max_restarts: 1,
+            within: Duration::from_secs(60),
+        };
+        let handle = spawn_actor(mock.runtime(), counter.clone(), || Counter { count: 0 }, policy)
+            .await
+            .unwrap();
+        mock.expect_registered(&counter).await;
+
+        // the restarted actor starts from a fresh state
+        let actor = ActorRef::new(new_client(&mock).await, counter.clone());
+        actor.tell(b"inc".to_vec()).await.unwrap();
+        actor.tell(b"fail".to_vec()).await.unwrap();
+        assert_eq!(actor.ask(b"get".to_vec(), Duration::from_secs(1)).await.unwrap(), b"0");
+
+        // the second failure within the window stops it, and its mailbox goes away
+        actor.tell(b"fail".to_vec()).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(1), handle.join())
+            .await
+            .unwrap();
+        mock.expect_unregistered(&counter).await;
+    }
+}
@@ -1,8 +1,10 @@
 use crate::wait_for_response;
 use clap::Parser;
+use std::collections::BTreeSet;
 use swbus_proto::swbus::*;
 use tabled::{Table, Tabled};
 use tokio::sync::mpsc;
+use tokio::time::{self, Duration, Instant};
 use tracing::info;
 
 const CMD_TIMEOUT: u32 = 10;
@@ -17,6 +19,7 @@ pub struct ShowCmd {
 enum ShowSubCmd {
     Route(ShowRouteCmd),
     Connections(ShowConnectionsCmd),
+    Registrations(ShowRegistrationsCmd),
 }
 
 #[derive(Parser, Debug)]
@@ -25,6 +28,13 @@ pub struct ShowRouteCmd {}
 #[derive(Parser, Debug)]
 pub struct ShowConnectionsCmd {}
 
+#[derive(Parser, Debug)]
+pub struct ShowRegistrationsCmd {
+    /// Only query this edge runtime. By default, swbusd and all edge runtimes connected to it are queried.
+    #[arg(value_parser = ServicePath::from_string)]
+    edge: Option<ServicePath>,
+}
+
 trait ShowCmdHandler {
     fn create_request(&self) -> ManagementRequest;
     fn process_response(&self, response: &RequestResponse);
@@ -39,6 +49,13 @@ struct RouteDisplay {
     nh_service_path: String,
 }
 
+#[derive(Tabled)]
+struct RegistrationDisplay {
+    service_path: String,
+    registered_at: String,
+    conn_id: String,
+}
+
 impl super::CmdHandler for ShowCmd {
     async fn handle(&self, ctx: &super::CommandContext) {
         // Create a channel to receive response
@@ -49,7 +66,8 @@ impl super::CmdHandler for ShowCmd {
         let dst_sp = ctx.sp.clone_for_local_mgmt();
 
         // Register the channel to the runtime to receive response
-        ctx.runtime
+        let _handler = ctx
+            .runtime
             .lock()
             .await
             .add_handler(src_sp.clone(), recv_queue_tx)
@@ -59,6 +77,12 @@ impl super::CmdHandler for ShowCmd {
         let sub_cmd: &dyn ShowCmdHandler = match &self.subcommand {
             ShowSubCmd::Route(show_route_args) => show_route_args,
             ShowSubCmd::Connections(show_connections_args) => show_connections_args,
+            ShowSubCmd::Registrations(show_registrations_args) => {
+                show_registrations_args
+                    .handle(ctx, &src_sp, &dst_sp, &mut recv_queue_rx)
+                    .await;
+                return;
+            }
         };
 
         let mgmt_request = sub_cmd.create_request();
@@ -143,3 +167,106 @@ impl ShowCmdHandler for ShowConnectionsCmd {
         info!("not implemented")
     }
 }
+
+impl ShowRegistrationsCmd {
+    async fn handle(
+        &self,
+        ctx: &super::CommandContext,
+        src_sp: &ServicePath,
+        mgmt_sp: &ServicePath,
+        recv_queue_rx: &mut mpsc::Receiver<SwbusMessage>,
+    ) {
+        let mut registrations = Vec::new();
+        match &self.edge {
+            Some(edge) => match query_registrations(ctx, src_sp, edge, recv_queue_rx).await {
+                Ok(entries) => registrations.extend(entries),
+                Err(e) => info!("{}: {}", edge.to_longest_path(), e),
+            },
+            None => {
+                let entries = match query_registrations(ctx, src_sp, mgmt_sp, recv_queue_rx).await {
+                    Ok(entries) => entries,
+                    Err(e) => {
+                        info!("{}", e);
+                        return;
+                    }
+                };
+                // Each edge runtime knows the services registered in it.
+                let edges: BTreeSet<ServicePath> = entries
+                    .iter()
+                    .filter_map(|entry| entry.edge_service_path.clone())
+                    .filter(|edge| edge != &ctx.sp)
+                    .collect();
+                registrations.extend(entries);
+                for edge in &edges {
+                    match query_registrations(ctx, src_sp, edge, recv_queue_rx).await {
+                        Ok(entries) => registrations.extend(entries),
+                        Err(e) => info!("{}: {}", edge.to_longest_path(), e),
+                    }
+                }
+            }
+        }
+
+        let mut rows: Vec<RegistrationDisplay> = registrations
+            .into_iter()
+            .map(|entry| RegistrationDisplay {
+                service_path: entry
+                    .service_path
+                    .as_ref()
+                    .map(|sp| sp.to_longest_path())
+                    .unwrap_or_default(),
+                registered_at: entry
+                    .edge_service_path
+                    .as_ref()
+                    .map(|sp| sp.to_longest_path())
+                    .unwrap_or_else(|| "swbusd".to_string()),
+                conn_id: entry.conn_id,
+            })
+            .collect();
+        rows.sort_by(|a, b| a.service_path.cmp(&b.service_path));
+        rows.dedup_by(|a, b| a.service_path == b.service_path && a.registered_at == b.registered_at);
+        info!("{}", Table::new(rows))
+    }
+}
+
+/// Send a registration query to a swbusd or an edge runtime and wait for the registrations.
+async fn query_registrations(
+    ctx: &super::CommandContext,
+    src_sp: &ServicePath,
+    target: &ServicePath,
+    recv_queue_rx: &mut mpsc::Receiver<SwbusMessage>,
+) -> Result<Vec<RegistrationEntry>, String> {
+    let header = SwbusMessageHeader::new(src_sp.clone(), target.clone(), ctx.id_generator.generate());
+    let request_id = header.id;
+    let request_msg = SwbusMessage::new(
+        header,
+        swbus_message::Body::RegistrationQueryRequest(RegistrationQueryRequest::default()),
+    );
+    ctx.runtime
+        .lock()
+        .await
+        .send(request_msg)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let deadline = Instant::now() + Duration::from_secs(CMD_TIMEOUT as u64);
+    loop {
+        let msg = match time::timeout_at(deadline, recv_queue_rx.recv()).await {
+            Ok(Some(msg)) => msg,
+            Ok(None) => return Err("channel broken".to_string()),
+            Err(_) => return Err("request timeout".to_string()),
+        };
+        match msg.body {
+            Some(swbus_message::Body::RegistrationQueryResponse(response)) if response.request_id == request_id => {
+                return Ok(response.entries);
+            }
+            Some(swbus_message::Body::Response(response)) if response.request_id == request_id => {
+                return Err(format!(
+                    "{}:{}",
+                    response.error_code().as_str_name(),
+                    response.error_message
+                ));
+            }
+            _ => continue,
+        }
+    }
+}
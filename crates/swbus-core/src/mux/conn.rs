@@ -3,16 +3,19 @@ use super::SwbusConnInfo;
 use super::SwbusConnProxy;
 use super::SwbusConnWorker;
 use super::SwbusMultiplexer;
+use std::future::Future;
 use std::io;
 use std::str::FromStr;
 use std::sync::Arc;
+use swbus_proto::protocol::SwbusProtocolSupport;
 use swbus_proto::result::*;
 use swbus_proto::swbus::swbus_service_client::SwbusServiceClient;
 use swbus_proto::swbus::*;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
 use tokio_stream::wrappers::ReceiverStream;
 use tokio_stream::StreamExt;
 use tokio_util::sync::CancellationToken;
+use tonic::codec::CompressionEncoding;
 use tonic::metadata::MetadataValue;
 use tonic::transport::{Channel, Endpoint};
 use tonic::{Request, Status, Streaming};
@@ -23,8 +26,9 @@ pub struct SwbusConn {
     // Connection information
     info: Arc<SwbusConnInfo>,
 
-    // Worker task
+    // Worker task, started once the connection is registered in the connection store
     worker_task: Option<tokio::task::JoinHandle<Result<()>>>,
+    worker_start_tx: Option<oneshot::Sender<()>>,
     shutdown_ct: CancellationToken,
 
     // Outgoing message queue
@@ -40,6 +44,7 @@ impl SwbusConn {
         SwbusConn {
             info: conn_info.clone(),
             worker_task: None,
+            worker_start_tx: None,
             shutdown_ct: CancellationToken::new(),
             send_queue_tx,
         }
@@ -57,6 +62,34 @@ impl SwbusConn {
         self.shutdown_ct.cancel();
         Ok(())
     }
+
+    /// Spawn the worker of the connection. It waits for the connection to be registered, see
+    /// [`take_worker_start`](Self::take_worker_start), so the connection loss it reports always finds the connection
+    /// in the connection store. It never runs if the connection is dropped before that.
+    pub(crate) fn spawn_worker(&mut self, worker: impl Future<Output = Result<()>> + Send + 'static) {
+        let (worker_start_tx, worker_start_rx) = oneshot::channel();
+        self.worker_start_tx = Some(worker_start_tx);
+        self.worker_task = Some(tokio::spawn(async move {
+            match worker_start_rx.await {
+                Ok(_) => worker.await,
+                Err(_) => Ok(()),
+            }
+        }));
+    }
+
+    /// Take the signal starting the worker, to be sent once the connection is registered.
+    pub(crate) fn take_worker_start(&mut self) -> Option<oneshot::Sender<()>> {
+        self.worker_start_tx.take()
+    }
+}
+
+/// gRPC encoding of the messages sent with the given compression.
+fn grpc_compression_encoding(compression: Compression) -> Option<CompressionEncoding> {
+    match compression {
+        Compression::None => None,
+        Compression::Gzip => Some(CompressionEncoding::Gzip),
+        Compression::Zstd => Some(CompressionEncoding::Zstd),
+    }
 }
 
 // Client-side connection factory and task entry
@@ -84,54 +117,50 @@ impl SwbusConn {
             }
         };
 
-        let client = SwbusServiceClient::new(channel);
+        let mut client = SwbusServiceClient::new(channel);
+        if let Some(encoding) = grpc_compression_encoding(conn_info.compression()) {
+            // The server answers with the same encoding, as long as it supports it.
+            client = client.send_compressed(encoding).accept_compressed(encoding);
+        }
         Self::start_client_worker_task(conn_info, client, mux, conn_store).await
     }
 
     async fn start_client_worker_task(
         conn_info: Arc<SwbusConnInfo>,
-        client: SwbusServiceClient<Channel>,
+        mut client: SwbusServiceClient<Channel>,
         mux: Arc<SwbusMultiplexer>,
         conn_store: Arc<SwbusConnStore>,
     ) -> Result<SwbusConn> {
         let (send_queue_tx, send_queue_rx) = mpsc::channel(16);
+        let (conn_info, incoming_stream) = Self::open_client_stream(&conn_info, &mut client, send_queue_rx).await?;
         let mut conn = SwbusConn::new(&conn_info, send_queue_tx);
 
         let conn_info_for_worker = conn.info().clone();
         let shutdown_ct_for_worker = conn.shutdown_ct.clone();
-        let worker_task = tokio::spawn(async move {
-            Self::run_client_worker_task(
-                conn_info_for_worker,
-                client,
-                shutdown_ct_for_worker,
-                send_queue_rx,
-                mux,
-                conn_store,
-            )
-            .await
-        });
-        conn.worker_task = Some(worker_task);
+        conn.spawn_worker(Self::run_client_worker_task(
+            conn_info_for_worker,
+            client,
+            shutdown_ct_for_worker,
+            incoming_stream,
+            mux,
+            conn_store,
+        ));
 
         Ok(conn)
     }
 
-    /// This function is the entry point for the client worker task.
-    /// It creates a stream of messages from the message queue and sends it to the server.
-    /// It also receives messages from the server and forwards them to the message queue.
+    /// Open the message stream to the server and negotiate the protocol version with it.
+    /// It returns the connection information with the negotiated protocol and the incoming message stream.
     ///
     /// parameters:
     /// - conn_info: The connection information.
     /// - client: The SwbusServiceClient.
-    /// - control_queue_rx: The control message queue
     /// - send_queue_rx: The outgoing message queue rx end.
-    async fn run_client_worker_task(
-        conn_info: Arc<SwbusConnInfo>,
-        mut client: SwbusServiceClient<Channel>,
-        shutdown_ct: CancellationToken,
+    async fn open_client_stream(
+        conn_info: &SwbusConnInfo,
+        client: &mut SwbusServiceClient<Channel>,
         send_queue_rx: mpsc::Receiver<Result<SwbusMessage, Status>>,
-        mux: Arc<SwbusMultiplexer>,
-        conn_store: Arc<SwbusConnStore>,
-    ) -> Result<()> {
+    ) -> Result<(Arc<SwbusConnInfo>, Streaming<SwbusMessage>)> {
         let request_stream = ReceiverStream::new(send_queue_rx)
             .map(|result| result.expect("Not expecting grpc client adding messages with error status"));
 
@@ -142,6 +171,7 @@ impl SwbusConn {
             .expect("missing local service path")
             .to_string();
 
+        let local_protocol = SwbusProtocolSupport::current();
         let meta = stream_message_request.metadata_mut();
 
         meta.insert(
@@ -152,18 +182,66 @@ impl SwbusConn {
             SWBUS_CONNECTION_TYPE,
             MetadataValue::from_str(conn_info.connection_type().as_str_name()).unwrap(),
         );
+        meta.insert(
+            SWBUS_PROTOCOL_VERSION,
+            MetadataValue::from_str(&local_protocol.version_metadata()).unwrap(),
+        );
+        meta.insert(
+            SWBUS_PROTOCOL_FEATURES,
+            MetadataValue::from_str(&local_protocol.features_metadata()).unwrap(),
+        );
 
-        let incoming_stream = match client.stream_messages(stream_message_request).await {
-            Ok(response) => response.into_inner(),
+        let response = match client.stream_messages(stream_message_request).await {
+            Ok(response) => response,
             Err(e) => {
                 error!("Failed to establish message streaming: {}.", e);
+                return Err(e.into());
+            }
+        };
+
+        // The server answers with the negotiated version, or nothing if it predates the negotiation.
+        let metadata = response.metadata();
+        let negotiated = SwbusProtocolSupport::from_metadata(
+            metadata.get(SWBUS_PROTOCOL_VERSION).and_then(|v| v.to_str().ok()),
+            metadata.get(SWBUS_PROTOCOL_FEATURES).and_then(|v| v.to_str().ok()),
+        )
+        .and_then(|server_protocol| local_protocol.negotiate(&server_protocol));
+        let negotiated = match negotiated {
+            Ok(negotiated) => negotiated,
+            Err(e) => {
+                error!("Failed to negotiate protocol with the server: {}.", e);
                 return Err(SwbusError::connection(
                     SwbusErrorCode::ConnectionError,
                     io::Error::new(io::ErrorKind::Unsupported, e.to_string()),
                 ));
             }
         };
+        info!(
+            version = negotiated.version,
+            features = negotiated.features,
+            "Negotiated protocol with the server"
+        );
 
+        let conn_info = Arc::new(conn_info.clone().with_negotiated_protocol(negotiated));
+        Ok((conn_info, response.into_inner()))
+    }
+
+    /// This function is the entry point for the client worker task.
+    /// It receives messages from the server and forwards them to the multiplexer until the stream ends.
+    ///
+    /// parameters:
+    /// - conn_info: The connection information.
+    /// - client: The SwbusServiceClient, kept alive for as long as the stream.
+    /// - shutdown_ct: The cancellation token to stop the worker.
+    /// - incoming_stream: The incoming message stream.
+    async fn run_client_worker_task(
+        conn_info: Arc<SwbusConnInfo>,
+        _client: SwbusServiceClient<Channel>,
+        shutdown_ct: CancellationToken,
+        incoming_stream: Streaming<SwbusMessage>,
+        mux: Arc<SwbusMultiplexer>,
+        conn_store: Arc<SwbusConnStore>,
+    ) -> Result<()> {
         let mut conn_worker = SwbusConnWorker::new(conn_info, shutdown_ct, incoming_stream, mux, conn_store);
         conn_worker.run().await
     }
@@ -200,17 +278,13 @@ impl SwbusConn {
 
         let conn_info_for_worker = conn_info.clone();
         let shutdown_ct_for_worker = conn.shutdown_ct.clone();
-        let worker_task = tokio::spawn(async move {
-            Self::run_server_worker_task(
-                conn_info_for_worker,
-                incoming_stream,
-                shutdown_ct_for_worker,
-                mux,
-                conn_store,
-            )
-            .await
-        });
-        conn.worker_task = Some(worker_task);
+        conn.spawn_worker(Self::run_server_worker_task(
+            conn_info_for_worker,
+            incoming_stream,
+            shutdown_ct_for_worker,
+            mux,
+            conn_store,
+        ));
 
         conn
     }
@@ -1,5 +1,7 @@
 use getset::{CopyGetters, Getters};
 use std::net::SocketAddr;
+use swbus_proto::protocol::SwbusNegotiatedProtocol;
+use swbus_proto::swbus::Compression;
 use swbus_proto::swbus::ConnectionType;
 use swbus_proto::swbus::ServicePath;
 
@@ -29,6 +31,14 @@ pub struct SwbusConnInfo {
 
     #[getset(get = "pub")]
     remote_service_path: ServicePath,
+
+    // Protocol version and features agreed with the peer when the message stream is set up
+    #[getset(get_copy = "pub")]
+    negotiated_protocol: Option<SwbusNegotiatedProtocol>,
+
+    // gRPC compression requested for the connection. Only used in client mode, the server follows the client.
+    #[getset(get_copy = "pub")]
+    compression: Compression,
 }
 
 impl SwbusConnInfo {
@@ -45,6 +55,8 @@ impl SwbusConnInfo {
             connection_type: conn_type,
             local_service_path: Some(local_service_path),
             remote_service_path,
+            negotiated_protocol: None,
+            compression: Compression::None,
         }
     }
 
@@ -60,12 +72,24 @@ impl SwbusConnInfo {
             connection_type: conn_type,
             local_service_path: None,
             remote_service_path,
+            negotiated_protocol: None,
+            compression: Compression::None,
         }
     }
 
     pub fn local_service_path(&self) -> Option<&ServicePath> {
         self.local_service_path.as_ref()
     }
+
+    pub fn with_negotiated_protocol(mut self, protocol: SwbusNegotiatedProtocol) -> SwbusConnInfo {
+        self.negotiated_protocol = Some(protocol);
+        self
+    }
+
+    pub fn with_compression(mut self, compression: Compression) -> SwbusConnInfo {
+        self.compression = compression;
+        self
+    }
 }
 
 #[cfg(test)]
@@ -91,6 +115,18 @@ mod tests {
         assert_eq!(conn_info.connection_type(), ConnectionType::Cluster);
         assert_eq!(conn_info.remote_service_path(), &remote_service_path);
         assert_eq!(conn_info.local_service_path(), Some(&local_service_path));
+        assert_eq!(conn_info.negotiated_protocol(), None);
+
+        let protocol = SwbusNegotiatedProtocol {
+            version: 1,
+            features: 0b11,
+        };
+        let conn_info = conn_info.with_negotiated_protocol(protocol);
+        assert_eq!(conn_info.negotiated_protocol(), Some(protocol));
+
+        assert_eq!(conn_info.compression(), Compression::None);
+        let conn_info = conn_info.with_compression(Compression::Gzip);
+        assert_eq!(conn_info.compression(), Compression::Gzip);
     }
 
     #[test]
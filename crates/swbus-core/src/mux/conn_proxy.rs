@@ -5,6 +5,10 @@ use tokio::sync::mpsc;
 use tokio::sync::mpsc::error::TrySendError;
 use tonic::Status;
 
+/// Part of the send queue kept for high priority messages, e.g. 4 for a quarter of it. Normal priority messages are
+/// refused once only that part is left, so high priority messages still get through a congested connection.
+const HIGH_PRIORITY_RESERVED_SHARE: usize = 4;
+
 #[derive(Debug, Clone)]
 pub(crate) struct SwbusConnProxy {
     pub send_queue_tx: mpsc::Sender<Result<SwbusMessage, Status>>,
@@ -15,8 +19,22 @@ impl SwbusConnProxy {
         SwbusConnProxy { send_queue_tx }
     }
 
-    pub async fn try_queue(&self, message: Result<SwbusMessage, Status>) -> Result<()> {
+    /// Number of messages currently waiting in the send queue.
+    pub fn queue_depth(&self) -> usize {
+        self.send_queue_tx.max_capacity() - self.send_queue_tx.capacity()
+    }
+
+    /// Queue a message without waiting for room. High priority messages can use the room reserved for them, see
+    /// [`HIGH_PRIORITY_RESERVED_SHARE`].
+    pub async fn try_queue(&self, message: Result<SwbusMessage, Status>, high_priority: bool) -> Result<()> {
         let tx = self.send_queue_tx.clone();
+        let reserved = tx.max_capacity() / HIGH_PRIORITY_RESERVED_SHARE;
+        if !high_priority && tx.capacity() <= reserved {
+            return Err(SwbusError::route(
+                SwbusErrorCode::QueueFull,
+                "send queue is full for normal priority messages".to_string(),
+            ));
+        }
 
         match tx.try_send(message) {
             Ok(_) => Ok(()),
@@ -39,7 +57,7 @@ mod tests {
         let proxy = SwbusConnProxy::new(tx);
 
         let message = SwbusMessage::default();
-        proxy.try_queue(Ok(message.clone())).await.unwrap();
+        proxy.try_queue(Ok(message.clone()), false).await.unwrap();
 
         let received = rx.recv().await.unwrap().unwrap();
         assert_eq!(received, message);
@@ -51,10 +69,10 @@ mod tests {
         let proxy = SwbusConnProxy::new(tx);
 
         let message = SwbusMessage::default();
-        proxy.try_queue(Ok(message.clone())).await.unwrap();
+        proxy.try_queue(Ok(message.clone()), false).await.unwrap();
 
         // This should fail because the channel is full
-        let result = proxy.try_queue(Ok(message.clone())).await;
+        let result = proxy.try_queue(Ok(message.clone()), false).await;
         assert!(result.is_err());
 
         let error = result.unwrap_err();
@@ -64,4 +82,21 @@ mod tests {
             panic!("Expected RouteError, got {:?}", error);
         }
     }
+
+    #[tokio::test]
+    async fn conn_proxy_keeps_room_for_high_priority_messages() {
+        let (tx, _rx) = mpsc::channel(4);
+        let proxy = SwbusConnProxy::new(tx);
+
+        let message = SwbusMessage::default();
+        for _ in 0..3 {
+            proxy.try_queue(Ok(message.clone()), false).await.unwrap();
+        }
+        let error = proxy.try_queue(Ok(message.clone()), false).await.unwrap_err();
+        assert_eq!(error.code(), SwbusErrorCode::QueueFull);
+
+        proxy.try_queue(Ok(message.clone()), true).await.unwrap();
+        let error = proxy.try_queue(Ok(message.clone()), true).await.unwrap_err();
+        assert_eq!(error.code(), SwbusErrorCode::QueueFull);
+    }
 }
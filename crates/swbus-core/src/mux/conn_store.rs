@@ -1,10 +1,12 @@
 use crate::mux::conn::SwbusConn;
+use crate::mux::metrics::{CONN_STATE_CONNECTING, CONN_STATE_ESTABLISHED};
 use crate::mux::route_config::{PeerConfig, RouteConfig};
 use crate::mux::SwbusConnInfo;
 use crate::mux::SwbusConnMode;
 use crate::mux::SwbusMultiplexer;
 use dashmap::{DashMap, DashSet};
 use std::sync::Arc;
+use tokio::sync::watch;
 use tokio::task::JoinHandle;
 use tokio::time::Duration;
 use tracing::*;
@@ -17,8 +19,12 @@ enum ConnTracker {
 
 pub struct SwbusConnStore {
     mux: Arc<SwbusMultiplexer>,
-    connections: DashMap<Arc<SwbusConnInfo>, ConnTracker>,
+    // Connections and connect tasks by connection id. The connection info of a peer changes once the protocol is
+    // negotiated, but its id stays the same, so the connection replaces its connect task in a single insert.
+    connections: DashMap<String, ConnTracker>,
     my_routes: DashSet<RouteConfig>,
+    // Number of established connections to the configured peers
+    established_peers_tx: watch::Sender<usize>,
 }
 
 impl SwbusConnStore {
@@ -27,9 +33,15 @@ impl SwbusConnStore {
             mux,
             connections: DashMap::new(),
             my_routes: DashSet::new(),
+            established_peers_tx: watch::Sender::new(0),
         }
     }
 
+    /// Subscribe to the number of established connections to the configured peers.
+    pub fn watch_established_peers(&self) -> watch::Receiver<usize> {
+        self.established_peers_tx.subscribe()
+    }
+
     #[instrument(skip(self, conn_info), fields(conn_id=conn_info.id()))]
     fn start_connect_task(self: &Arc<SwbusConnStore>, conn_info: Arc<SwbusConnInfo>, reconnect: bool) {
         let conn_info_clone = conn_info.clone();
@@ -47,7 +59,7 @@ impl SwbusConnStore {
                     match SwbusConn::connect(conn_info.clone(), mux_clone.clone(), conn_store.clone()).await {
                         Ok(conn) => {
                             info!("Successfully connect to the peer");
-                            // register the new connection and update the route table
+                            // register the new connection in place of this task and update the route table
                             conn_store.conn_established(conn);
                             return;
                         }
@@ -59,7 +71,34 @@ impl SwbusConnStore {
             }
             .instrument(current_span.clone()),
         );
-        self.connections.insert(conn_info_clone, ConnTracker::Task(retry_task));
+        self.connections
+            .insert(conn_info_clone.id().clone(), ConnTracker::Task(retry_task));
+        self.update_conn_state();
+    }
+
+    fn update_conn_state(&self) {
+        let (mut connecting, mut established, mut established_peers) = (0, 0, 0);
+        for entry in self.connections.iter() {
+            match entry.value() {
+                ConnTracker::SwbusConn(conn) => {
+                    established += 1;
+                    if conn.info().mode() == SwbusConnMode::Client {
+                        established_peers += 1;
+                    }
+                }
+                ConnTracker::Task(_) => connecting += 1,
+            }
+        }
+        self.established_peers_tx.send_if_modified(|count| {
+            let modified = *count != established_peers;
+            *count = established_peers;
+            modified
+        });
+        let connections = &self.mux.metrics().connections;
+        connections.with_label_values(&[CONN_STATE_CONNECTING]).set(connecting);
+        connections
+            .with_label_values(&[CONN_STATE_ESTABLISHED])
+            .set(established);
     }
 
     pub fn add_my_route(&self, my_route: RouteConfig) {
@@ -69,34 +108,56 @@ impl SwbusConnStore {
     pub fn add_peer(self: &Arc<SwbusConnStore>, peer: PeerConfig) {
         // todo: assuming only one route for now. Will be improved to send routes in route update message and remove this
         let my_route = self.my_routes.iter().next().expect("My service path is not set");
-        let conn_info = Arc::new(SwbusConnInfo::new_client(
-            peer.conn_type,
-            peer.endpoint,
-            peer.id.clone(),
-            my_route.key.clone(),
-        ));
+        let conn_info = Arc::new(
+            SwbusConnInfo::new_client(peer.conn_type, peer.endpoint, peer.id.clone(), my_route.key.clone())
+                .with_compression(peer.compression),
+        );
         self.start_connect_task(conn_info, false);
     }
 
     pub fn conn_lost(self: &Arc<SwbusConnStore>, conn_info: Arc<SwbusConnInfo>) {
-        // First, we remove the connection from the connection table.
-        self.connections.remove(&conn_info);
+        // First, we remove the connection from the connection table. A connection that is already replaced, e.g. by a
+        // reconnect, or removed by a shutdown is left alone. The worker reporting the loss only starts once the
+        // connection is registered, so a connection lost right after connecting is found here too.
+        let removed = self.connections.remove_if(
+            conn_info.id(),
+            |_, tracker| matches!(tracker, ConnTracker::SwbusConn(conn) if Arc::ptr_eq(conn.info(), &conn_info)),
+        );
+        if removed.is_none() {
+            return;
+        }
 
         // If connection is client mode, we start a new connection task.
         if conn_info.mode() == SwbusConnMode::Client {
             self.start_connect_task(conn_info, true /*reconnect from connection loss*/);
+        } else {
+            self.update_conn_state();
         }
     }
 
-    pub fn conn_established(&self, conn: SwbusConn) {
+    pub fn conn_established(&self, mut conn: SwbusConn) {
         self.mux.register(conn.info(), conn.new_proxy());
+        // the worker only starts once the connection is tracked, so the loss it reports finds the connection
+        let worker_start = conn.take_worker_start();
         self.connections
-            .insert(conn.info().clone(), ConnTracker::SwbusConn(conn));
+            .insert(conn.info().id().clone(), ConnTracker::SwbusConn(conn));
+        if let Some(worker_start) = worker_start {
+            let _ = worker_start.send(());
+        }
+        self.update_conn_state();
+
+        // the messages held for the new route can be sent now
+        let mux = self.mux.clone();
+        tokio::spawn(async move { mux.release_held_messages().await });
     }
 
     pub async fn shutdown(&self) {
-        while let Some(entry) = self.connections.iter().next() {
-            match entry.value() {
+        let ids: Vec<String> = self.connections.iter().map(|entry| entry.key().clone()).collect();
+        for id in ids {
+            let Some((_, tracker)) = self.connections.remove(&id) else {
+                continue;
+            };
+            match tracker {
                 ConnTracker::SwbusConn(conn) => {
                     if let Err(swbus_err) = conn.shutdown().await {
                         error!("Failed to shutdown connection: {:?}", swbus_err);
@@ -106,14 +167,15 @@ impl SwbusConnStore {
                     task.abort();
                 }
             }
-            self.connections.remove(entry.key());
         }
+        self.update_conn_state();
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use swbus_proto::swbus::Compression;
     use swbus_proto::swbus::ConnectionType;
     use swbus_proto::swbus::RouteScope;
     use swbus_proto::swbus::ServicePath;
@@ -126,6 +188,7 @@ mod tests {
             conn_type: ConnectionType::Local,
             endpoint: "127.0.0.1:8080".to_string().parse().unwrap(),
             id: ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+            compression: Compression::None,
         };
         let route_config = RouteConfig {
             key: ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0").unwrap(),
@@ -136,7 +199,7 @@ mod tests {
         conn_store.add_peer(peer_config);
 
         assert!(conn_store.connections.iter().any(|entry| {
-            entry.key().id() == "swbs-to://127.0.0.1:8080" && matches!(entry.value(), ConnTracker::Task(_))
+            entry.key() == "swbs-to://127.0.0.1:8080" && matches!(entry.value(), ConnTracker::Task(_))
         }));
     }
 
@@ -170,13 +233,65 @@ mod tests {
             ServicePath::from_string("regiona.clustera.10.0.0.2-dpu0").unwrap(),
             ServicePath::from_string("regiona.clustera.10.0.0.1-dpu0").unwrap(),
         ));
-        conn_store.conn_lost(conn_info.clone());
+        let (send_queue_tx, _) = mpsc::channel(16);
+        conn_store.conn_established(SwbusConn::new(&conn_info, send_queue_tx.clone()));
 
+        // a connection that was already replaced doesn't remove its successor
+        let stale = Arc::new(conn_info.as_ref().clone());
+        conn_store.conn_lost(stale);
+        assert!(matches!(
+            conn_store.connections.get(conn_info.id()).as_deref(),
+            Some(ConnTracker::SwbusConn(_))
+        ));
+
+        conn_store.conn_lost(conn_info.clone());
+        assert_eq!(conn_store.connections.len(), 1);
         assert!(conn_store.connections.iter().any(|entry| {
-            entry.key().id() == "swbs-to://127.0.0.1:8080" && matches!(entry.value(), ConnTracker::Task(_))
+            entry.key() == "swbs-to://127.0.0.1:8080" && matches!(entry.value(), ConnTracker::Task(_))
         }));
     }
 
+    #[tokio::test]
+    async fn test_conn_lost_before_registered() {
+        let mux = Arc::new(SwbusMultiplexer::new());
+        let conn_store = Arc::new(SwbusConnStore::new(mux.clone()));
+        let route_config = RouteConfig {
+            key: ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0").unwrap(),
+            scope: RouteScope::Cluster,
+        };
+        conn_store.add_my_route(route_config);
+
+        let conn_info = Arc::new(SwbusConnInfo::new_client(
+            ConnectionType::Cluster,
+            "127.0.0.1:8080".parse().unwrap(),
+            ServicePath::from_string("regiona.clustera.10.0.0.2-dpu0").unwrap(),
+            ServicePath::from_string("regiona.clustera.10.0.0.1-dpu0").unwrap(),
+        ));
+        let (send_queue_tx, _) = mpsc::channel(16);
+        let mut conn = SwbusConn::new(&conn_info, send_queue_tx);
+
+        // the stream is gone as soon as the worker starts, before the connection is registered
+        let (conn_store_for_worker, conn_info_for_worker) = (conn_store.clone(), conn_info.clone());
+        conn.spawn_worker(async move {
+            conn_store_for_worker.conn_lost(conn_info_for_worker);
+            Ok(())
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        conn_store.conn_established(conn);
+
+        // the lost connection is replaced by a reconnect task
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !matches!(
+                conn_store.connections.get(conn_info.id()).as_deref(),
+                Some(ConnTracker::Task(_))
+            ) {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
     #[tokio::test]
     async fn test_conn_established() {
         let mux = Arc::new(SwbusMultiplexer::new());
@@ -200,6 +315,14 @@ mod tests {
         assert!(conn_store
             .connections
             .iter()
-            .any(|entry| entry.key().id() == conn_info.id() && matches!(entry.value(), ConnTracker::SwbusConn(_))));
+            .any(|entry| entry.key() == conn_info.id() && matches!(entry.value(), ConnTracker::SwbusConn(_))));
+        assert_eq!(
+            mux.metrics()
+                .connections
+                .with_label_values(&[CONN_STATE_ESTABLISHED])
+                .get(),
+            1
+        );
+        assert_eq!(*conn_store.watch_established_peers().borrow(), 1);
     }
 }
@@ -4,6 +4,8 @@ use crate::mux::conn_store::SwbusConnStore;
 use futures_core::stream::Stream;
 use std::io;
 use std::sync::Arc;
+use swbus_proto::message_flags::SwbusMessageFlags;
+use swbus_proto::protocol::SWBUS_MAX_PROTOCOL_VERSION;
 use swbus_proto::result::*;
 use swbus_proto::swbus::SwbusMessage;
 use swbus_proto::swbus::*;
@@ -99,10 +101,7 @@ where
                         }
                         Some(Err(err)) => {
                             error!("Failed to receive message: {}.", err);
-                            return Err(SwbusError::connection(
-                                SwbusErrorCode::ConnectionError,
-                                io::Error::new(io::ErrorKind::ConnectionReset, err.to_string()),
-                            ));
+                            return Err(err.into());
                         }
                         None => {
                             info!("Message stream closed.");
@@ -122,12 +121,18 @@ where
     #[instrument(name="receive_msg", level="debug", skip_all, fields(message.id=message.header.as_ref().unwrap().id))]
     async fn process_data_message(&mut self, message: SwbusMessage) -> Result<()> {
         debug!("{:?}", &message);
+        self.mux
+            .metrics()
+            .record_received(self.info.id(), prost::Message::encoded_len(&message));
         self.validate_message_common(&message)?;
         match message.body {
             Some(swbus_message::Body::TraceRouteRequest(_)) => {
                 info!("Received traceroute request: {:?}", message);
                 // self.process_ping_request(&message);
             }
+            Some(swbus_message::Body::RegistrationRequest(ref request)) => {
+                self.process_registration_request(&message, request).await?;
+            }
             _ => {
                 self.mux.route_message(message).await?;
             }
@@ -135,6 +140,48 @@ where
         Ok(())
     }
 
+    /// Registrations are processed by the swbusd receiving them, because they are about the connection they are
+    /// received from.
+    async fn process_registration_request(
+        &mut self,
+        message: &SwbusMessage,
+        request: &RegistrationRequest,
+    ) -> Result<()> {
+        info!(
+            register = request.register.len(),
+            unregister = request.unregister.len(),
+            replace = request.replace,
+            "Received registration request"
+        );
+        let (error_code, error_message) = match self.mux.process_registration(&self.info, request) {
+            Ok(_) => {
+                // the messages held for the new routes can be sent now
+                let mux = self.mux.clone();
+                tokio::spawn(async move { mux.release_held_messages().await });
+                if !message.has_flags(SwbusMessageFlags::ACK_REQUESTED) {
+                    return Ok(());
+                }
+                (SwbusErrorCode::Ok, String::new())
+            }
+            Err(e) => {
+                error!("Failed to process registration request: {}", e);
+                if message.has_flags(SwbusMessageFlags::NO_RESPONSE_ON_ERROR) {
+                    return Ok(());
+                }
+                (e.code(), e.to_string())
+            }
+        };
+        let response = SwbusMessage::new_response(
+            message,
+            None,
+            error_code,
+            &error_message,
+            self.mux.generate_message_id(),
+            None,
+        );
+        self.mux.route_message(response).await
+    }
+
     fn validate_message_common(&mut self, message: &SwbusMessage) -> Result<()> {
         if message.header.is_none() {
             return Err(SwbusError::input(
@@ -151,6 +198,20 @@ where
             ));
         }
 
+        let max_version = self
+            .info
+            .negotiated_protocol()
+            .map_or(SWBUS_MAX_PROTOCOL_VERSION, |protocol| protocol.version);
+        if message_header.version > max_version {
+            return Err(SwbusError::input(
+                SwbusErrorCode::InvalidHeader,
+                format!(
+                    "Message version {} is higher than the negotiated version {}",
+                    message_header.version, max_version
+                ),
+            ));
+        }
+
         if message_header.source.is_none() {
             return Err(SwbusError::input(
                 SwbusErrorCode::InvalidHeader,
@@ -271,6 +332,25 @@ mod tests {
             id: 1,
             flag: 0,
             ttl: 64,
+            deadline_ms: 0,
+            payload_compression: Compression::None as i32,
+            source: Some(ServicePath::from_string("regiona.clustera.10.0.0.1-dpu0").unwrap()),
+            destination: Some(ServicePath::from_string("regiona.clustera.10.0.0.2-dpu0").unwrap()),
+        };
+        let message = SwbusMessage {
+            header: Some(header),
+            body: Some(swbus_message::Body::TraceRouteRequest(Default::default())),
+        };
+        assert!(worker.validate_message_common(&message).is_err());
+
+        // verify message with version higher than the negotiated one
+        let header = SwbusMessageHeader {
+            version: SWBUS_MAX_PROTOCOL_VERSION + 1,
+            id: 1,
+            flag: 0,
+            ttl: 64,
+            deadline_ms: 0,
+            payload_compression: Compression::None as i32,
             source: Some(ServicePath::from_string("regiona.clustera.10.0.0.1-dpu0").unwrap()),
             destination: Some(ServicePath::from_string("regiona.clustera.10.0.0.2-dpu0").unwrap()),
         };
@@ -286,6 +366,8 @@ mod tests {
             id: 1,
             flag: 0,
             ttl: 64,
+            deadline_ms: 0,
+            payload_compression: Compression::None as i32,
             source: None,
             destination: Some(ServicePath::from_string("regiona.clustera.10.0.0.2-dpu0").unwrap()),
         };
@@ -301,6 +383,8 @@ mod tests {
             id: 1,
             flag: 0,
             ttl: 64,
+            deadline_ms: 0,
+            payload_compression: Compression::None as i32,
             source: Some(ServicePath::from_string("regiona.clustera.10.0.0.2-dpu0").unwrap()),
             destination: None,
         };
@@ -316,6 +400,8 @@ mod tests {
             id: 1,
             flag: 0,
             ttl: 64,
+            deadline_ms: 0,
+            payload_compression: Compression::None as i32,
             source: Some(ServicePath::from_string("regiona.clustera.10.0.0.1-dpu0").unwrap()),
             destination: Some(ServicePath::from_string("regiona.clustera.10.0.0.2-dpu0").unwrap()),
         };
@@ -0,0 +1,257 @@
+use axum::{extract::State, http::header, response::IntoResponse, routing::get, Router};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use swbus_proto::result::*;
+use swbus_proto::swbus::*;
+use tracing::*;
+
+/// Label value used for the per-connection metrics when the message doesn't belong to any connection.
+pub const METRICS_CONN_LOCAL: &str = "local";
+
+/// Connection states exported in `swbus_connections`.
+pub const CONN_STATE_CONNECTING: &str = "connecting";
+pub const CONN_STATE_ESTABLISHED: &str = "established";
+
+/// Prometheus metrics of a swbusd instance.
+///
+/// Each [`SwbusMultiplexer`](super::SwbusMultiplexer) owns its own registry, so multiple instances in the same
+/// process (e.g. in tests) don't collide with each other.
+pub struct SwbusMetrics {
+    registry: Registry,
+
+    /// Number of entries in the route table.
+    pub route_count: IntGauge,
+
+    /// Number of connections by state.
+    pub connections: IntGaugeVec,
+
+    /// Per-connection message and byte counters.
+    pub messages_received: IntCounterVec,
+    pub bytes_received: IntCounterVec,
+    pub messages_sent: IntCounterVec,
+    pub bytes_sent: IntCounterVec,
+    pub messages_dropped: IntCounterVec,
+
+    /// Highest observed depth of the per-connection send queue.
+    pub send_queue_high_watermark: IntGaugeVec,
+
+    /// Time spent in `SwbusMultiplexer::route_message`, by route result.
+    pub route_latency: HistogramVec,
+}
+
+impl SwbusMetrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+
+        let route_count = IntGauge::new("swbus_routes", "Number of entries in the route table").unwrap();
+        let connections = IntGaugeVec::new(
+            Opts::new("swbus_connections", "Number of connections by state"),
+            &["state"],
+        )
+        .unwrap();
+        let messages_received = Self::new_conn_counter("swbus_conn_messages_received_total", "Messages received");
+        let bytes_received = Self::new_conn_counter("swbus_conn_bytes_received_total", "Bytes received");
+        let messages_sent = Self::new_conn_counter("swbus_conn_messages_sent_total", "Messages queued for sending");
+        let bytes_sent = Self::new_conn_counter("swbus_conn_bytes_sent_total", "Bytes queued for sending");
+        let messages_dropped = Self::new_conn_counter("swbus_conn_messages_dropped_total", "Messages dropped");
+        let send_queue_high_watermark = IntGaugeVec::new(
+            Opts::new(
+                "swbus_conn_send_queue_high_watermark",
+                "Highest observed depth of the connection send queue",
+            ),
+            &["conn_id"],
+        )
+        .unwrap();
+        let route_latency = HistogramVec::new(
+            HistogramOpts::new(
+                "swbus_route_message_duration_seconds",
+                "Time spent on routing a message",
+            )
+            .buckets(vec![
+                0.00001, 0.00005, 0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0,
+            ]),
+            &["result"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(route_count.clone())).unwrap();
+        registry.register(Box::new(connections.clone())).unwrap();
+        registry.register(Box::new(messages_received.clone())).unwrap();
+        registry.register(Box::new(bytes_received.clone())).unwrap();
+        registry.register(Box::new(messages_sent.clone())).unwrap();
+        registry.register(Box::new(bytes_sent.clone())).unwrap();
+        registry.register(Box::new(messages_dropped.clone())).unwrap();
+        registry.register(Box::new(send_queue_high_watermark.clone())).unwrap();
+        registry.register(Box::new(route_latency.clone())).unwrap();
+
+        SwbusMetrics {
+            registry,
+            route_count,
+            connections,
+            messages_received,
+            bytes_received,
+            messages_sent,
+            bytes_sent,
+            messages_dropped,
+            send_queue_high_watermark,
+            route_latency,
+        }
+    }
+
+    fn new_conn_counter(name: &str, help: &str) -> IntCounterVec {
+        IntCounterVec::new(Opts::new(name, help), &["conn_id"]).unwrap()
+    }
+
+    pub fn record_received(&self, conn_id: &str, message_len: usize) {
+        self.messages_received.with_label_values(&[conn_id]).inc();
+        self.bytes_received
+            .with_label_values(&[conn_id])
+            .inc_by(message_len as u64);
+    }
+
+    pub fn record_sent(&self, conn_id: &str, message_len: usize) {
+        self.messages_sent.with_label_values(&[conn_id]).inc();
+        self.bytes_sent.with_label_values(&[conn_id]).inc_by(message_len as u64);
+    }
+
+    pub fn record_dropped(&self, conn_id: &str) {
+        self.messages_dropped.with_label_values(&[conn_id]).inc();
+    }
+
+    pub fn record_queue_depth(&self, conn_id: &str, depth: usize) {
+        let gauge = self.send_queue_high_watermark.with_label_values(&[conn_id]);
+        if depth as i64 > gauge.get() {
+            gauge.set(depth as i64);
+        }
+    }
+
+    /// Remove all per-connection series of a connection that is gone.
+    pub fn remove_conn(&self, conn_id: &str) {
+        for counter in [
+            &self.messages_received,
+            &self.bytes_received,
+            &self.messages_sent,
+            &self.bytes_sent,
+            &self.messages_dropped,
+        ] {
+            let _ = counter.remove_label_values(&[conn_id]);
+        }
+        let _ = self.send_queue_high_watermark.remove_label_values(&[conn_id]);
+    }
+
+    /// Encode all metrics in Prometheus text exposition format.
+    pub fn encode(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("Encoding metrics into a buffer should not fail");
+        String::from_utf8(buffer).expect("Prometheus text format is always UTF-8")
+    }
+}
+
+impl Default for SwbusMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Serve the metrics at `http://<addr>/metrics` until the task is dropped.
+pub async fn serve_metrics(addr: SocketAddr, metrics: Arc<SwbusMetrics>) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await.map_err(|e| {
+        SwbusError::connection(
+            SwbusErrorCode::ConnectionError,
+            io::Error::other(format!("Failed to listen at {} for metrics: {}", addr, e)),
+        )
+    })?;
+    info!("Serving metrics at http://{}/metrics", addr);
+
+    let app = Router::new().route("/metrics", get(get_metrics)).with_state(metrics);
+    axum::serve(listener, app).await.map_err(|e| {
+        SwbusError::connection(
+            SwbusErrorCode::ConnectionError,
+            io::Error::other(format!("Metrics server at {} failed: {}", addr, e)),
+        )
+    })
+}
+
+async fn get_metrics(State(metrics): State<Arc<SwbusMetrics>>) -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], metrics.encode())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    fn mock_message() -> SwbusMessage {
+        SwbusMessage::new(
+            SwbusMessageHeader::new(
+                ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/testsvc/0").unwrap(),
+                ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/local-mgmt/0").unwrap(),
+                1,
+            ),
+            swbus_message::Body::PingRequest(PingRequest::new()),
+        )
+    }
+
+    #[test]
+    fn metrics_can_be_recorded_and_encoded() {
+        let metrics = SwbusMetrics::new();
+        let message_len = prost::Message::encoded_len(&mock_message());
+        metrics.route_count.set(3);
+        metrics.connections.with_label_values(&[CONN_STATE_ESTABLISHED]).set(2);
+        metrics.record_received("swbs-from://127.0.0.1:1000", message_len);
+        metrics.record_sent("swbs-from://127.0.0.1:1000", message_len);
+        metrics.record_dropped("swbs-from://127.0.0.1:1000");
+        metrics.record_queue_depth("swbs-from://127.0.0.1:1000", 5);
+        metrics.record_queue_depth("swbs-from://127.0.0.1:1000", 2);
+
+        let text = metrics.encode();
+        assert!(text.contains("swbus_routes 3"));
+        assert!(text.contains("swbus_connections{state=\"established\"} 2"));
+        assert!(text.contains("swbus_conn_messages_received_total{conn_id=\"swbs-from://127.0.0.1:1000\"} 1"));
+        assert!(text.contains("swbus_conn_messages_dropped_total{conn_id=\"swbs-from://127.0.0.1:1000\"} 1"));
+        assert!(text.contains("swbus_conn_send_queue_high_watermark{conn_id=\"swbs-from://127.0.0.1:1000\"} 5"));
+
+        metrics.remove_conn("swbs-from://127.0.0.1:1000");
+        assert!(!metrics.encode().contains("swbs-from://127.0.0.1:1000"));
+    }
+
+    #[tokio::test]
+    async fn metrics_can_be_scraped_over_http() {
+        let metrics = Arc::new(SwbusMetrics::new());
+        metrics.route_count.set(7);
+
+        // Reserve a free port, then release it for the server to bind.
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        tokio::spawn(serve_metrics(addr, metrics.clone()));
+
+        let mut stream = None;
+        for _ in 0..50 {
+            if let Ok(s) = TcpStream::connect(addr).await {
+                stream = Some(s);
+                break;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+        }
+        let mut stream = stream.expect("metrics server is not listening");
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("text/plain; version=0.0.4"));
+        assert!(response.contains("swbus_routes 7"));
+    }
+}
@@ -4,8 +4,10 @@ mod conn_proxy;
 mod conn_store;
 mod conn_worker;
 mod message_handler;
+pub mod metrics;
 mod multiplexer;
 pub mod nexthop;
+mod pubsub;
 pub mod route_config;
 pub mod service;
 
@@ -16,4 +18,5 @@ pub use conn_worker::*;
 pub use message_handler::*;
 pub(crate) use multiplexer::*;
 pub(crate) use nexthop::*;
+pub(crate) use pubsub::*;
 pub(crate) use route_config::*;
@@ -1,20 +1,38 @@
+use super::metrics::SwbusMetrics;
 use super::route_config::RouteConfig;
-use super::{NextHopType, SwbusConnInfo, SwbusConnProxy, SwbusNextHop};
+use super::{NextHopType, SwbusConnInfo, SwbusConnProxy, SwbusNextHop, SwbusSubscriptions};
 use dashmap::mapref::entry::*;
 use dashmap::{DashMap, DashSet};
-use std::sync::Arc;
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use swbus_proto::message_flags::SwbusMessageFlags;
 use swbus_proto::message_id_generator::MessageIdGenerator;
 use swbus_proto::result::*;
+use swbus_proto::service_path_pattern::ServicePathPattern;
 use swbus_proto::swbus::*;
 use tracing::*;
 
 enum RouteStage {
+    Exact,
     Global,
     Region,
     Cluster,
     Local,
 }
-const ROUTE_STAGES: [RouteStage; 4] = [
+
+enum RouteResult {
+    Forwarded,
+    NoRoute,
+    Held,
+    Expired,
+}
+
+/// Maximum number of messages flagged with `HOLD_IF_UNREACHABLE` that are held waiting for a route.
+const MAX_HELD_MESSAGES: usize = 1024;
+
+const ROUTE_STAGES: [RouteStage; 5] = [
+    RouteStage::Exact,
     RouteStage::Local,
     RouteStage::Cluster,
     RouteStage::Region,
@@ -27,6 +45,10 @@ pub struct SwbusMultiplexer {
     routes: DashMap<String, SwbusNextHop>,
     id_generator: MessageIdGenerator,
     my_routes: DashSet<RouteConfig>,
+    metrics: Arc<SwbusMetrics>,
+    subscriptions: SwbusSubscriptions,
+    /// Messages waiting for a route to their destination, see `SwbusMessageFlags::HOLD_IF_UNREACHABLE`.
+    held_messages: Mutex<VecDeque<SwbusMessage>>,
 }
 
 impl SwbusMultiplexer {
@@ -35,38 +57,134 @@ impl SwbusMultiplexer {
             routes: DashMap::new(),
             id_generator: MessageIdGenerator::new(),
             my_routes: DashSet::new(),
+            metrics: Arc::new(SwbusMetrics::new()),
+            subscriptions: SwbusSubscriptions::default(),
+            held_messages: Mutex::new(VecDeque::new()),
         }
     }
 
+    /// Use `id_generator` for the IDs of the messages generated by swbusd, e.g. to keep them unique across restarts.
+    pub fn with_id_generator(mut self, id_generator: MessageIdGenerator) -> Self {
+        self.id_generator = id_generator;
+        self
+    }
+
+    pub fn metrics(&self) -> &Arc<SwbusMetrics> {
+        &self.metrics
+    }
+
+    pub(crate) fn subscriptions(&self) -> &SwbusSubscriptions {
+        &self.subscriptions
+    }
+
     pub fn generate_message_id(&self) -> u64 {
         self.id_generator.generate()
     }
 
     pub(crate) fn register(&self, conn_info: &Arc<SwbusConnInfo>, proxy: SwbusConnProxy) {
         // Update the route table.
-        let path = conn_info.remote_service_path();
-        let route_key = match conn_info.connection_type() {
-            ConnectionType::Global => path.to_regional_prefix(),
-            ConnectionType::Region => path.to_cluster_prefix(),
-            ConnectionType::Cluster => path.to_node_prefix(),
-            ConnectionType::Local => path.to_service_prefix(),
-            ConnectionType::Client => path.to_string(),
-        };
+        let route_key = conn_route_key(conn_info);
         let nexthop = SwbusNextHop::new_remote(conn_info.clone(), proxy, 1);
         self.update_route(route_key, nexthop);
     }
 
     pub(crate) fn unregister(&self, conn_info: Arc<SwbusConnInfo>) {
-        // remove the route entry from the route table.
-        let path = conn_info.remote_service_path();
-        let route_key = match conn_info.connection_type() {
-            ConnectionType::Global => path.to_regional_prefix(),
-            ConnectionType::Region => path.to_cluster_prefix(),
-            ConnectionType::Cluster => path.to_node_prefix(),
-            ConnectionType::Local => path.to_service_prefix(),
-            ConnectionType::Client => path.to_string(),
-        };
+        // remove the route entry from the route table, together with the routes of the services registered over the
+        // connection.
+        let route_key = conn_route_key(&conn_info);
         self.routes.remove(&route_key);
+        let mut removed_keys = vec![route_key];
+        self.routes.retain(|key, nexthop| {
+            let via_conn = is_via_conn(nexthop, &conn_info);
+            if via_conn {
+                removed_keys.push(key.clone());
+            }
+            !via_conn
+        });
+        self.metrics.route_count.set(self.routes.len() as i64);
+        self.metrics.remove_conn(conn_info.id());
+
+        // the subscribers behind the connection are gone as well
+        for route_key in removed_keys {
+            if let Ok(route_path) = ServicePath::from_string(&route_key) {
+                let removed = self.subscriptions.remove_under(&route_path);
+                if removed > 0 {
+                    info!("Removed subscriptions of {} subscribers under {}", removed, route_key);
+                }
+            }
+        }
+    }
+
+    /// Install and remove the routes of the services an edge runtime registers over its connection. The routes share
+    /// the next hop of the connection itself. An edge runtime can only register paths under its own service, so it
+    /// can't take over the messages of another process.
+    pub(crate) fn process_registration(
+        &self,
+        conn_info: &Arc<SwbusConnInfo>,
+        request: &RegistrationRequest,
+    ) -> Result<()> {
+        if !matches!(
+            conn_info.connection_type(),
+            ConnectionType::Local | ConnectionType::Client
+        ) {
+            return Err(SwbusError::input(
+                SwbusErrorCode::InvalidArgs,
+                format!(
+                    "services can't be registered over {} connections",
+                    conn_info.connection_type()
+                ),
+            ));
+        }
+        let service_prefix = conn_info.remote_service_path().to_service_prefix();
+        if let Some(service_path) = request
+            .register
+            .iter()
+            .chain(&request.unregister)
+            .find(|service_path| service_path.to_service_prefix() != service_prefix)
+        {
+            return Err(SwbusError::input(
+                SwbusErrorCode::InvalidArgs,
+                format!(
+                    "{} is not under the service of the edge runtime {}",
+                    service_path, service_prefix
+                ),
+            ));
+        }
+
+        let conn_route_key = conn_route_key(conn_info);
+        let nexthop = self
+            .routes
+            .get(&conn_route_key)
+            .filter(|nexthop| is_via_conn(nexthop, conn_info))
+            .map(|nexthop| nexthop.clone())
+            .ok_or_else(|| {
+                SwbusError::route(
+                    SwbusErrorCode::NoRoute,
+                    format!("connection {} is not registered", conn_info.id()),
+                )
+            })?;
+
+        let unregister: Vec<String> = match request.replace {
+            true => self
+                .routes
+                .iter()
+                .filter(|entry| is_via_conn(entry.value(), conn_info))
+                .map(|entry| entry.key().clone())
+                .collect(),
+            false => request.unregister.iter().map(|sp| sp.to_longest_path()).collect(),
+        };
+        for route_key in unregister {
+            // The route of the connection itself stays as long as the connection.
+            if route_key != conn_route_key {
+                self.routes
+                    .remove_if(&route_key, |_, nexthop| is_via_conn(nexthop, conn_info));
+            }
+        }
+        for service_path in &request.register {
+            self.update_route(service_path.to_longest_path(), nexthop.clone());
+        }
+        self.metrics.route_count.set(self.routes.len() as i64);
+        Ok(())
     }
 
     #[instrument(name = "update_route", level = "info", skip(self, nexthop), fields(nh_type=?nexthop.nh_type(), hop_count=nexthop.hop_count(), conn_info=nexthop.conn_info().as_ref().map(|x| x.id()).unwrap_or(&"None".to_string())))]
@@ -86,6 +204,7 @@ impl SwbusMultiplexer {
                 entry.insert(nexthop);
             }
         }
+        self.metrics.route_count.set(self.routes.len() as i64);
 
         // // If we already have one, then we update the entry only when we have a smaller hop count.
         // // The dashmap RefMut reference will hold a lock to the entry, which makes this function atomic.
@@ -123,6 +242,23 @@ impl SwbusMultiplexer {
     }
     #[instrument(name="route_message", parent=None, level="debug", skip_all, fields(message_id=?message.header.as_ref().unwrap().id))]
     pub async fn route_message(&self, message: SwbusMessage) -> Result<()> {
+        let start = Instant::now();
+        let result = self.route_message_internal(message).await;
+        let result_label = match &result {
+            Ok(RouteResult::Forwarded) => "forwarded",
+            Ok(RouteResult::NoRoute) => "no_route",
+            Ok(RouteResult::Held) => "held",
+            Ok(RouteResult::Expired) => "expired",
+            Err(_) => "error",
+        };
+        self.metrics
+            .route_latency
+            .with_label_values(&[result_label])
+            .observe(start.elapsed().as_secs_f64());
+        result.map(|_| ())
+    }
+
+    async fn route_message_internal(&self, message: SwbusMessage) -> Result<RouteResult> {
         debug!(
             destination = message
                 .header
@@ -153,13 +289,17 @@ impl SwbusMultiplexer {
             }
         };
 
+        if header.is_expired() {
+            return self.respond_expired(message).await;
+        }
+
+        if destination.is_group() {
+            let destination = destination.clone();
+            return self.route_group_message(message, destination).await;
+        }
+
         for stage in &ROUTE_STAGES {
-            let route_key = match stage {
-                RouteStage::Local => destination.to_service_prefix(),
-                RouteStage::Cluster => destination.to_node_prefix(),
-                RouteStage::Region => destination.to_cluster_prefix(),
-                RouteStage::Global => destination.to_regional_prefix(),
-            };
+            let route_key = stage_route_key(stage, destination);
             // If the route entry doesn't exist, we drop the message.
             let nexthop = match self.routes.get(&route_key) {
                 Some(entry) => entry,
@@ -168,17 +308,201 @@ impl SwbusMultiplexer {
                 }
             };
 
+            if header.has_flags(SwbusMessageFlags::TRACE) {
+                info!(
+                    message_id = header.id,
+                    source = header.source.as_ref().map(|source| source.to_longest_path()),
+                    destination = destination.to_longest_path(),
+                    route = route_key,
+                    nh_type = ?nexthop.nh_type(),
+                    conn_id = nexthop.conn_info().as_ref().map(|conn_info| conn_info.id().as_str()),
+                    "Forwarding traced message"
+                );
+            }
+
             // If the route entry is resolved, we forward the message to the next hop.
-            let response = nexthop.queue_message(self, message).await.unwrap();
+            let response = nexthop.queue_message(self, message).await?;
             if let Some(response) = response {
-                Box::pin(self.route_message(response)).await.unwrap();
+                Box::pin(self.route_message(response)).await?;
             } else {
                 // todo: try another nexthop if there is one
             }
-            return Ok(());
+            return Ok(RouteResult::Forwarded);
         }
 
         info!("No route found for destination: {}", destination.to_longest_path());
+        self.respond_no_route(message).await
+    }
+
+    /// Replicate a message sent to a group destination to every next hop that leads to a member of the group.
+    ///
+    /// Each copy gets its destination narrowed down to the route it is sent over, e.g. `region-a.cluster-a.*/hamgrd/*`
+    /// becomes `region-a.cluster-a.10.0.0.2-dpu0/hamgrd/*` when sent to that node, so the next swbusd only delivers it
+    /// to its own members. Routes covered by a less specific matching route are skipped, because the message will
+    /// reach them through that route, so every member gets a single copy.
+    async fn route_group_message(&self, message: SwbusMessage, destination: ServicePath) -> Result<RouteResult> {
+        if destination.resource_type == SERVICE_PATH_WILDCARD || destination.resource_id == SERVICE_PATH_WILDCARD {
+            return Err(SwbusError::input(
+                SwbusErrorCode::InvalidDestination,
+                format!(
+                    "wildcards are only supported in location and service of a group destination: {}",
+                    destination
+                ),
+            ));
+        }
+
+        let group = ServicePathPattern::from(destination.clone());
+        let matched: Vec<(ServicePath, SwbusNextHop)> = self
+            .routes
+            .iter()
+            // Only remote endpoints are group members, swbusd's own management endpoint is not.
+            .filter(|entry| entry.value().nh_type() == NextHopType::Remote)
+            .filter_map(|entry| {
+                let route_path = ServicePath::from_string(entry.key()).ok()?;
+                group
+                    .matches_prefix(&route_path)
+                    .then(|| (route_path, entry.value().clone()))
+            })
+            .collect();
+        let targets: Vec<&(ServicePath, SwbusNextHop)> = matched
+            .iter()
+            .filter(|(route_path, _)| {
+                !matched
+                    .iter()
+                    .any(|(other, _)| other != route_path && is_route_prefix_of(other, route_path))
+            })
+            .collect();
+
+        if targets.is_empty() {
+            info!("No route found for group destination: {}", destination);
+            return self.respond_no_route(message).await;
+        }
+
+        let mut errors = Vec::new();
+        for (route_path, nexthop) in &targets {
+            let mut copy = message.clone();
+            if let Some(header) = copy.header.as_mut() {
+                header.destination = Some(narrow_group_destination(&destination, route_path));
+            }
+            let result = match nexthop.queue_message(self, copy).await {
+                Ok(Some(response)) => Box::pin(self.route_message(response)).await,
+                Ok(None) => Ok(()),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                errors.push((route_path, e));
+            }
+        }
+        if errors.is_empty() {
+            return Ok(RouteResult::Forwarded);
+        }
+
+        let error = aggregate_delivery_errors(&format!("next hops of group {}", destination), targets.len(), errors);
+        // the source learns about the members that didn't get the message, unless it asked not to
+        if !message.has_flags(SwbusMessageFlags::NO_RESPONSE_ON_ERROR)
+            && !matches!(message.body, Some(swbus_message::Body::Response(_)))
+        {
+            let response = SwbusMessage::new_response(
+                &message,
+                Some(&self.get_my_service_path()),
+                error.code(),
+                &error.detail(),
+                self.id_generator.generate(),
+                None,
+            );
+            if let Err(e) = Box::pin(self.route_message(response)).await {
+                warn!("Failed to send group delivery error to the source: {}", e);
+            }
+        }
+        Err(error)
+    }
+
+    /// Deliver a published message to the matching subscribers on this swbusd. If the publisher is a client of this
+    /// swbusd, the message is also sent to the local-mgmt service of every directly connected peer, which delivers it
+    /// to its own subscribers.
+    pub(crate) async fn publish_message(&self, message: &SwbusMessage) -> Result<()> {
+        let (publisher, topic) = match (message.header.as_ref(), message.body.as_ref()) {
+            (Some(header), Some(swbus_message::Body::PublishRequest(request))) => {
+                (header.source.as_ref(), request.topic.as_str())
+            }
+            _ => {
+                return Err(SwbusError::input(
+                    SwbusErrorCode::InvalidPayload,
+                    "not a publish request".to_string(),
+                ))
+            }
+        };
+        let publisher = publisher
+            .ok_or_else(|| SwbusError::input(SwbusErrorCode::InvalidSource, "missing publisher".to_string()))?;
+
+        let mut destinations = self.subscriptions.subscribers(topic, publisher);
+        let is_local_publisher = self
+            .my_routes
+            .iter()
+            .any(|route| route.key.to_node_prefix() == publisher.to_node_prefix());
+        if is_local_publisher {
+            destinations.extend(self.peer_mgmt_service_paths());
+        }
+
+        let mut errors = Vec::new();
+        for destination in &destinations {
+            let mut copy = message.clone();
+            if let Some(header) = copy.header.as_mut() {
+                header.destination = Some(destination.clone());
+            }
+            if let Err(e) = Box::pin(self.route_message(copy)).await {
+                errors.push((destination, e));
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(aggregate_delivery_errors(
+                &format!("subscribers of topic \"{}\"", topic),
+                destinations.len(),
+                errors,
+            )),
+        }
+    }
+
+    /// Local-mgmt service paths of the swbusd peers that are directly connected to this swbusd.
+    fn peer_mgmt_service_paths(&self) -> Vec<ServicePath> {
+        let mut conn_ids = HashSet::new();
+        self.routes
+            .iter()
+            .filter_map(|entry| {
+                let conn_info = entry.value().conn_info().clone()?;
+                let is_peer = matches!(
+                    conn_info.connection_type(),
+                    ConnectionType::Cluster | ConnectionType::Region | ConnectionType::Global
+                );
+                (is_peer && conn_ids.insert(conn_info.id().clone()))
+                    .then(|| conn_info.remote_service_path().clone_for_local_mgmt())
+            })
+            .collect()
+    }
+
+    async fn respond_no_route(&self, message: SwbusMessage) -> Result<RouteResult> {
+        let flags = message.flags();
+        if flags.contains(SwbusMessageFlags::TRACE) {
+            info!(
+                message_id = message.header.as_ref().map(|header| header.id),
+                "No route found for traced message"
+            );
+        }
+        if flags.contains(SwbusMessageFlags::HOLD_IF_UNREACHABLE) {
+            match self.hold_message(message) {
+                Ok(()) => return Ok(RouteResult::Held),
+                Err(rejected) => return self.respond_no_route_now(*rejected, flags).await,
+            }
+        }
+        self.respond_no_route_now(message, flags).await
+    }
+
+    async fn respond_no_route_now(&self, message: SwbusMessage, flags: SwbusMessageFlags) -> Result<RouteResult> {
+        if flags.contains(SwbusMessageFlags::NO_RESPONSE_ON_ERROR) {
+            debug!("Dropping message without route");
+            return Ok(RouteResult::NoRoute);
+        }
         let response = SwbusMessage::new_response(
             &message,
             Some(&self.get_my_service_path()),
@@ -193,11 +517,88 @@ impl SwbusMultiplexer {
         // Here it will send 'no-route' response[2] for response[1]. response[1] has source SP of A
         // because the response is originated from A. So response[2]'s dest is to A (itself).
         // Response[2] will be sent to a drop nexhop, which should drop the unexpected response packet.
-        Box::pin(self.route_message(response)).await.unwrap();
+        Box::pin(self.route_message(response)).await?;
+
+        Ok(RouteResult::NoRoute)
+    }
+
+    /// Drop a message whose deadline has passed and answer TIMEOUT to its source, unless it is a response itself.
+    async fn respond_expired(&self, message: SwbusMessage) -> Result<RouteResult> {
+        let flags = message.flags();
+        if flags.contains(SwbusMessageFlags::TRACE) {
+            info!(
+                message_id = message.header.as_ref().map(|header| header.id),
+                "Dropping expired traced message"
+            );
+        }
+        if flags.contains(SwbusMessageFlags::NO_RESPONSE_ON_ERROR)
+            || matches!(message.body, Some(swbus_message::Body::Response(_)))
+        {
+            debug!("Dropping expired message");
+            return Ok(RouteResult::Expired);
+        }
+        let response = SwbusMessage::new_response(
+            &message,
+            Some(&self.get_my_service_path()),
+            SwbusErrorCode::Timeout,
+            "Message deadline exceeded",
+            self.id_generator.generate(),
+            None,
+        );
+        Box::pin(self.route_message(response)).await?;
+        Ok(RouteResult::Expired)
+    }
 
+    /// Hold a message until a route to its destination shows up. Group messages and messages beyond the hold capacity
+    /// are given back.
+    fn hold_message(&self, message: SwbusMessage) -> std::result::Result<(), Box<SwbusMessage>> {
+        let is_group = message
+            .header
+            .as_ref()
+            .and_then(|header| header.destination.as_ref())
+            .is_none_or(|destination| destination.is_group());
+        let mut held_messages = self.held_messages.lock().unwrap();
+        if is_group || held_messages.len() >= MAX_HELD_MESSAGES {
+            return Err(Box::new(message));
+        }
+        debug!("Holding message until a route is available");
+        held_messages.push_back(message);
         Ok(())
     }
 
+    /// Route the held messages whose destination is reachable now, and answer the expired ones. It is called when a
+    /// connection is established.
+    pub(crate) async fn release_held_messages(&self) {
+        let released: Vec<SwbusMessage> = {
+            let mut held_messages = self.held_messages.lock().unwrap();
+            let (released, still_held): (Vec<SwbusMessage>, Vec<SwbusMessage>) =
+                held_messages.drain(..).partition(|message| {
+                    message.is_expired()
+                        || message
+                            .header
+                            .as_ref()
+                            .and_then(|header| header.destination.as_ref())
+                            .is_some_and(|destination| self.has_route(destination))
+                });
+            *held_messages = still_held.into();
+            released
+        };
+        if !released.is_empty() {
+            info!("Releasing {} held messages", released.len());
+        }
+        for message in released {
+            if let Err(e) = self.route_message(message).await {
+                error!("Failed to route held message: {}", e);
+            }
+        }
+    }
+
+    fn has_route(&self, destination: &ServicePath) -> bool {
+        ROUTE_STAGES
+            .iter()
+            .any(|stage| self.routes.contains_key(&stage_route_key(stage, destination)))
+    }
+
     pub fn export_routes(&self, scope: Option<RouteScope>) -> RouteQueryResult {
         let entries: Vec<RouteQueryResultEntry> = self
             .routes
@@ -234,6 +635,133 @@ impl SwbusMultiplexer {
 
         RouteQueryResult { entries }
     }
+
+    /// List the services of this swbusd and the ones registered behind the edge runtimes connected to it.
+    pub fn export_registrations(&self) -> Vec<RegistrationEntry> {
+        let mut entries: Vec<RegistrationEntry> = self
+            .routes
+            .iter()
+            .filter_map(|entry| {
+                let service_path = ServicePath::from_string(entry.key()).ok()?;
+                match entry.value().nh_type() {
+                    NextHopType::Local => Some(RegistrationEntry {
+                        service_path: Some(service_path),
+                        edge_service_path: None,
+                        conn_id: String::new(),
+                    }),
+                    NextHopType::Remote => {
+                        let conn_info = entry.value().conn_info().clone()?;
+                        matches!(
+                            conn_info.connection_type(),
+                            ConnectionType::Client | ConnectionType::Local
+                        )
+                        .then(|| RegistrationEntry {
+                            service_path: Some(service_path),
+                            edge_service_path: Some(conn_info.remote_service_path().clone()),
+                            conn_id: conn_info.id().to_string(),
+                        })
+                    }
+                    NextHopType::Drop => None,
+                }
+            })
+            .collect();
+        entries.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        entries
+    }
+}
+
+/// Key of the route to the remote endpoint of a connection.
+fn conn_route_key(conn_info: &SwbusConnInfo) -> String {
+    let path = conn_info.remote_service_path();
+    match conn_info.connection_type() {
+        ConnectionType::Global => path.to_regional_prefix(),
+        ConnectionType::Region => path.to_cluster_prefix(),
+        ConnectionType::Cluster => path.to_node_prefix(),
+        ConnectionType::Local => path.to_service_prefix(),
+        ConnectionType::Client => path.to_string(),
+    }
+}
+
+fn is_via_conn(nexthop: &SwbusNextHop, conn_info: &SwbusConnInfo) -> bool {
+    nexthop
+        .conn_info()
+        .as_ref()
+        .is_some_and(|nh_conn_info| nh_conn_info.id() == conn_info.id())
+}
+
+fn stage_route_key(stage: &RouteStage, destination: &ServicePath) -> String {
+    match stage {
+        RouteStage::Exact => destination.to_longest_path(),
+        RouteStage::Local => destination.to_service_prefix(),
+        RouteStage::Cluster => destination.to_node_prefix(),
+        RouteStage::Region => destination.to_cluster_prefix(),
+        RouteStage::Global => destination.to_regional_prefix(),
+    }
+}
+
+/// Merge the errors of delivering a message to multiple destinations. The error code is kept if all deliveries
+/// failed for the same routing reason.
+fn aggregate_delivery_errors<T: std::fmt::Display>(
+    destinations: &str,
+    total: usize,
+    errors: Vec<(T, SwbusError)>,
+) -> SwbusError {
+    let detail = format!(
+        "failed to deliver to {} of {} {}: {}",
+        errors.len(),
+        total,
+        destinations,
+        errors
+            .iter()
+            .map(|(destination, e)| format!("{}: {}", destination, e))
+            .collect::<Vec<String>>()
+            .join("; ")
+    );
+    let code = errors.first().map_or(SwbusErrorCode::Fail, |(_, e)| e.code());
+    let same_code = errors.iter().all(|(_, e)| e.code() == code);
+    match same_code && code > SwbusErrorCode::RouteErrorMin && code < SwbusErrorCode::RouteErrorMax {
+        true => SwbusError::route(code, detail),
+        false => SwbusError::internal(SwbusErrorCode::Fail, detail),
+    }
+}
+
+/// Check if every service under the route `path` is also under the route `prefix`.
+pub(crate) fn is_route_prefix_of(prefix: &ServicePath, path: &ServicePath) -> bool {
+    let prefix_components = route_components(prefix);
+    let path_components = route_components(path);
+    prefix_components
+        .iter()
+        .zip(path_components)
+        .all(|(prefix_component, path_component)| prefix_component.is_empty() || *prefix_component == path_component)
+}
+
+/// Replace the components of a group destination with the ones of the route it is sent over.
+fn narrow_group_destination(destination: &ServicePath, route_path: &ServicePath) -> ServicePath {
+    let pick = |group: &String, route: &String| match route.is_empty() {
+        true => group.clone(),
+        false => route.clone(),
+    };
+    ServicePath {
+        region_id: pick(&destination.region_id, &route_path.region_id),
+        cluster_id: pick(&destination.cluster_id, &route_path.cluster_id),
+        node_id: pick(&destination.node_id, &route_path.node_id),
+        service_type: pick(&destination.service_type, &route_path.service_type),
+        service_id: pick(&destination.service_id, &route_path.service_id),
+        resource_type: pick(&destination.resource_type, &route_path.resource_type),
+        resource_id: pick(&destination.resource_id, &route_path.resource_id),
+    }
+}
+
+fn route_components(path: &ServicePath) -> [&String; 7] {
+    [
+        &path.region_id,
+        &path.cluster_id,
+        &path.node_id,
+        &path.service_type,
+        &path.service_id,
+        &path.resource_type,
+        &path.resource_id,
+    ]
 }
 
 #[cfg(test)]
@@ -244,6 +772,7 @@ mod tests {
 
     use super::*;
     use crate::mux::SwbusConn;
+    use std::time::SystemTime;
     use tokio::time::Duration;
 
     #[test]
@@ -524,6 +1053,497 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    fn flagged_ping(destination: &str, flags: SwbusMessageFlags) -> SwbusMessage {
+        let header = SwbusMessageHeader::new(
+            ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/testsvc/0").unwrap(),
+            ServicePath::from_string(destination).unwrap(),
+            1,
+        );
+        SwbusMessage::new(
+            header.with_flags(flags),
+            swbus_message::Body::PingRequest(PingRequest::new()),
+        )
+    }
+
+    fn mux_with_route_to_source() -> (SwbusMultiplexer, mpsc::Receiver<Result<SwbusMessage, Status>>) {
+        let mux = SwbusMultiplexer::new();
+        mux.set_my_routes(vec![RouteConfig {
+            key: ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+            scope: RouteScope::Cluster,
+        }]);
+        let send_queue_rx = add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.1-dpu0",
+            1,
+            "region-a.cluster-a.10.0.0.1-dpu0",
+            ConnectionType::Cluster,
+        );
+        (mux, send_queue_rx)
+    }
+
+    #[tokio::test]
+    async fn test_route_message_noroute_without_response() {
+        let (mux, mut send_queue_rx1) = mux_with_route_to_source();
+
+        let message = flagged_ping(
+            "region-a.cluster-a.10.0.0.3-dpu0/testsvc/0",
+            SwbusMessageFlags::NO_RESPONSE_ON_ERROR,
+        );
+        mux.route_message(message).await.unwrap();
+        assert!(send_queue_rx1.try_recv().is_err());
+
+        // without the flag, the source gets a NO_ROUTE response
+        let message = flagged_ping("region-a.cluster-a.10.0.0.3-dpu0/testsvc/0", SwbusMessageFlags::empty());
+        mux.route_message(message).await.unwrap();
+        assert!(send_queue_rx1.try_recv().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_route_message_expired() {
+        let (mux, mut send_queue_rx1) = mux_with_route_to_source();
+        let mut send_queue_rx3 = add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.3-dpu0",
+            1,
+            "region-a.cluster-a.10.0.0.3-dpu0",
+            ConnectionType::Cluster,
+        );
+        let with_deadline = |message: SwbusMessage, deadline: SystemTime| {
+            let mut message = message;
+            let header = message.header.take().unwrap().with_deadline(deadline);
+            message.header = Some(header);
+            message
+        };
+
+        let message = flagged_ping("region-a.cluster-a.10.0.0.3-dpu0/testsvc/0", SwbusMessageFlags::empty());
+        let message = with_deadline(message, SystemTime::now() + Duration::from_secs(60));
+        mux.route_message(message).await.unwrap();
+        assert!(send_queue_rx3.try_recv().is_ok());
+
+        let message = flagged_ping("region-a.cluster-a.10.0.0.3-dpu0/testsvc/0", SwbusMessageFlags::empty());
+        let message = with_deadline(message, SystemTime::now() - Duration::from_millis(1));
+        mux.route_message(message).await.unwrap();
+        assert!(send_queue_rx3.try_recv().is_err());
+        let response = send_queue_rx1.try_recv().unwrap().unwrap();
+        assert_eq!(
+            response.header.as_ref().unwrap().source,
+            Some(ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap())
+        );
+        match response.body.unwrap() {
+            swbus_message::Body::Response(response) => {
+                assert_eq!(response.error_code, SwbusErrorCode::Timeout as i32);
+                assert_eq!(response.request_id, 1);
+            }
+            body => panic!("Expected response, got {:?}", body),
+        }
+
+        let message = flagged_ping(
+            "region-a.cluster-a.10.0.0.3-dpu0/testsvc/0",
+            SwbusMessageFlags::NO_RESPONSE_ON_ERROR,
+        );
+        let message = with_deadline(message, SystemTime::now() - Duration::from_millis(1));
+        mux.route_message(message).await.unwrap();
+        assert!(send_queue_rx3.try_recv().is_err());
+        assert!(send_queue_rx1.try_recv().is_err());
+
+        // responses are never answered
+        let mut message = flagged_ping("region-a.cluster-a.10.0.0.3-dpu0/testsvc/0", SwbusMessageFlags::empty());
+        message.body = Some(swbus_message::Body::Response(RequestResponse::ok(7)));
+        let message = with_deadline(message, SystemTime::now() - Duration::from_millis(1));
+        mux.route_message(message).await.unwrap();
+        assert!(send_queue_rx3.try_recv().is_err());
+        assert!(send_queue_rx1.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_route_message_hold_if_unreachable() {
+        let (mux, mut send_queue_rx1) = mux_with_route_to_source();
+
+        let message = flagged_ping(
+            "region-a.cluster-a.10.0.0.3-dpu0/testsvc/0",
+            SwbusMessageFlags::HOLD_IF_UNREACHABLE,
+        );
+        mux.route_message(message.clone()).await.unwrap();
+        assert!(send_queue_rx1.try_recv().is_err());
+        assert_eq!(mux.held_messages.lock().unwrap().len(), 1);
+
+        // still unreachable, the message stays held
+        mux.release_held_messages().await;
+        assert_eq!(mux.held_messages.lock().unwrap().len(), 1);
+
+        let mut send_queue_rx3 = add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.3-dpu0",
+            1,
+            "region-a.cluster-a.10.0.0.3-dpu0",
+            ConnectionType::Cluster,
+        );
+        mux.release_held_messages().await;
+        assert!(mux.held_messages.lock().unwrap().is_empty());
+        let received = send_queue_rx3.try_recv().unwrap().unwrap();
+        assert_eq!(
+            received.header.as_ref().unwrap().id,
+            message.header.as_ref().unwrap().id
+        );
+        assert!(send_queue_rx1.try_recv().is_err());
+
+        // group destinations are not held
+        let message = flagged_ping("region-a.cluster-b.*/testsvc/0", SwbusMessageFlags::HOLD_IF_UNREACHABLE);
+        mux.route_message(message).await.unwrap();
+        assert!(mux.held_messages.lock().unwrap().is_empty());
+        assert!(send_queue_rx1.try_recv().is_ok());
+    }
+
+    #[derive(Clone, Default)]
+    struct LogCapture(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogCapture {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_route_message_trace() {
+        let (mux, mut send_queue_rx1) = mux_with_route_to_source();
+        let logs = LogCapture::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(Level::INFO)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let message = flagged_ping("region-a.cluster-a.10.0.0.1-dpu0/testsvc/1", SwbusMessageFlags::empty());
+        mux.route_message(message).await.unwrap();
+        assert!(send_queue_rx1.try_recv().is_ok());
+        assert!(!String::from_utf8(logs.0.lock().unwrap().clone())
+            .unwrap()
+            .contains("traced message"));
+
+        let message = flagged_ping("region-a.cluster-a.10.0.0.1-dpu0/testsvc/1", SwbusMessageFlags::TRACE);
+        mux.route_message(message).await.unwrap();
+        assert!(send_queue_rx1.try_recv().is_ok());
+        let output = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("Forwarding traced message"));
+        assert!(output.contains("route=\"region-a.cluster-a.10.0.0.1-dpu0\""));
+
+        let message = flagged_ping("region-a.cluster-a.10.0.0.3-dpu0/testsvc/1", SwbusMessageFlags::TRACE);
+        mux.route_message(message).await.unwrap();
+        let output = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("No route found for traced message"));
+    }
+
+    fn group_message(destination: &str) -> SwbusMessage {
+        SwbusMessage::new(
+            SwbusMessageHeader::new(
+                ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0").unwrap(),
+                ServicePath::from_string(destination).unwrap(),
+                1,
+            ),
+            swbus_message::Body::DataRequest(DataRequest::new(b"ha set updated".to_vec())),
+        )
+    }
+
+    fn received_destination(send_queue_rx: &mut mpsc::Receiver<Result<SwbusMessage, Status>>) -> Option<String> {
+        let message = send_queue_rx.try_recv().ok()?.ok()?;
+        Some(message.header?.destination?.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_route_group_message() {
+        let mux = Arc::new(SwbusMultiplexer::new());
+        mux.set_my_routes(vec![RouteConfig {
+            key: ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+            scope: RouteScope::Cluster,
+        }]);
+
+        let mut local_hamgrd_rx = add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0",
+            1,
+            "region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0",
+            ConnectionType::Local,
+        );
+        let mut local_swss_rx = add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.2-dpu0/swss/0",
+            1,
+            "region-a.cluster-a.10.0.0.2-dpu0/swss/0",
+            ConnectionType::Local,
+        );
+        let mut peer1_rx = add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.1-dpu0",
+            1,
+            "region-a.cluster-a.10.0.0.1-dpu0",
+            ConnectionType::Cluster,
+        );
+        let mut cluster_b_rx = add_route(
+            &mux,
+            "region-a.cluster-b",
+            1,
+            "region-a.cluster-b.10.0.0.1-dpu0",
+            ConnectionType::Region,
+        );
+        // covered by the cluster-b route, so it must not get a second copy
+        let mut cluster_b_node_rx = add_route(
+            &mux,
+            "region-a.cluster-b.10.0.0.9-dpu0",
+            1,
+            "region-a.cluster-b.10.0.0.9-dpu0",
+            ConnectionType::Cluster,
+        );
+        let mut region_b_rx = add_route(
+            &mux,
+            "region-b",
+            1,
+            "region-b.cluster-a.10.0.0.1-dpu0",
+            ConnectionType::Global,
+        );
+
+        mux.route_message(group_message("region-a.*.*/hamgrd/*")).await.unwrap();
+        assert_eq!(
+            received_destination(&mut local_hamgrd_rx).as_deref(),
+            Some("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0")
+        );
+        assert_eq!(
+            received_destination(&mut peer1_rx).as_deref(),
+            Some("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/*")
+        );
+        assert_eq!(
+            received_destination(&mut cluster_b_rx).as_deref(),
+            Some("region-a.cluster-b.*/hamgrd/*")
+        );
+        for rx in [
+            &mut local_hamgrd_rx,
+            &mut local_swss_rx,
+            &mut peer1_rx,
+            &mut cluster_b_rx,
+            &mut cluster_b_node_rx,
+            &mut region_b_rx,
+        ] {
+            assert_eq!(received_destination(rx), None);
+        }
+
+        // node-wide broadcast only reaches the local services
+        mux.route_message(group_message("region-a.cluster-a.10.0.0.2-dpu0/*/*"))
+            .await
+            .unwrap();
+        assert!(received_destination(&mut local_hamgrd_rx).is_some());
+        assert!(received_destination(&mut local_swss_rx).is_some());
+        assert_eq!(received_destination(&mut peer1_rx), None);
+    }
+
+    #[tokio::test]
+    async fn test_route_group_message_aggregates_errors() {
+        let mux = Arc::new(SwbusMultiplexer::new());
+        mux.set_my_routes(vec![RouteConfig {
+            key: ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+            scope: RouteScope::Cluster,
+        }]);
+
+        let mut peer1_rx = add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.1-dpu0",
+            1,
+            "region-a.cluster-a.10.0.0.1-dpu0",
+            ConnectionType::Cluster,
+        );
+        drop(add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.3-dpu0",
+            1,
+            "region-a.cluster-a.10.0.0.3-dpu0",
+            ConnectionType::Cluster,
+        ));
+        drop(add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.4-dpu0",
+            1,
+            "region-a.cluster-a.10.0.0.4-dpu0",
+            ConnectionType::Cluster,
+        ));
+
+        let err = mux
+            .route_message(group_message("region-a.cluster-a.*/hamgrd/*"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), SwbusErrorCode::NoRoute);
+        assert!(err.to_string().contains("failed to deliver to 2 of 3 next hops"));
+        assert!(err.to_string().contains("region-a.cluster-a.10.0.0.3-dpu0"));
+        assert!(err.to_string().contains("region-a.cluster-a.10.0.0.4-dpu0"));
+        // the reachable member still gets the message
+        assert!(received_destination(&mut peer1_rx).is_some());
+
+        let err = mux
+            .route_message(group_message("region-a.cluster-a.*/hamgrd/0/hascope/*"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), SwbusErrorCode::InvalidDestination);
+    }
+
+    #[tokio::test]
+    async fn test_route_group_message_responds_delivery_errors() {
+        let mux = Arc::new(SwbusMultiplexer::new());
+        mux.set_my_routes(vec![RouteConfig {
+            key: ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+            scope: RouteScope::Cluster,
+        }]);
+        let mut source_rx = add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.2-dpu0/swss/0",
+            0,
+            "region-a.cluster-a.10.0.0.2-dpu0/swss/0",
+            ConnectionType::Local,
+        );
+        let _peer1_rx = add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.1-dpu0",
+            1,
+            "region-a.cluster-a.10.0.0.1-dpu0",
+            ConnectionType::Cluster,
+        );
+        drop(add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.3-dpu0",
+            1,
+            "region-a.cluster-a.10.0.0.3-dpu0",
+            ConnectionType::Cluster,
+        ));
+        let from_swss = |flags| {
+            let mut message = group_message("region-a.cluster-a.*/hamgrd/*");
+            let header = message.header.take().unwrap();
+            let source = ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/swss/0").unwrap();
+            message.header = Some(
+                SwbusMessageHeader {
+                    source: Some(source),
+                    ..header
+                }
+                .with_flags(flags),
+            );
+            message
+        };
+
+        assert!(mux.route_message(from_swss(SwbusMessageFlags::empty())).await.is_err());
+        let response = source_rx.try_recv().unwrap().unwrap();
+        match response.body {
+            Some(swbus_message::Body::Response(response)) => {
+                assert_eq!(response.request_id, 1);
+                assert_eq!(response.error_code, SwbusErrorCode::NoRoute as i32);
+                assert!(response.error_message.contains("failed to deliver to 1 of 2 next hops"));
+            }
+            body => panic!("Expected response, got {:?}", body),
+        }
+
+        assert!(mux
+            .route_message(from_swss(SwbusMessageFlags::NO_RESPONSE_ON_ERROR))
+            .await
+            .is_err());
+        assert!(source_rx.try_recv().is_err());
+    }
+
+    fn publish_message(publisher: &str, swbusd: &str) -> SwbusMessage {
+        SwbusMessage::new(
+            SwbusMessageHeader::new(
+                ServicePath::from_string(publisher).unwrap(),
+                ServicePath::from_string(swbusd).unwrap(),
+                1,
+            ),
+            swbus_message::Body::PublishRequest(PublishRequest::new("ha-scope-state", b"active".to_vec())),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_publish_message() {
+        let mux = Arc::new(SwbusMultiplexer::new());
+        mux.set_my_routes(vec![RouteConfig {
+            key: ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+            scope: RouteScope::Cluster,
+        }]);
+        let mut publisher_rx = add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0",
+            1,
+            "region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0",
+            ConnectionType::Local,
+        );
+        let mut subscriber_rx = add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.2-dpu0/hamgrd/1",
+            1,
+            "region-a.cluster-a.10.0.0.2-dpu0/hamgrd/1",
+            ConnectionType::Local,
+        );
+        let mut peer1_rx = add_route(
+            &mux,
+            "region-a.cluster-a.10.0.0.1-dpu0",
+            1,
+            "region-a.cluster-a.10.0.0.1-dpu0",
+            ConnectionType::Cluster,
+        );
+
+        // subscribe through the local-mgmt service, like an edge client does
+        let subscriber = ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/1/hascope/eni0").unwrap();
+        let subscribe = SwbusMessage::new(
+            SwbusMessageHeader::new(subscriber.clone(), subscriber.clone_for_local_mgmt(), 1),
+            swbus_message::Body::SubscribeRequest(SubscribeRequest::new("ha-scope-state", "")),
+        );
+        mux.route_message(subscribe).await.unwrap();
+        let response = subscriber_rx.try_recv().unwrap().unwrap();
+        match response.body {
+            Some(swbus_message::Body::Response(response)) => assert_eq!(response.error_code(), SwbusErrorCode::Ok),
+            body => panic!("Unexpected response: {:?}", body),
+        }
+
+        // a local publisher reaches the local subscribers and the peers
+        mux.route_message(publish_message(
+            "region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0",
+            "region-a.cluster-a.10.0.0.2-dpu0/local-mgmt/0",
+        ))
+        .await
+        .unwrap();
+        assert_eq!(
+            received_destination(&mut subscriber_rx).as_deref(),
+            Some("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/1/hascope/eni0")
+        );
+        assert_eq!(
+            received_destination(&mut peer1_rx).as_deref(),
+            Some("region-a.cluster-a.10.0.0.1-dpu0/local-mgmt/0")
+        );
+        assert_eq!(received_destination(&mut publisher_rx), None);
+
+        // a message published on a peer is only delivered to the local subscribers
+        mux.route_message(publish_message(
+            "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0",
+            "region-a.cluster-a.10.0.0.2-dpu0/local-mgmt/0",
+        ))
+        .await
+        .unwrap();
+        assert!(received_destination(&mut subscriber_rx).is_some());
+        assert_eq!(received_destination(&mut peer1_rx), None);
+
+        // subscriptions are removed with the connection
+        mux.unregister(Arc::new(SwbusConnInfo::new_server(
+            ConnectionType::Local,
+            "127.0.0.1:8081".parse().unwrap(),
+            ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/1").unwrap(),
+        )));
+        assert!(mux
+            .subscriptions()
+            .subscribers(
+                "ha-scope-state",
+                &ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0").unwrap()
+            )
+            .is_empty());
+    }
+
     #[test]
     fn test_export_routes() {
         let mux = Arc::new(SwbusMultiplexer::new());
@@ -580,4 +1600,81 @@ mod tests {
         let expected = RouteQueryResult { entries: vec![entry2] };
         assert_eq!(normalized_routes, expected);
     }
+
+    #[tokio::test]
+    async fn test_process_registration() {
+        let mux = Arc::new(SwbusMultiplexer::new());
+        mux.set_my_routes(vec![RouteConfig {
+            key: ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0").unwrap(),
+            scope: RouteScope::Cluster,
+        }]);
+        let conn_info = Arc::new(SwbusConnInfo::new_client(
+            ConnectionType::Local,
+            "127.0.0.1:8080".parse().unwrap(),
+            ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0").unwrap(),
+            ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0").unwrap(),
+        ));
+        let (send_queue_tx, mut send_queue_rx) = mpsc::channel(16);
+        let conn = SwbusConn::new(&conn_info, send_queue_tx);
+        mux.register(&conn_info, conn.new_proxy());
+        let sp = |s: &str| ServicePath::from_string(s).unwrap();
+        let registered = |mux: &SwbusMultiplexer| -> Vec<String> {
+            mux.export_registrations()
+                .into_iter()
+                .filter(|entry| entry.edge_service_path.is_some())
+                .map(|entry| entry.service_path.unwrap().to_string())
+                .collect()
+        };
+
+        // paths under the service of the edge runtime can be registered, services of other processes in the node or in
+        // other nodes can't
+        let request = RegistrationRequest::register(vec![
+            sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni0"),
+            sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni2"),
+        ]);
+        mux.process_registration(&conn_info, &request).unwrap();
+        for service_path in [
+            "region-a.cluster-a.10.0.0.1-dpu0/swss/0",
+            "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/1/hascope/eni0",
+            "region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0",
+        ] {
+            let request = RegistrationRequest::register(vec![sp(service_path)]);
+            let error = mux.process_registration(&conn_info, &request).unwrap_err();
+            assert_eq!(error.code(), SwbusErrorCode::InvalidArgs);
+        }
+        assert_eq!(
+            registered(&mux),
+            vec![
+                "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0",
+                "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni0",
+                "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni2"
+            ]
+        );
+
+        // each registered service is reachable
+        for destination in [
+            "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni0",
+            "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni2",
+        ] {
+            mux.route_message(group_message(destination)).await.unwrap();
+            assert_eq!(received_destination(&mut send_queue_rx).as_deref(), Some(destination));
+        }
+
+        let request =
+            RegistrationRequest::unregister(vec![sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni2")]);
+        mux.process_registration(&conn_info, &request).unwrap();
+        let request = RegistrationRequest::replace(vec![sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni1")]);
+        mux.process_registration(&conn_info, &request).unwrap();
+        assert_eq!(
+            registered(&mux),
+            vec![
+                "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0",
+                "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni1"
+            ]
+        );
+
+        // the registrations go away with the connection
+        mux.unregister(conn_info);
+        assert!(registered(&mux).is_empty());
+    }
 }
@@ -1,9 +1,12 @@
+use super::metrics::METRICS_CONN_LOCAL;
+use super::SubscriptionFilter;
 use super::SwbusConnInfo;
 use super::SwbusConnProxy;
 use super::SwbusMultiplexer;
 use getset::CopyGetters;
 use getset::Getters;
 use std::sync::Arc;
+use swbus_proto::message_flags::SwbusMessageFlags;
 use swbus_proto::result::*;
 use swbus_proto::swbus::*;
 use swbus_proto::swbus::{swbus_message, SwbusMessage};
@@ -67,7 +70,7 @@ impl SwbusNextHop {
         let current_span = tracing::Span::current();
         debug!("Queue message");
         match self.nh_type {
-            NextHopType::Drop => self.drop_message(message).instrument(current_span.clone()).await,
+            NextHopType::Drop => self.drop_message(mux, message).instrument(current_span.clone()).await,
             NextHopType::Local => {
                 self.process_local_message(mux, message)
                     .instrument(current_span.clone())
@@ -78,6 +81,9 @@ impl SwbusNextHop {
                 header.ttl -= 1;
                 if header.ttl == 0 {
                     debug!("TTL expired");
+                    if header.has_flags(SwbusMessageFlags::NO_RESPONSE_ON_ERROR) {
+                        return Ok(None);
+                    }
                     let response = SwbusMessage::new_response(
                         &message,
                         Some(&mux.get_my_service_path()),
@@ -88,14 +94,32 @@ impl SwbusNextHop {
                     );
                     return Ok(Some(response));
                 }
+                // the peer only understands the negotiated protocol
+                if let Some(protocol) = self.conn_info.as_ref().and_then(|info| info.negotiated_protocol()) {
+                    protocol.prepare_outgoing(&mut message)?;
+                }
                 debug!("Sending to the remote endpoint");
-                self.conn_proxy
+                let conn_id = self.conn_info.as_ref().map(|x| x.id().as_str()).unwrap_or_default();
+                let conn_proxy = self
+                    .conn_proxy
                     .as_ref()
-                    .expect("conn_proxy shouldn't be None in remote nexthop")
-                    .try_queue(Ok(message))
-                    .await
-                    .unwrap();
-                Ok(None)
+                    .expect("conn_proxy shouldn't be None in remote nexthop");
+                mux.metrics().record_queue_depth(conn_id, conn_proxy.queue_depth());
+                let message_len = prost::Message::encoded_len(&message);
+                // Never wait for room in the queue, even for high priority messages: routing is shared by all the
+                // connections, so a slow peer would stall the others. High priority messages get the room kept for
+                // them instead.
+                let high_priority = message.has_flags(SwbusMessageFlags::HIGH_PRIORITY);
+                match conn_proxy.try_queue(Ok(message), high_priority).await {
+                    Ok(_) => {
+                        mux.metrics().record_sent(conn_id, message_len);
+                        Ok(None)
+                    }
+                    Err(e) => {
+                        mux.metrics().record_dropped(conn_id);
+                        Err(e)
+                    }
+                }
             }
         }
     }
@@ -111,6 +135,20 @@ impl SwbusNextHop {
             Some(swbus_message::Body::ManagementRequest(mgmt_request)) => {
                 self.process_mgmt_request(mux, &message, mgmt_request).unwrap()
             }
+            Some(swbus_message::Body::RegistrationQueryRequest(_)) => self.process_registration_query(mux, &message),
+            Some(swbus_message::Body::SubscribeRequest(request)) => {
+                self.process_subscribe_request(mux, &message, &request.topic, &request.publisher, true)
+            }
+            Some(swbus_message::Body::UnsubscribeRequest(request)) => {
+                self.process_subscribe_request(mux, &message, &request.topic, &request.publisher, false)
+            }
+            Some(swbus_message::Body::PublishRequest(_)) => {
+                mux.publish_message(&message).await?;
+                if !message.has_flags(SwbusMessageFlags::ACK_REQUESTED) {
+                    return Ok(None);
+                }
+                SwbusMessage::new_response(&message, None, SwbusErrorCode::Ok, "", mux.generate_message_id(), None)
+            }
             _ => {
                 debug!("Invalid message type to a local endpoint");
                 return Err(SwbusError::input(
@@ -162,9 +200,55 @@ impl SwbusNextHop {
         }
     }
 
-    async fn drop_message(&self, _: SwbusMessage) -> Result<Option<SwbusMessage>> {
+    fn process_registration_query(&self, mux: &SwbusMultiplexer, message: &SwbusMessage) -> SwbusMessage {
+        debug!("Received registration query");
+        let header = message.header.as_ref().unwrap();
+        let response = RegistrationQueryResponse::new(header.id, mux.export_registrations());
+        SwbusMessage::new(
+            SwbusMessageHeader::new(
+                header.destination.clone().unwrap_or_default(),
+                header.source.clone().unwrap_or_default(),
+                mux.generate_message_id(),
+            ),
+            swbus_message::Body::RegistrationQueryResponse(response),
+        )
+    }
+
+    fn process_subscribe_request(
+        &self,
+        mux: &SwbusMultiplexer,
+        message: &SwbusMessage,
+        topic: &str,
+        publisher: &str,
+        subscribe: bool,
+    ) -> SwbusMessage {
+        let subscriber = message
+            .header
+            .as_ref()
+            .and_then(|header| header.source.clone())
+            .unwrap_or_default();
+        debug!(subscribe, topic, publisher, "Received subscription request");
+        let result = SubscriptionFilter::new(topic, publisher).map(|filter| match subscribe {
+            true => mux.subscriptions().subscribe(subscriber, filter),
+            false => mux.subscriptions().unsubscribe(&subscriber, &filter),
+        });
+        let (error_code, error_message) = match result {
+            Ok(_) => (SwbusErrorCode::Ok, String::new()),
+            Err(e) => (e.code(), e.to_string()),
+        };
+        SwbusMessage::new_response(
+            message,
+            None,
+            error_code,
+            &error_message,
+            mux.generate_message_id(),
+            None,
+        )
+    }
+
+    async fn drop_message(&self, mux: &SwbusMultiplexer, _: SwbusMessage) -> Result<Option<SwbusMessage>> {
         debug!("Drop message");
-        // todo: increment drop counter
+        mux.metrics().record_dropped(METRICS_CONN_LOCAL);
         Ok(None)
     }
 }
@@ -175,6 +259,7 @@ mod tests {
     use crate::mux::RouteConfig;
     use crate::mux::SwbusConn;
     use std::sync::Arc;
+    use std::time::Duration;
     use swbus_proto::swbus::SwbusMessage;
     use tokio::sync::mpsc;
 
@@ -317,4 +402,122 @@ mod tests {
             _ => panic!("Expected response message"),
         }
     }
+
+    fn remote_nexthop(queue_size: usize) -> (SwbusNextHop, mpsc::Receiver<Result<SwbusMessage, tonic::Status>>) {
+        let conn_info = Arc::new(SwbusConnInfo::new_client(
+            ConnectionType::Cluster,
+            "127.0.0.1:8080".parse().unwrap(),
+            ServicePath::from_string("region-a.cluster-a.10.0.0.3-dpu0").unwrap(),
+            ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+        ));
+        let (send_queue_tx, send_queue_rx) = mpsc::channel(queue_size);
+        let conn = SwbusConn::new(&conn_info, send_queue_tx);
+        (SwbusNextHop::new_remote(conn_info, conn.new_proxy(), 1), send_queue_rx)
+    }
+
+    fn flagged_message(destination: &str, body: swbus_message::Body, flags: SwbusMessageFlags) -> SwbusMessage {
+        let header = SwbusMessageHeader::new(
+            ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/testsvc/0").unwrap(),
+            ServicePath::from_string(destination).unwrap(),
+            1,
+        );
+        SwbusMessage::new(header.with_flags(flags), body)
+    }
+
+    fn mux_with_my_route() -> SwbusMultiplexer {
+        let mux = SwbusMultiplexer::default();
+        mux.set_my_routes(vec![RouteConfig {
+            key: ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+            scope: RouteScope::Cluster,
+        }]);
+        mux
+    }
+
+    #[tokio::test]
+    async fn test_queue_message_remote_ttl_expired_without_response() {
+        let (nexthop, _send_queue_rx) = remote_nexthop(16);
+        let mux = mux_with_my_route();
+
+        let mut message = flagged_message(
+            "region-a.cluster-a.10.0.0.3-dpu0/testsvc/0",
+            swbus_message::Body::PingRequest(PingRequest::new()),
+            SwbusMessageFlags::NO_RESPONSE_ON_ERROR,
+        );
+        message.header.as_mut().unwrap().ttl = 1;
+
+        let result = nexthop.queue_message(&mux, message).await.unwrap();
+        assert!(result.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_queue_message_local_publish_with_ack() {
+        let nexthop = SwbusNextHop::new_local();
+        let mux = mux_with_my_route();
+        let publish = |flags| {
+            flagged_message(
+                "region-a.cluster-a.10.0.0.2-dpu0/local-mgmt/0",
+                swbus_message::Body::PublishRequest(PublishRequest::new("ha-scope-state", b"up".to_vec())),
+                flags,
+            )
+        };
+
+        let result = nexthop.queue_message(&mux, publish(SwbusMessageFlags::empty())).await;
+        assert!(result.unwrap().is_none());
+
+        let response = nexthop
+            .queue_message(&mux, publish(SwbusMessageFlags::ACK_REQUESTED))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            response.header.as_ref().unwrap().destination,
+            Some(ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/testsvc/0").unwrap())
+        );
+        match response.body.unwrap() {
+            swbus_message::Body::Response(response) => {
+                assert_eq!(response.error_code, SwbusErrorCode::Ok as i32);
+                assert_eq!(response.request_id, 1);
+            }
+            _ => panic!("Expected response message"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_queue_message_remote_high_priority_uses_reserved_room() {
+        let (nexthop, _send_queue_rx) = remote_nexthop(4);
+        let mux = mux_with_my_route();
+        let data = |flags| {
+            flagged_message(
+                "region-a.cluster-a.10.0.0.3-dpu0/testsvc/0",
+                swbus_message::Body::DataRequest(DataRequest::new(vec![])),
+                flags,
+            )
+        };
+
+        for _ in 0..3 {
+            nexthop
+                .queue_message(&mux, data(SwbusMessageFlags::empty()))
+                .await
+                .unwrap();
+        }
+        let error = nexthop
+            .queue_message(&mux, data(SwbusMessageFlags::empty()))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), SwbusErrorCode::QueueFull);
+
+        // the room kept for high priority messages is used, but they don't wait once it is full either
+        nexthop
+            .queue_message(&mux, data(SwbusMessageFlags::HIGH_PRIORITY))
+            .await
+            .unwrap();
+        let error = tokio::time::timeout(
+            Duration::from_millis(100),
+            nexthop.queue_message(&mux, data(SwbusMessageFlags::HIGH_PRIORITY)),
+        )
+        .await
+        .expect("high priority message must not wait for room")
+        .unwrap_err();
+        assert_eq!(error.code(), SwbusErrorCode::QueueFull);
+    }
 }
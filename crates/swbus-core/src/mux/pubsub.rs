@@ -0,0 +1,144 @@
+use super::multiplexer::is_route_prefix_of;
+use dashmap::DashMap;
+use std::collections::HashSet;
+use swbus_proto::result::*;
+use swbus_proto::service_path_pattern::ServicePathPattern;
+use swbus_proto::swbus::*;
+
+/// What a subscriber is interested in. Empty topic or publisher matches everything.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct SubscriptionFilter {
+    topic: String,
+    publisher: Option<ServicePathPattern>,
+}
+
+impl SubscriptionFilter {
+    pub fn new(topic: &str, publisher: &str) -> Result<Self> {
+        if topic.is_empty() && publisher.is_empty() {
+            return Err(SwbusError::input(
+                SwbusErrorCode::InvalidArgs,
+                "Either topic or publisher must be set in a subscription".to_string(),
+            ));
+        }
+        let publisher = match publisher.is_empty() {
+            true => None,
+            false => Some(ServicePathPattern::from_string(publisher)?),
+        };
+        Ok(SubscriptionFilter {
+            topic: topic.to_string(),
+            publisher,
+        })
+    }
+
+    pub fn matches(&self, topic: &str, publisher: &ServicePath) -> bool {
+        (self.topic.is_empty() || self.topic == topic)
+            && self.publisher.as_ref().is_none_or(|pattern| pattern.matches(publisher))
+    }
+}
+
+/// Subscriptions of the clients connected to this swbusd, keyed by the subscriber service path.
+#[derive(Default)]
+pub(crate) struct SwbusSubscriptions {
+    subscriptions: DashMap<ServicePath, HashSet<SubscriptionFilter>>,
+}
+
+impl SwbusSubscriptions {
+    /// Add a subscription. Returns false if the subscriber already has the same subscription.
+    pub fn subscribe(&self, subscriber: ServicePath, filter: SubscriptionFilter) -> bool {
+        self.subscriptions.entry(subscriber).or_default().insert(filter)
+    }
+
+    /// Remove a subscription. Returns false if the subscriber doesn't have it.
+    pub fn unsubscribe(&self, subscriber: &ServicePath, filter: &SubscriptionFilter) -> bool {
+        let removed = match self.subscriptions.get_mut(subscriber) {
+            Some(mut filters) => filters.remove(filter),
+            None => false,
+        };
+        self.subscriptions
+            .remove_if(subscriber, |_, filters| filters.is_empty());
+        removed
+    }
+
+    /// Remove the subscriptions of all subscribers under the route prefix, e.g. after their connection is lost.
+    /// Returns the number of subscribers removed.
+    pub fn remove_under(&self, prefix: &ServicePath) -> usize {
+        let count = self.subscriptions.len();
+        self.subscriptions
+            .retain(|subscriber, _| !is_route_prefix_of(prefix, subscriber));
+        count - self.subscriptions.len()
+    }
+
+    /// Get the subscribers of a message published to `topic` by `publisher`.
+    pub fn subscribers(&self, topic: &str, publisher: &ServicePath) -> Vec<ServicePath> {
+        self.subscriptions
+            .iter()
+            .filter(|entry| entry.value().iter().any(|filter| filter.matches(topic, publisher)))
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn sp(s: &str) -> ServicePath {
+        ServicePath::from_string(s).unwrap()
+    }
+
+    fn filter(topic: &str, publisher: &str) -> SubscriptionFilter {
+        SubscriptionFilter::new(topic, publisher).unwrap()
+    }
+
+    #[test]
+    fn subscription_filter_matches_topic_and_publisher() {
+        let publisher = sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0");
+        assert!(filter("ha-scope-state", "").matches("ha-scope-state", &publisher));
+        assert!(!filter("ha-scope-state", "").matches("ha-set-config", &publisher));
+        assert!(filter("", "region-a.cluster-a.*/hamgrd/**").matches("ha-set-config", &publisher));
+        assert!(!filter("", "region-a.cluster-b.**").matches("ha-set-config", &publisher));
+        assert!(filter("ha-scope-state", "region-a.**").matches("ha-scope-state", &publisher));
+        assert!(!filter("ha-scope-state", "region-a.**").matches("ha-set-config", &publisher));
+
+        assert!(SubscriptionFilter::new("", "").is_err());
+        assert!(SubscriptionFilter::new("", "region-a..**").is_err());
+    }
+
+    #[test]
+    fn subscriptions_can_be_added_and_removed() {
+        let subscriptions = SwbusSubscriptions::default();
+        let publisher = sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0");
+        let subscriber1 = sp("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0");
+        let subscriber2 = sp("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/1/hascope/eni0");
+
+        assert!(subscriptions.subscribe(subscriber1.clone(), filter("ha-scope-state", "")));
+        assert!(!subscriptions.subscribe(subscriber1.clone(), filter("ha-scope-state", "")));
+        assert!(subscriptions.subscribe(subscriber2.clone(), filter("", "region-a.**")));
+
+        let mut subscribers = subscriptions.subscribers("ha-scope-state", &publisher);
+        subscribers.sort_by_key(|subscriber| subscriber.to_string());
+        assert_eq!(subscribers, vec![subscriber1.clone(), subscriber2.clone()]);
+        assert_eq!(
+            subscriptions.subscribers("ha-set-config", &publisher),
+            vec![subscriber2.clone()]
+        );
+
+        assert!(subscriptions.unsubscribe(&subscriber1, &filter("ha-scope-state", "")));
+        assert!(!subscriptions.unsubscribe(&subscriber1, &filter("ha-scope-state", "")));
+        assert_eq!(
+            subscriptions.subscribers("ha-scope-state", &publisher),
+            vec![subscriber2.clone()]
+        );
+
+        assert_eq!(
+            subscriptions.remove_under(&sp("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0")),
+            0
+        );
+        assert_eq!(
+            subscriptions.remove_under(&sp("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/1")),
+            1
+        );
+        assert!(subscriptions.subscribers("ha-scope-state", &publisher).is_empty());
+    }
+}
@@ -1,4 +1,6 @@
 use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
 use std::io::BufReader;
 use std::net::SocketAddr;
@@ -23,6 +25,9 @@ pub struct PeerConfig {
     pub id: ServicePath,
     pub endpoint: SocketAddr,
     pub conn_type: ConnectionType,
+    /// gRPC compression of the messages sent to the peer. The peer answers with the same compression if it supports it.
+    #[serde(default)]
+    pub compression: Compression,
 }
 
 impl RoutesConfig {
@@ -35,6 +40,172 @@ impl RoutesConfig {
         // let routes_config = RoutesConfig::from_routes_config_serde(&route_config_serde);
         Ok(routes_config)
     }
+
+    /// Check the configuration for mistakes that would make swbusd misbehave at runtime.
+    ///
+    /// All problems are collected, so they can be reported to the user at once.
+    pub fn validate(&self) -> Result<(), Vec<ConfigIssue>> {
+        let mut issues = Vec::new();
+
+        if self.routes.is_empty() {
+            issues.push(ConfigIssue::new(
+                "routes",
+                "no route is configured, at least one is required",
+            ));
+        }
+
+        for (i, route) in self.routes.iter().enumerate() {
+            let location = format!("routes[{}]", i);
+            validate_node_path(&route.key, &format!("{}.key", location), &mut issues);
+            if route.scope == RouteScope::Client {
+                issues.push(ConfigIssue::new(
+                    format!("{}.scope", location),
+                    "scope Client is reserved for client connections, use Local, Cluster, Region or Global",
+                ));
+            }
+        }
+
+        let mut peer_ids: HashMap<&ServicePath, usize> = HashMap::new();
+        let mut peer_endpoints: HashMap<SocketAddr, usize> = HashMap::new();
+        for (i, peer) in self.peers.iter().enumerate() {
+            let location = format!("peers[{}]", i);
+            validate_node_path(&peer.id, &format!("{}.id", location), &mut issues);
+
+            if let Some(first) = peer_ids.insert(&peer.id, i) {
+                issues.push(ConfigIssue::new(
+                    format!("{}.id", location),
+                    format!(
+                        "duplicate peer {}, already defined in peers[{}]",
+                        peer.id.to_longest_path(),
+                        first
+                    ),
+                ));
+            }
+
+            if let Some(first) = peer_endpoints.insert(peer.endpoint, i) {
+                issues.push(ConfigIssue::new(
+                    format!("{}.endpoint", location),
+                    format!("endpoint {} is already used by peers[{}]", peer.endpoint, first),
+                ));
+            }
+
+            if self.routes.iter().any(|route| route.key == peer.id) {
+                issues.push(ConfigIssue::new(
+                    format!("{}.id", location),
+                    format!(
+                        "peer {} is the same as one of our own routes",
+                        peer.id.to_longest_path()
+                    ),
+                ));
+            }
+
+            self.validate_peer_conn_type(peer, &location, &mut issues);
+        }
+
+        match issues.is_empty() {
+            true => Ok(()),
+            false => Err(issues),
+        }
+    }
+
+    fn validate_peer_conn_type(&self, peer: &PeerConfig, location: &str, issues: &mut Vec<ConfigIssue>) {
+        let location = format!("{}.conn_type", location);
+        // The part of the service path that must be shared with one of our routes for the connection type.
+        let (expected_prefix, shared): (fn(&ServicePath) -> String, &str) = match peer.conn_type {
+            ConnectionType::Client => {
+                issues.push(ConfigIssue::new(
+                    location,
+                    "conn_type Client is only used by clients connecting to swbusd, use Local, Cluster, Region or Global",
+                ));
+                return;
+            }
+            ConnectionType::Local => (ServicePath::to_node_prefix, "node"),
+            ConnectionType::Cluster => (ServicePath::to_cluster_prefix, "cluster"),
+            ConnectionType::Region => (ServicePath::to_regional_prefix, "region"),
+            ConnectionType::Global => return,
+        };
+
+        let peer_prefix = expected_prefix(&peer.id);
+        if !self
+            .routes
+            .iter()
+            .any(|route| expected_prefix(&route.key) == peer_prefix)
+        {
+            issues.push(ConfigIssue::new(
+                location,
+                format!(
+                    "conn_type {:?} requires the peer to be in the same {} as one of our routes, but no route matches {}",
+                    peer.conn_type,
+                    shared,
+                    peer_prefix
+                ),
+            ));
+        }
+    }
+}
+
+/// A problem found in [`RoutesConfig`] by [`RoutesConfig::validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    /// Where the problem is, e.g. `peers[1].endpoint`.
+    pub location: String,
+    pub message: String,
+}
+
+impl ConfigIssue {
+    pub fn new(location: impl Into<String>, message: impl Into<String>) -> Self {
+        ConfigIssue {
+            location: location.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+/// Routes and peers are swbusd instances, so they must be identified by a complete node location
+/// (region.cluster.node) without any service or resource part.
+fn validate_node_path(sp: &ServicePath, location: &str, issues: &mut Vec<ConfigIssue>) {
+    let components = [
+        ("region_id", &sp.region_id),
+        ("cluster_id", &sp.cluster_id),
+        ("node_id", &sp.node_id),
+    ];
+    for (name, value) in components {
+        if value.is_empty() {
+            issues.push(ConfigIssue::new(
+                location,
+                format!(
+                    "{} is empty in \"{}\", expected region.cluster.node",
+                    name,
+                    sp.to_longest_path()
+                ),
+            ));
+        } else if value.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            issues.push(ConfigIssue::new(
+                location,
+                format!("{} \"{}\" contains whitespace or control characters", name, value),
+            ));
+        }
+    }
+
+    if !sp.service_type.is_empty()
+        || !sp.service_id.is_empty()
+        || !sp.resource_type.is_empty()
+        || !sp.resource_id.is_empty()
+    {
+        issues.push(ConfigIssue::new(
+            location,
+            format!(
+                "\"{}\" must not contain service or resource components",
+                sp.to_longest_path()
+            ),
+        ));
+    }
 }
 
 #[cfg(test)]
@@ -56,6 +227,7 @@ mod tests {
           - id: "region-a.cluster-a.10.0.0.3-dpu0"
             endpoint: "10.0.0.3:8000"
             conn_type: "Cluster"
+            compression: "Zstd"
         "#;
 
         let dir = tempdir().unwrap();
@@ -81,6 +253,9 @@ mod tests {
         );
         assert_eq!(config.routes[0].scope, RouteScope::Cluster);
 
+        assert_eq!(config.peers[0].compression, Compression::None);
+        assert_eq!(config.peers[1].compression, Compression::Zstd);
+
         assert_eq!(
             config.peers[0].id,
             ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap()
@@ -99,5 +274,161 @@ mod tests {
             "10.0.0.3:8000".parse().expect("not expecting error")
         );
         assert_eq!(config.peers[1].conn_type, ConnectionType::Cluster);
+        assert!(config.validate().is_ok());
+    }
+
+    fn route(key: &str) -> RouteConfig {
+        RouteConfig {
+            key: ServicePath::from_string(key).unwrap(),
+            scope: RouteScope::Cluster,
+        }
+    }
+
+    fn peer(id: &str, endpoint: &str, conn_type: ConnectionType) -> PeerConfig {
+        PeerConfig {
+            id: ServicePath::from_string(id).unwrap(),
+            endpoint: endpoint.parse().unwrap(),
+            conn_type,
+            compression: Compression::None,
+        }
+    }
+
+    fn validate_and_get_locations(config: &RoutesConfig) -> Vec<String> {
+        config
+            .validate()
+            .unwrap_err()
+            .into_iter()
+            .map(|issue| issue.location)
+            .collect()
+    }
+
+    #[test]
+    fn test_validate_empty_routes() {
+        let config = RoutesConfig {
+            routes: vec![],
+            peers: vec![],
+        };
+        assert_eq!(validate_and_get_locations(&config), vec!["routes"]);
+    }
+
+    #[test]
+    fn test_validate_duplicate_peers_and_endpoints() {
+        let config = RoutesConfig {
+            routes: vec![route("region-a.cluster-a.10.0.0.1-dpu0")],
+            peers: vec![
+                peer(
+                    "region-a.cluster-a.10.0.0.2-dpu0",
+                    "10.0.0.2:8000",
+                    ConnectionType::Cluster,
+                ),
+                peer(
+                    "region-a.cluster-a.10.0.0.2-dpu0",
+                    "10.0.0.3:8000",
+                    ConnectionType::Cluster,
+                ),
+                peer(
+                    "region-a.cluster-a.10.0.0.4-dpu0",
+                    "10.0.0.2:8000",
+                    ConnectionType::Cluster,
+                ),
+            ],
+        };
+        assert_eq!(
+            validate_and_get_locations(&config),
+            vec!["peers[1].id", "peers[2].endpoint"]
+        );
+    }
+
+    #[test]
+    fn test_validate_peer_same_as_my_route() {
+        let config = RoutesConfig {
+            routes: vec![route("region-a.cluster-a.10.0.0.1-dpu0")],
+            peers: vec![peer(
+                "region-a.cluster-a.10.0.0.1-dpu0",
+                "10.0.0.1:8000",
+                ConnectionType::Cluster,
+            )],
+        };
+        let issues = config.validate().unwrap_err();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(
+            issues[0].to_string(),
+            "peers[0].id: peer region-a.cluster-a.10.0.0.1-dpu0 is the same as one of our own routes"
+        );
+    }
+
+    #[test]
+    fn test_validate_conn_type_scope() {
+        let config = RoutesConfig {
+            routes: vec![route("region-a.cluster-a.10.0.0.1-dpu0")],
+            peers: vec![
+                peer(
+                    "region-a.cluster-b.10.0.0.2-dpu0",
+                    "10.0.0.2:8000",
+                    ConnectionType::Cluster,
+                ),
+                peer(
+                    "region-a.cluster-b.10.0.0.3-dpu0",
+                    "10.0.0.3:8000",
+                    ConnectionType::Region,
+                ),
+                peer(
+                    "region-b.cluster-b.10.0.0.4-dpu0",
+                    "10.0.0.4:8000",
+                    ConnectionType::Region,
+                ),
+                peer(
+                    "region-b.cluster-b.10.0.0.5-dpu0",
+                    "10.0.0.5:8000",
+                    ConnectionType::Global,
+                ),
+                peer(
+                    "region-a.cluster-a.10.0.0.6-dpu0",
+                    "10.0.0.6:8000",
+                    ConnectionType::Client,
+                ),
+            ],
+        };
+        assert_eq!(
+            validate_and_get_locations(&config),
+            vec!["peers[0].conn_type", "peers[2].conn_type", "peers[4].conn_type"]
+        );
+    }
+
+    #[test]
+    fn test_validate_invalid_paths() {
+        // These paths are rejected by ServicePath::from_string, but can still be built by code.
+        let mut client_route = route("region-a.cluster-a.10.0.0.9-dpu0");
+        client_route.scope = RouteScope::Client;
+        let mut empty_cluster_route = route("region-a.cluster-a.10.0.0.1-dpu0");
+        empty_cluster_route.key.cluster_id.clear();
+        let mut whitespace_peer = peer(
+            "region-a.cluster-a.10.0.0.4-dpu0",
+            "10.0.0.4:8000",
+            ConnectionType::Global,
+        );
+        whitespace_peer.id.cluster_id = "cluster a".to_string();
+        let config = RoutesConfig {
+            routes: vec![empty_cluster_route, client_route],
+            peers: vec![
+                peer("region-a.cluster-a", "10.0.0.2:8000", ConnectionType::Global),
+                peer(
+                    "region-a.cluster-a.10.0.0.3-dpu0/hamgrd/0",
+                    "10.0.0.3:8000",
+                    ConnectionType::Global,
+                ),
+                whitespace_peer,
+            ],
+        };
+        assert_eq!(
+            validate_and_get_locations(&config),
+            vec![
+                "routes[0].key",
+                "routes[1].scope",
+                "peers[0].id",
+                "peers[1].id",
+                "peers[2].id"
+            ]
+        );
     }
 }
@@ -1,21 +1,33 @@
 use super::SwbusConn;
 use super::SwbusMultiplexer;
 use crate::mux::conn_store::SwbusConnStore;
+use crate::mux::metrics::serve_metrics;
 use crate::mux::RoutesConfig;
 use crate::mux::SwbusConnInfo;
 use std::io;
+use std::net::SocketAddr;
 use std::pin::Pin;
+use std::str::FromStr;
 use std::sync::Arc;
+use swbus_proto::message_id_generator::MessageIdGenerator;
+use swbus_proto::protocol::SwbusProtocolSupport;
 use swbus_proto::result::*;
 use swbus_proto::swbus::swbus_service_server::{SwbusService, SwbusServiceServer};
 use swbus_proto::swbus::*;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 use tokio_stream::wrappers::ReceiverStream;
 use tokio_stream::Stream;
+use tonic::codec::CompressionEncoding;
+use tonic::metadata::MetadataValue;
+use tonic::server::NamedService;
 use tonic::{transport::Server, Request, Response, Status, Streaming};
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
 use tracing::*;
 pub struct SwbusServiceHost {
     swbus_server_addr: String,
+    metrics_addr: Option<SocketAddr>,
+    min_established_peers: usize,
     mux: Arc<SwbusMultiplexer>,
     conn_store: Arc<SwbusConnStore>,
 }
@@ -25,16 +37,35 @@ type SwbusMessageStream = Pin<Box<dyn Stream<Item = Result<SwbusMessage, Status>
 
 impl SwbusServiceHost {
     pub fn new(swbus_server_addr: String) -> Self {
-        let mux = Arc::new(SwbusMultiplexer::new());
+        Self::with_message_id_generator(swbus_server_addr, MessageIdGenerator::new())
+    }
+
+    /// Create a host that generates the IDs of the messages sent by swbusd with `id_generator`.
+    pub fn with_message_id_generator(swbus_server_addr: String, id_generator: MessageIdGenerator) -> Self {
+        let mux = Arc::new(SwbusMultiplexer::new().with_id_generator(id_generator));
         let conn_store = Arc::new(SwbusConnStore::new(mux.clone()));
         // populate the mux with the routes
         Self {
             swbus_server_addr,
+            metrics_addr: None,
+            min_established_peers: 0,
             mux,
             conn_store,
         }
     }
 
+    /// Serve Prometheus metrics over HTTP at the given address once the host is started.
+    pub fn with_metrics_addr(mut self, metrics_addr: SocketAddr) -> Self {
+        self.metrics_addr = Some(metrics_addr);
+        self
+    }
+
+    /// Only report SERVING in the gRPC health service once at least this many configured peers are connected.
+    pub fn with_min_established_peers(mut self, min_established_peers: usize) -> Self {
+        self.min_established_peers = min_established_peers;
+        self
+    }
+
     pub async fn start(self: SwbusServiceHost, routes_config: RoutesConfig) -> Result<()> {
         let addr = self.swbus_server_addr.parse().map_err(|e| {
             SwbusError::input(
@@ -56,22 +87,87 @@ impl SwbusServiceHost {
             self.conn_store.add_my_route(route);
         }
 
+        // local routes are installed, start reporting health based on peer connections. The reporter starts with the
+        // server SERVING, so the status is set before serving.
+        let (mut health_reporter, health_service) = tonic_health::server::health_reporter();
+        let mut established_peers_rx = self.conn_store.watch_established_peers();
+        let established_peers = *established_peers_rx.borrow_and_update();
+        Self::report_health(&mut health_reporter, established_peers, self.min_established_peers).await;
+        tokio::spawn(Self::run_health_task(
+            health_reporter,
+            established_peers_rx,
+            self.min_established_peers,
+        ));
+
         // add peers to the connection store
         for peer in routes_config.peers {
             self.conn_store.add_peer(peer);
         }
 
+        if let Some(metrics_addr) = self.metrics_addr {
+            let metrics = self.mux.metrics().clone();
+            tokio::spawn(async move {
+                if let Err(e) = serve_metrics(metrics_addr, metrics).await {
+                    error!("Metrics server stopped: {}", e);
+                }
+            });
+        }
+
         Server::builder()
-            .add_service(SwbusServiceServer::new(self))
+            .add_service(health_service)
+            .add_service(
+                // Any compression a peer is configured with is accepted. Responses use the compression that the
+                // client accepts, so each connection is compressed the way its client asked.
+                SwbusServiceServer::new(self)
+                    .accept_compressed(CompressionEncoding::Gzip)
+                    .accept_compressed(CompressionEncoding::Zstd)
+                    .send_compressed(CompressionEncoding::Gzip)
+                    .send_compressed(CompressionEncoding::Zstd),
+            )
             .serve(addr)
             .await
             .map_err(|e| {
                 SwbusError::connection(
                     SwbusErrorCode::ConnectionError,
-                    io::Error::new(io::ErrorKind::Other, format!("Failed to listen at {}: {}", addr, e)),
+                    io::Error::other(format!("Failed to listen at {}: {}", addr, e)),
                 )
             })
     }
+
+    /// Keep the health status of swbusd up to date as the established peers change, see
+    /// [`report_health`](Self::report_health).
+    async fn run_health_task(
+        mut health_reporter: HealthReporter,
+        mut established_peers_rx: watch::Receiver<usize>,
+        min_established_peers: usize,
+    ) {
+        while established_peers_rx.changed().await.is_ok() {
+            let established_peers = *established_peers_rx.borrow_and_update();
+            Self::report_health(&mut health_reporter, established_peers, min_established_peers).await;
+        }
+    }
+
+    /// Set the health status of swbusd. The server is SERVING when at least `min_established_peers` configured peers
+    /// are connected, otherwise NOT_SERVING.
+    async fn report_health(
+        health_reporter: &mut HealthReporter,
+        established_peers: usize,
+        min_established_peers: usize,
+    ) {
+        let status = match established_peers >= min_established_peers {
+            true => ServingStatus::Serving,
+            false => ServingStatus::NotServing,
+        };
+        info!(
+            established_peers,
+            min_established_peers, "Updating health status to {:?}", status
+        );
+        // "" is the overall health of the server
+        health_reporter.set_service_status("", status).await;
+        health_reporter
+            .set_service_status(SwbusServiceServer::<SwbusServiceHost>::NAME, status)
+            .await;
+    }
 }
 
 #[tonic::async_trait]
@@ -116,21 +212,242 @@ impl SwbusService for SwbusServiceHost {
             }
         };
 
+        let client_protocol = SwbusProtocolSupport::from_metadata(
+            request
+                .metadata()
+                .get(SWBUS_PROTOCOL_VERSION)
+                .and_then(|v| v.to_str().ok()),
+            request
+                .metadata()
+                .get(SWBUS_PROTOCOL_FEATURES)
+                .and_then(|v| v.to_str().ok()),
+        );
+        let negotiated = match client_protocol.and_then(|p| SwbusProtocolSupport::current().negotiate(&p)) {
+            Ok(negotiated) => negotiated,
+            Err(e) => {
+                error!("SwbusServiceServer::rejecting client {}: {}", service_path, e);
+                return Err(Status::failed_precondition(e.to_string()));
+            }
+        };
+
         let in_stream = request.into_inner();
         info!(
             conn_type = conn_type as i32,
             service_path = service_path.to_longest_path(),
+            protocol_version = negotiated.version,
+            protocol_features = negotiated.features,
             "Creating SwbusConn"
         );
         // outgoing message queue
         let (out_tx, out_rx) = mpsc::channel(16);
 
-        let conn_info = Arc::new(SwbusConnInfo::new_server(conn_type, client_addr, service_path));
+        let conn_info = Arc::new(
+            SwbusConnInfo::new_server(conn_type, client_addr, service_path).with_negotiated_protocol(negotiated),
+        );
         let conn =
             SwbusConn::from_incoming_stream(conn_info, in_stream, out_tx, self.mux.clone(), self.conn_store.clone())
                 .await;
         self.conn_store.conn_established(conn);
         let out_stream = ReceiverStream::new(out_rx);
-        Ok(Response::new(Box::pin(out_stream) as Self::StreamMessagesStream))
+        let mut response = Response::new(Box::pin(out_stream) as Self::StreamMessagesStream);
+        let negotiated = negotiated.as_support();
+        let meta = response.metadata_mut();
+        meta.insert(
+            SWBUS_PROTOCOL_VERSION,
+            MetadataValue::from_str(&negotiated.version_metadata()).unwrap(),
+        );
+        meta.insert(
+            SWBUS_PROTOCOL_FEATURES,
+            MetadataValue::from_str(&negotiated.features_metadata()).unwrap(),
+        );
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mux::{PeerConfig, RouteConfig};
+    use tokio::time::{self, Duration};
+    use tonic_health::pb::health_check_response::ServingStatus as PbServingStatus;
+    use tonic_health::pb::health_client::HealthClient;
+    use tonic_health::pb::HealthCheckRequest;
+
+    async fn query_health(addr: SocketAddr, service: &str) -> PbServingStatus {
+        let mut client = None;
+        for _ in 0..50 {
+            let endpoint = tonic::transport::Endpoint::from_shared(format!("http://{}", addr)).unwrap();
+            if let Ok(channel) = endpoint.connect().await {
+                client = Some(HealthClient::new(channel));
+                break;
+            }
+            time::sleep(Duration::from_millis(20)).await;
+        }
+        let response = client
+            .expect("swbusd is not listening")
+            .check(HealthCheckRequest {
+                service: service.to_string(),
+            })
+            .await
+            .unwrap();
+        response.into_inner().status()
+    }
+
+    fn free_addr() -> SocketAddr {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    fn routes_config(peer_addr: SocketAddr) -> RoutesConfig {
+        RoutesConfig {
+            routes: vec![RouteConfig {
+                key: ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0").unwrap(),
+                scope: RouteScope::Cluster,
+            }],
+            peers: vec![PeerConfig {
+                id: ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+                endpoint: peer_addr,
+                conn_type: ConnectionType::Cluster,
+                compression: Compression::None,
+            }],
+        }
+    }
+
+    #[tokio::test]
+    async fn health_is_serving_without_peer_requirement() {
+        let addr = free_addr();
+        let host = SwbusServiceHost::new(addr.to_string());
+        tokio::spawn(host.start(routes_config(free_addr())));
+
+        assert_eq!(query_health(addr, "").await, PbServingStatus::Serving);
+        assert_eq!(query_health(addr, "swbus.SwbusService").await, PbServingStatus::Serving);
+    }
+
+    #[tokio::test]
+    async fn health_is_not_serving_until_peers_are_established() {
+        let addr = free_addr();
+        let peer_addr = free_addr();
+        let host = SwbusServiceHost::new(addr.to_string()).with_min_established_peers(1);
+        tokio::spawn(host.start(routes_config(peer_addr)));
+
+        assert_eq!(query_health(addr, "").await, PbServingStatus::NotServing);
+
+        // bring up the peer and wait for the connection to be established
+        let mut peer_config = routes_config(addr);
+        peer_config.routes[0].key = ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap();
+        tokio::spawn(SwbusServiceHost::new(peer_addr.to_string()).start(peer_config));
+
+        let mut status = PbServingStatus::NotServing;
+        for _ in 0..50 {
+            status = query_health(addr, "").await;
+            if status == PbServingStatus::Serving {
+                break;
+            }
+            time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(status, PbServingStatus::Serving);
+    }
+
+    #[tokio::test]
+    async fn peers_can_connect_with_compression() {
+        let addr = free_addr();
+        let peer_addr = free_addr();
+        let mut config = routes_config(peer_addr);
+        config.peers[0].compression = Compression::Zstd;
+        let host = SwbusServiceHost::new(addr.to_string()).with_min_established_peers(1);
+        tokio::spawn(host.start(config));
+
+        let mut peer_config = routes_config(addr);
+        peer_config.routes[0].key = ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0").unwrap();
+        peer_config.peers[0].compression = Compression::Gzip;
+        tokio::spawn(SwbusServiceHost::new(peer_addr.to_string()).start(peer_config));
+
+        let mut status = PbServingStatus::NotServing;
+        for _ in 0..50 {
+            status = query_health(addr, "").await;
+            if status == PbServingStatus::Serving {
+                break;
+            }
+            time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(status, PbServingStatus::Serving);
+    }
+
+    /// Peer stand-in handing the metadata of every message stream opened to it to the test.
+    struct RecordingPeer {
+        metadata_tx: mpsc::Sender<tonic::metadata::MetadataMap>,
+    }
+
+    #[tonic::async_trait]
+    impl SwbusService for RecordingPeer {
+        type StreamMessagesStream = SwbusMessageStream;
+
+        async fn stream_messages(
+            &self,
+            request: Request<Streaming<SwbusMessage>>,
+        ) -> SwbusMessageResult<SwbusMessageStream> {
+            let _ = self.metadata_tx.send(request.metadata().clone()).await;
+            Err(Status::unavailable("recording only"))
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_are_compressed_as_configured() {
+        // swbusd compresses the streams it opens to a peer with the compression of the peer
+        let peer_addr = free_addr();
+        let (metadata_tx, mut metadata_rx) = mpsc::channel(4);
+        tokio::spawn(
+            Server::builder()
+                .add_service(
+                    SwbusServiceServer::new(RecordingPeer { metadata_tx }).accept_compressed(CompressionEncoding::Zstd),
+                )
+                .serve(peer_addr),
+        );
+        let addr = free_addr();
+        let mut config = routes_config(peer_addr);
+        config.peers[0].compression = Compression::Zstd;
+        tokio::spawn(SwbusServiceHost::new(addr.to_string()).start(config));
+
+        let metadata = time::timeout(Duration::from_secs(5), metadata_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(metadata.get("grpc-encoding").unwrap(), "zstd");
+
+        // and answers the streams opened to it with the compression their client accepts
+        let channel = loop {
+            let endpoint = tonic::transport::Endpoint::from_shared(format!("http://{}", addr)).unwrap();
+            if let Ok(channel) = endpoint.connect().await {
+                break channel;
+            }
+            time::sleep(Duration::from_millis(20)).await;
+        };
+        let mut client = swbus_proto::swbus::swbus_service_client::SwbusServiceClient::new(channel)
+            .send_compressed(CompressionEncoding::Gzip)
+            .accept_compressed(CompressionEncoding::Gzip);
+        let (_send_queue_tx, send_queue_rx) = mpsc::channel::<SwbusMessage>(1);
+        let mut request = Request::new(ReceiverStream::new(send_queue_rx));
+        let protocol = SwbusProtocolSupport::current();
+        let meta = request.metadata_mut();
+        meta.insert(
+            SWBUS_CLIENT_SERVICE_PATH,
+            MetadataValue::from_str("region-a.cluster-a.10.0.0.2-dpu0").unwrap(),
+        );
+        meta.insert(
+            SWBUS_CONNECTION_TYPE,
+            MetadataValue::from_str(ConnectionType::Cluster.as_str_name()).unwrap(),
+        );
+        meta.insert(
+            SWBUS_PROTOCOL_VERSION,
+            MetadataValue::from_str(&protocol.version_metadata()).unwrap(),
+        );
+        meta.insert(
+            SWBUS_PROTOCOL_FEATURES,
+            MetadataValue::from_str(&protocol.features_metadata()).unwrap(),
+        );
+        let response = client.stream_messages(request).await.unwrap();
+        assert_eq!(response.metadata().get("grpc-encoding").unwrap(), "gzip");
     }
 }
@@ -17,7 +17,6 @@ use tracing::{error, info};
 pub const RECEIVE_TIMEOUT: u32 = 3;
 
 /// The Topo struct contains the server jobs and clients' TX and RX of its message queues.
-
 pub struct TopoRuntime {
     pub name: String,
     /// The server jobs are the tokio tasks that run the swbusd servers.
@@ -143,8 +142,15 @@ impl TopoRuntime {
         let addr = format!("http://{}", node_addr);
 
         while start.elapsed() < Duration::from_secs(10) {
-            match SwbusCoreClient::connect(addr.clone(), client_sp.clone(), receive_queue_tx.clone()).await {
-                Ok((_, send_queue_tx, _)) => {
+            match SwbusCoreClient::connect(
+                addr.clone(),
+                client_sp.clone(),
+                receive_queue_tx.clone(),
+                Compression::None,
+            )
+            .await
+            {
+                Ok((_, send_queue_tx, _, _, _)) => {
                     self.client_receivers.insert(name.to_string(), receive_queue_rx);
                     self.client_senders.insert(name.to_string(), send_queue_tx);
                     info!("Client {} connected to {}", name, node_addr);
@@ -173,14 +179,14 @@ pub async fn run_tests(topo: &mut TopoRuntime, test_json_file: &str, test_case_n
                 continue;
             }
         }
-        if test.topo.is_some() && test.topo.as_ref().unwrap() != &topo.name {
-            info!(
-                "Skipping test {} due to mismatched topo: test.topo={}, running-topo={}",
-                test.name,
-                test.topo.as_ref().unwrap(),
-                topo.name
-            );
-            continue;
+        if let Some(test_topo) = test.topo.as_ref() {
+            if test_topo != &topo.name {
+                info!(
+                    "Skipping test {} due to mismatched topo: test.topo={}, running-topo={}",
+                    test.name, test_topo, topo.name
+                );
+                continue;
+            }
         }
         info!("Running test: {}", test.name);
         for (i, step) in test.steps.iter_mut().enumerate() {
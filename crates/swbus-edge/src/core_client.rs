@@ -1,13 +1,22 @@
 use contracts::requires;
 use dashmap::DashSet;
+use std::collections::VecDeque;
 use std::io;
+use std::pin::Pin;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use swbus_proto::message_flags::SwbusMessageFlags;
+use swbus_proto::message_id_generator::MessageIdGenerator;
+use swbus_proto::protocol::{SwbusNegotiatedProtocol, SwbusProtocolSupport};
 use swbus_proto::result::*;
 use swbus_proto::swbus::swbus_service_client::SwbusServiceClient;
 use swbus_proto::swbus::*;
-use tokio::sync::mpsc;
-use tokio_stream::wrappers::ReceiverStream;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio_stream::Stream;
+use tonic::codec::CompressionEncoding;
 use tonic::metadata::MetadataValue;
 use tonic::transport::Channel;
 use tonic::transport::Endpoint;
@@ -15,30 +24,123 @@ use tonic::Request;
 use tonic::Streaming;
 use tracing::error;
 use tracing::info;
+use tracing::warn;
+
+/// Maximum number of messages buffered in the client, e.g. while it reconnects to swbusd.
+pub const SWBUS_SEND_QUEUE_SIZE: usize = 10000;
+
+/// Delay before the first attempt to reconnect to swbusd. It doubles after each failed attempt.
+pub const DEFAULT_RECONNECT_INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Maximum delay between two attempts to reconnect to swbusd.
+pub const DEFAULT_RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// State of the connection of a [`SwbusCoreClient`] to swbusd.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display)]
+pub enum SwbusConnectionState {
+    /// Not started, or the connection is lost and the client waits before reconnecting.
+    Disconnected,
+    Connecting,
+    /// Connected, and the registered services are pushed to swbusd.
+    Connected,
+}
+
+type SwbusConnection = (
+    JoinHandle<Result<()>>,
+    mpsc::Sender<SwbusMessage>,
+    SwbusServiceClient<Channel>,
+    SwbusNegotiatedProtocol,
+    SwbusSendQueue,
+);
+
+/// The receiving end of the send queue of a connection, streamed to swbusd. It is shared, so the messages still queued
+/// when the connection is lost can be taken back and sent over the next connection.
+#[derive(Clone)]
+pub struct SwbusSendQueue(Arc<Mutex<mpsc::Receiver<SwbusMessage>>>);
+
+/// The send queue of a connection, as streamed to swbusd. The queue is closed once the stream is dropped, so the
+/// messages sent to a dead connection are refused instead of waiting for room forever.
+struct SwbusSendStream(SwbusSendQueue);
+
+impl Stream for SwbusSendStream {
+    type Item = SwbusMessage;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<SwbusMessage>> {
+        self.0 .0.lock().unwrap().poll_recv(cx)
+    }
+}
+
+impl Drop for SwbusSendStream {
+    fn drop(&mut self) {
+        self.0 .0.lock().unwrap().close();
+    }
+}
 
 pub struct SwbusCoreClient {
     uri: String,
     sp: ServicePath,
     local_services: Arc<DashSet<ServicePath>>,
+    id_generator: Arc<MessageIdGenerator>,
+    reconnect_backoff: (Duration, Duration),
+    compression: Compression,
 
-    client: Option<SwbusServiceClient<Channel>>,
-    send_queue_tx: Option<mpsc::Sender<SwbusMessage>>,
+    // The messages sent through the client are buffered here, and forwarded to swbusd by the connection task.
+    send_queue_tx: mpsc::Sender<SwbusMessage>,
+    send_queue_rx: Option<mpsc::Receiver<SwbusMessage>>,
     message_processor_tx: mpsc::Sender<SwbusMessage>,
+    state_tx: Arc<watch::Sender<SwbusConnectionState>>,
 
-    recv_stream_task: Option<tokio::task::JoinHandle<Result<()>>>,
+    connection_task: Option<JoinHandle<()>>,
 }
 
 // Factory functions
 impl SwbusCoreClient {
     pub fn new(uri: String, sp: ServicePath, message_processor_tx: mpsc::Sender<SwbusMessage>) -> Self {
+        let (send_queue_tx, send_queue_rx) = mpsc::channel(SWBUS_SEND_QUEUE_SIZE);
+        let (state_tx, _) = watch::channel(SwbusConnectionState::Disconnected);
         Self {
             uri,
             sp,
             local_services: Arc::new(DashSet::new()),
-            client: None,
-            send_queue_tx: None,
+            id_generator: Arc::new(MessageIdGenerator::new()),
+            reconnect_backoff: (DEFAULT_RECONNECT_INITIAL_BACKOFF, DEFAULT_RECONNECT_MAX_BACKOFF),
+            compression: Compression::None,
+            send_queue_tx,
+            send_queue_rx: Some(send_queue_rx),
             message_processor_tx,
-            recv_stream_task: None,
+            state_tx: Arc::new(state_tx),
+            connection_task: None,
+        }
+    }
+
+    /// Set the delays between the attempts to reconnect to swbusd. The delay starts at `initial` and doubles after
+    /// each failed attempt, up to `max`.
+    pub fn with_reconnect_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.reconnect_backoff = (initial, max.max(initial));
+        self
+    }
+
+    /// Compress the message streams to swbusd with the given gRPC compression. swbusd answers with the same
+    /// compression.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+}
+
+/// gRPC encoding of the messages sent with the given compression.
+fn grpc_compression_encoding(compression: Compression) -> Option<CompressionEncoding> {
+    match compression {
+        Compression::None => None,
+        Compression::Gzip => Some(CompressionEncoding::Gzip),
+        Compression::Zstd => Some(CompressionEncoding::Zstd),
+    }
+}
+
+impl Drop for SwbusCoreClient {
+    fn drop(&mut self) {
+        if let Some(connection_task) = self.connection_task.take() {
+            connection_task.abort();
         }
     }
 }
@@ -53,8 +155,43 @@ impl SwbusCoreClient {
         self.local_services.remove(&svc);
     }
 
-    pub fn push_svc(&self) -> Result<()> {
-        Ok(())
+    /// Push all registered services to swbusd, in place of the ones pushed before. swbusd routes the messages to
+    /// each of them to this client. The services are pushed every time the client (re)connects.
+    pub async fn push_svc(&self) -> Result<()> {
+        let message = Self::push_message(&self.sp, &self.local_services, &self.id_generator);
+        self.send(message).await
+    }
+
+    fn push_message(
+        sp: &ServicePath,
+        local_services: &DashSet<ServicePath>,
+        id_generator: &MessageIdGenerator,
+    ) -> SwbusMessage {
+        let services: Vec<ServicePath> = local_services.iter().map(|svc| svc.key().clone()).collect();
+        info!("Pushing {} registered services to swbusd", services.len());
+        Self::registration_message(sp, id_generator.generate(), RegistrationRequest::replace(services))
+    }
+
+    /// Build the message that sends `request` from the edge runtime `sp` to the swbusd it is connected to.
+    pub(crate) fn registration_message(sp: &ServicePath, id: u64, request: RegistrationRequest) -> SwbusMessage {
+        SwbusMessage::new(
+            SwbusMessageHeader::new(sp.clone(), sp.clone_for_local_mgmt(), id),
+            swbus_message::Body::RegistrationRequest(request),
+        )
+    }
+
+    /// Generator of the IDs of the messages generated by the edge runtime itself.
+    pub fn id_generator(&self) -> Arc<MessageIdGenerator> {
+        self.id_generator.clone()
+    }
+
+    /// The services registered in this client, shared with the edge runtime that registers its handlers in it.
+    pub fn local_services(&self) -> Arc<DashSet<ServicePath>> {
+        self.local_services.clone()
+    }
+
+    pub fn sp(&self) -> &ServicePath {
+        &self.sp
     }
 }
 
@@ -64,19 +201,12 @@ impl SwbusCoreClient {
         uri: String,
         sp: ServicePath,
         receive_queue_tx: mpsc::Sender<SwbusMessage>,
-    ) -> Result<(
-        tokio::task::JoinHandle<Result<()>>,
-        mpsc::Sender<SwbusMessage>,
-        SwbusServiceClient<Channel>,
-    )> {
+        compression: Compression,
+    ) -> Result<SwbusConnection> {
         let (send_queue_tx, send_queue_rx) = mpsc::channel::<SwbusMessage>(100);
+        let send_queue = SwbusSendQueue(Arc::new(Mutex::new(send_queue_rx)));
 
-        let endpoint = Endpoint::from_str(&uri).map_err(|e| {
-            SwbusError::input(
-                SwbusErrorCode::InvalidArgs,
-                format!("Failed to create endpoint: {}.", e),
-            )
-        })?;
+        let endpoint = Self::endpoint(&uri)?;
 
         let channel = match endpoint.connect().await {
             Ok(c) => c,
@@ -90,9 +220,11 @@ impl SwbusCoreClient {
         };
         info!("Connected to the server");
         let mut client = SwbusServiceClient::new(channel);
+        if let Some(encoding) = grpc_compression_encoding(compression) {
+            client = client.send_compressed(encoding).accept_compressed(encoding);
+        }
 
-        let send_stream = ReceiverStream::new(send_queue_rx);
-        let mut send_stream_request = Request::new(send_stream);
+        let mut send_stream_request = Request::new(SwbusSendStream(send_queue.clone()));
 
         let meta = send_stream_request.metadata_mut();
 
@@ -106,48 +238,125 @@ impl SwbusCoreClient {
             MetadataValue::from_str(ConnectionType::Local.as_str_name()).unwrap(),
         );
 
-        let recv_stream = match client.stream_messages(send_stream_request).await {
-            Ok(response) => response.into_inner(),
+        let local_protocol = SwbusProtocolSupport::current();
+        meta.insert(
+            SWBUS_PROTOCOL_VERSION,
+            MetadataValue::from_str(&local_protocol.version_metadata()).unwrap(),
+        );
+        meta.insert(
+            SWBUS_PROTOCOL_FEATURES,
+            MetadataValue::from_str(&local_protocol.features_metadata()).unwrap(),
+        );
+
+        let response = match client.stream_messages(send_stream_request).await {
+            Ok(response) => response,
             Err(e) => {
                 error!("Failed to establish message streaming: {}.", e);
-                return Err(SwbusError::connection(
-                    SwbusErrorCode::ConnectionError,
-                    io::Error::new(io::ErrorKind::Unsupported, e.to_string()),
-                ));
+                return Err(e.into());
             }
         };
 
+        let metadata = response.metadata();
+        let negotiated = SwbusProtocolSupport::from_metadata(
+            metadata.get(SWBUS_PROTOCOL_VERSION).and_then(|v| v.to_str().ok()),
+            metadata.get(SWBUS_PROTOCOL_FEATURES).and_then(|v| v.to_str().ok()),
+        )
+        .and_then(|server_protocol| local_protocol.negotiate(&server_protocol))
+        .map_err(|e| {
+            error!("Failed to negotiate protocol with the server: {}.", e);
+            SwbusError::connection(
+                SwbusErrorCode::ConnectionError,
+                io::Error::new(io::ErrorKind::Unsupported, e.to_string()),
+            )
+        })?;
+        info!(
+            version = negotiated.version,
+            features = negotiated.features,
+            "Negotiated protocol with the server"
+        );
+        let recv_stream = response.into_inner();
+
         let message_processor_tx_clone = receive_queue_tx.clone();
         let recv_stream_task =
             tokio::spawn(async move { Self::run_recv_stream_task(recv_stream, message_processor_tx_clone).await });
-        Ok((recv_stream_task, send_queue_tx, client))
+        Ok((recv_stream_task, send_queue_tx, client, negotiated, send_queue))
     }
 
-    #[requires(self.recv_stream_task.is_none() && self.client.is_none() && self.send_queue_tx.is_none())]
+    fn endpoint(uri: &str) -> Result<Endpoint> {
+        Endpoint::from_str(uri).map_err(|e| {
+            SwbusError::input(
+                SwbusErrorCode::InvalidArgs,
+                format!("Failed to create endpoint: {}.", e),
+            )
+        })
+    }
+
+    /// Connect to swbusd in the background, and keep the client connected from then on: until the first connection,
+    /// and whenever the connection is lost, the client connects with backoff and pushes the registered services. The
+    /// messages sent in the meantime are buffered, up to [`SWBUS_SEND_QUEUE_SIZE`] messages.
+    ///
+    /// Returns without waiting for the connection, see [`watch_connection_state`](Self::watch_connection_state). Only
+    /// fails if the URI is invalid.
+    #[requires(self.connection_task.is_none())]
     pub async fn start(&mut self) -> Result<()> {
-        let (recv_stream_task, send_queue_tx, client) =
-            Self::connect(self.uri.clone(), self.sp.clone(), self.message_processor_tx.clone()).await?;
-        self.client = Some(client);
-        self.recv_stream_task = Some(recv_stream_task);
-        self.send_queue_tx = Some(send_queue_tx);
+        Self::endpoint(&self.uri)?;
 
+        let supervisor = SwbusConnectionSupervisor {
+            uri: self.uri.clone(),
+            sp: self.sp.clone(),
+            local_services: self.local_services.clone(),
+            id_generator: self.id_generator.clone(),
+            reconnect_backoff: self.reconnect_backoff,
+            compression: self.compression,
+            send_queue_rx: self.send_queue_rx.take().expect("client can only be started once"),
+            message_processor_tx: self.message_processor_tx.clone(),
+            state_tx: self.state_tx.clone(),
+        };
+        self.connection_task = Some(tokio::spawn(supervisor.run()));
         Ok(())
     }
 
+    /// Start the client without connecting to swbusd, e.g. to emulate swbusd in tests. Returns the receiver of the
+    /// messages sent to swbusd, and the sender of the messages received from it. The client is connected from then on.
+    #[cfg(any(test, feature = "test-util"))]
+    #[requires(self.connection_task.is_none())]
+    pub(crate) fn start_detached(&mut self) -> (mpsc::Receiver<SwbusMessage>, mpsc::Sender<SwbusMessage>) {
+        let send_queue_rx = self.send_queue_rx.take().expect("client can only be started once");
+        self.state_tx.send_replace(SwbusConnectionState::Connected);
+        (send_queue_rx, self.message_processor_tx.clone())
+    }
+
+    /// Subscribe to the state of the connection to swbusd.
+    pub fn watch_connection_state(&self) -> watch::Receiver<SwbusConnectionState> {
+        self.state_tx.subscribe()
+    }
+
+    /// Send a message to swbusd. While the client is not connected, the message is buffered, and an error is returned
+    /// if the buffer is full.
     pub async fn send(&self, message: SwbusMessage) -> Result<()> {
         // TODO: Check local registrations
-        match self.send_queue_tx.as_ref().unwrap().send(message).await {
-            Ok(_) => {}
-            Err(e) => {
-                error!("Failed to send message: {}.", e);
-                return Err(SwbusError::connection(
+        let connected = *self.state_tx.borrow() == SwbusConnectionState::Connected;
+        let result = match connected {
+            true => self.send_queue_tx.send(message).await.map_err(|_| None),
+            false => self.send_queue_tx.try_send(message).map_err(|e| match e {
+                mpsc::error::TrySendError::Full(_) => Some(SwbusError::route(
+                    SwbusErrorCode::QueueFull,
+                    "Send queue is full while not connected to swbusd".to_string(),
+                )),
+                mpsc::error::TrySendError::Closed(_) => None,
+            }),
+        };
+        match result {
+            Ok(_) => Ok(()),
+            Err(Some(e)) => Err(e),
+            Err(None) => {
+                error!("Failed to send message: connection task is stopped.");
+                Err(SwbusError::connection(
                     SwbusErrorCode::ConnectionError,
-                    io::Error::new(io::ErrorKind::ConnectionReset, e.to_string()),
-                ));
+                    io::Error::new(io::ErrorKind::ConnectionReset, "Connection task is stopped"),
+                ))
             }
         }
-
-        Ok(())
     }
 
     async fn run_recv_stream_task(
@@ -166,10 +375,7 @@ impl SwbusCoreClient {
                 // gRPC error was sent by the sender instead of a valid response message.
                 Err(e) => {
                     error!("Failed to receive message: {}.", e);
-                    return Err(SwbusError::connection(
-                        SwbusErrorCode::ConnectionError,
-                        io::Error::new(io::ErrorKind::ConnectionReset, e.to_string()),
-                    ));
+                    return Err(e.into());
                 }
             };
 
@@ -190,3 +396,435 @@ impl SwbusCoreClient {
         }
     }
 }
+
+/// Keeps a started [`SwbusCoreClient`] connected to swbusd, and forwards the messages sent through the client to the
+/// current connection.
+struct SwbusConnectionSupervisor {
+    uri: String,
+    sp: ServicePath,
+    local_services: Arc<DashSet<ServicePath>>,
+    id_generator: Arc<MessageIdGenerator>,
+    reconnect_backoff: (Duration, Duration),
+    compression: Compression,
+    send_queue_rx: mpsc::Receiver<SwbusMessage>,
+    message_processor_tx: mpsc::Sender<SwbusMessage>,
+    state_tx: Arc<watch::Sender<SwbusConnectionState>>,
+}
+
+impl SwbusConnectionSupervisor {
+    async fn run(mut self) {
+        // The messages swbusd didn't get when a connection is lost are sent again over the next one, before the
+        // messages sent through the client in the meantime.
+        let mut pending = VecDeque::new();
+        let mut connection = self.connect(false).await;
+        while self.forward_messages(&mut connection, &mut pending).await {
+            self.state_tx.send_replace(SwbusConnectionState::Disconnected);
+            warn!("Lost connection to swbusd, reconnecting.");
+            let (recv_stream_task, conn_send_queue_tx, _, _, send_queue) = connection;
+            recv_stream_task.abort();
+            drop(conn_send_queue_tx);
+            Self::take_back_unsent(&send_queue, &mut pending);
+            connection = self.connect(true).await;
+        }
+        info!("Client is gone, stopping connection task.");
+    }
+
+    /// Take back the messages still queued for a lost connection, and put them in front of the pending ones. The
+    /// services pushed when connecting are left out, as they are pushed again over the next connection.
+    fn take_back_unsent(send_queue: &SwbusSendQueue, pending: &mut VecDeque<SwbusMessage>) {
+        let mut send_queue_rx = send_queue.0.lock().unwrap();
+        send_queue_rx.close();
+        let mut unsent = Vec::new();
+        while let Ok(message) = send_queue_rx.try_recv() {
+            if !matches!(&message.body, Some(swbus_message::Body::RegistrationRequest(request)) if request.replace) {
+                unsent.push(message);
+            }
+        }
+        if !unsent.is_empty() {
+            info!(
+                "Sending {} messages queued for the lost connection again.",
+                unsent.len()
+            );
+        }
+        for message in unsent.into_iter().rev() {
+            pending.push_front(message);
+        }
+    }
+
+    /// Forward the sent messages to the connection until it is lost. Returns false once the client is gone.
+    async fn forward_messages(
+        &mut self,
+        connection: &mut SwbusConnection,
+        pending: &mut VecDeque<SwbusMessage>,
+    ) -> bool {
+        let (recv_stream_task, conn_send_queue_tx, _, protocol, _) = connection;
+
+        // swbusd only knows the services pushed over this connection.
+        let push = SwbusCoreClient::push_message(&self.sp, &self.local_services, &self.id_generator);
+        if Self::send_to_connection(conn_send_queue_tx, recv_stream_task, push)
+            .await
+            .is_err()
+        {
+            return true;
+        }
+        self.state_tx.send_replace(SwbusConnectionState::Connected);
+
+        loop {
+            let mut message = match pending.pop_front() {
+                Some(message) => message,
+                None => tokio::select! {
+                    message = self.send_queue_rx.recv() => match message {
+                        Some(message) => message,
+                        None => return false,
+                    },
+                    result = &mut *recv_stream_task => {
+                        Self::log_stream_end(result);
+                        return true;
+                    }
+                },
+            };
+            // swbusd only understands the negotiated protocol
+            if let Err(e) = protocol.prepare_outgoing(&mut message) {
+                self.refuse(message, e).await;
+                continue;
+            }
+            if let Err(message) = Self::send_to_connection(conn_send_queue_tx, recv_stream_task, message).await {
+                pending.push_front(message);
+                return true;
+            }
+        }
+    }
+
+    /// Queue a message for the connection, unless the connection is lost while waiting for room in its queue. The
+    /// message is given back if it is not queued.
+    async fn send_to_connection(
+        conn_send_queue_tx: &mpsc::Sender<SwbusMessage>,
+        recv_stream_task: &mut JoinHandle<Result<()>>,
+        message: SwbusMessage,
+    ) -> std::result::Result<(), SwbusMessage> {
+        tokio::select! {
+            permit = conn_send_queue_tx.reserve() => match permit {
+                Ok(permit) => {
+                    permit.send(message);
+                    Ok(())
+                }
+                Err(_) => {
+                    info!("Message stream to swbusd closed.");
+                    Err(message)
+                }
+            },
+            result = recv_stream_task => {
+                Self::log_stream_end(result);
+                Err(message)
+            }
+        }
+    }
+
+    fn log_stream_end(result: std::result::Result<Result<()>, tokio::task::JoinError>) {
+        match result {
+            Ok(Ok(_)) => info!("Message stream closed by swbusd."),
+            Ok(Err(e)) => error!("Message stream failed: {}.", e),
+            Err(e) => error!("Message stream task failed: {}.", e),
+        }
+    }
+
+    /// Answer a message that can't be sent to swbusd with an error response, unless it is a response itself or its
+    /// sender asked for no error response.
+    async fn refuse(&self, message: SwbusMessage, error: SwbusError) {
+        warn!("Can't send message to swbusd: {}", error);
+        if matches!(message.body, Some(swbus_message::Body::Response(_)))
+            || message.has_flags(SwbusMessageFlags::NO_RESPONSE_ON_ERROR)
+        {
+            return;
+        }
+        let response = SwbusMessage::new_response(
+            &message,
+            Some(&self.sp),
+            error.code(),
+            &error.to_string(),
+            self.id_generator.generate(),
+            None,
+        );
+        let _ = self.message_processor_tx.send(response).await;
+    }
+
+    /// Connect to swbusd, retrying with backoff until it succeeds. With `wait`, the first attempt is delayed too.
+    async fn connect(&self, mut wait: bool) -> SwbusConnection {
+        let (mut backoff, max_backoff) = self.reconnect_backoff;
+        loop {
+            if wait {
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(max_backoff);
+            }
+            wait = true;
+            self.state_tx.send_replace(SwbusConnectionState::Connecting);
+            let connection = SwbusCoreClient::connect(
+                self.uri.clone(),
+                self.sp.clone(),
+                self.message_processor_tx.clone(),
+                self.compression,
+            );
+            match connection.await {
+                Ok(connection) => {
+                    info!("Connected to swbusd.");
+                    return connection;
+                }
+                Err(e) => {
+                    warn!("Failed to connect to swbusd: {}. Retrying in {:?}.", e, backoff);
+                    self.state_tx.send_replace(SwbusConnectionState::Disconnected);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use swbus_proto::swbus::swbus_service_server::{SwbusService, SwbusServiceServer};
+    use tokio::time::timeout;
+    use tokio_stream::wrappers::ReceiverStream;
+    use tonic::{Response, Status};
+
+    type ServerConnection = (
+        Streaming<SwbusMessage>,
+        mpsc::Sender<std::result::Result<SwbusMessage, Status>>,
+        tonic::metadata::MetadataMap,
+    );
+
+    /// swbusd stand-in handing every accepted connection to the test.
+    struct FakeSwbusd {
+        connections_tx: mpsc::Sender<ServerConnection>,
+    }
+
+    #[tonic::async_trait]
+    impl SwbusService for FakeSwbusd {
+        type StreamMessagesStream = ReceiverStream<std::result::Result<SwbusMessage, Status>>;
+
+        async fn stream_messages(
+            &self,
+            request: Request<Streaming<SwbusMessage>>,
+        ) -> std::result::Result<Response<Self::StreamMessagesStream>, Status> {
+            let (tx, rx) = mpsc::channel(16);
+            let metadata = request.metadata().clone();
+            self.connections_tx
+                .send((request.into_inner(), tx, metadata))
+                .await
+                .unwrap();
+            Ok(Response::new(ReceiverStream::new(rx)))
+        }
+    }
+
+    fn free_addr() -> std::net::SocketAddr {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    fn start_fake_swbusd(addr: std::net::SocketAddr) -> mpsc::Receiver<ServerConnection> {
+        let (connections_tx, connections_rx) = mpsc::channel(4);
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(
+                    SwbusServiceServer::new(FakeSwbusd { connections_tx })
+                        .accept_compressed(CompressionEncoding::Gzip)
+                        .accept_compressed(CompressionEncoding::Zstd),
+                )
+                .serve(addr),
+        );
+        connections_rx
+    }
+
+    async fn next_connection(connections_rx: &mut mpsc::Receiver<ServerConnection>) -> ServerConnection {
+        timeout(Duration::from_secs(5), connections_rx.recv())
+            .await
+            .expect("timeout waiting for a connection")
+            .unwrap()
+    }
+
+    async fn next_message(conn: &mut ServerConnection) -> SwbusMessage {
+        timeout(Duration::from_secs(5), conn.0.message())
+            .await
+            .expect("timeout waiting for a message")
+            .unwrap()
+            .unwrap()
+    }
+
+    fn sp(s: &str) -> ServicePath {
+        ServicePath::from_string(s).unwrap()
+    }
+
+    fn assert_services_pushed(message: SwbusMessage, services: &[ServicePath]) {
+        match message.body {
+            Some(swbus_message::Body::RegistrationRequest(request)) => {
+                assert!(request.replace);
+                assert_eq!(request.register, services);
+            }
+            body => panic!("Expected registration request, got {:?}", body),
+        }
+    }
+
+    fn ping(client_sp: &ServicePath, id: u64) -> SwbusMessage {
+        SwbusMessage::new(
+            SwbusMessageHeader::new(client_sp.clone(), sp("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0"), id),
+            swbus_message::Body::PingRequest(PingRequest::new()),
+        )
+    }
+
+    #[tokio::test]
+    async fn client_starts_before_swbusd_is_up() {
+        let addr = free_addr();
+        let client_sp = sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0");
+        let (processor_tx, _processor_rx) = mpsc::channel(16);
+        let mut client = SwbusCoreClient::new(format!("http://{}", addr), client_sp.clone(), processor_tx)
+            .with_reconnect_backoff(Duration::from_millis(10), Duration::from_millis(50));
+        let mut state_rx = client.watch_connection_state();
+
+        // nothing is listening yet, the messages sent in the meantime are delivered once connected
+        client.start().await.unwrap();
+        client.send(ping(&client_sp, 1)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_ne!(*state_rx.borrow(), SwbusConnectionState::Connected);
+
+        let mut connections_rx = start_fake_swbusd(addr);
+        let mut conn = next_connection(&mut connections_rx).await;
+        assert_services_pushed(next_message(&mut conn).await, &[]);
+        assert_eq!(next_message(&mut conn).await, ping(&client_sp, 1));
+        state_rx
+            .wait_for(|state| *state == SwbusConnectionState::Connected)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn client_compresses_streams_to_swbusd() {
+        let addr = free_addr();
+        let mut connections_rx = start_fake_swbusd(addr);
+        let client_sp = sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0");
+        let (processor_tx, _processor_rx) = mpsc::channel(16);
+        let mut client = SwbusCoreClient::new(format!("http://{}", addr), client_sp, processor_tx)
+            .with_compression(Compression::Zstd);
+
+        client.start().await.unwrap();
+        let mut conn = next_connection(&mut connections_rx).await;
+        assert_eq!(conn.2.get("grpc-encoding").unwrap(), "zstd");
+        assert_services_pushed(next_message(&mut conn).await, &[]);
+    }
+
+    #[tokio::test]
+    async fn client_with_invalid_uri_fails_to_start() {
+        let (processor_tx, _processor_rx) = mpsc::channel(16);
+        let mut client = SwbusCoreClient::new(
+            "not a uri".to_string(),
+            sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0"),
+            processor_tx,
+        );
+        assert_eq!(client.start().await.unwrap_err().code(), SwbusErrorCode::InvalidArgs);
+    }
+
+    #[test]
+    fn messages_queued_for_lost_connection_are_taken_back() {
+        let client_sp = sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0");
+        let (send_queue_tx, send_queue_rx) = mpsc::channel(16);
+        let send_queue = SwbusSendQueue(Arc::new(Mutex::new(send_queue_rx)));
+        let push = SwbusCoreClient::registration_message(&client_sp, 1, RegistrationRequest::replace(vec![]));
+        for message in [push, ping(&client_sp, 2), ping(&client_sp, 3)] {
+            send_queue_tx.try_send(message).unwrap();
+        }
+
+        // the message that failed to be queued comes after the ones already queued, and the push is left out
+        let mut pending = VecDeque::from([ping(&client_sp, 4)]);
+        SwbusConnectionSupervisor::take_back_unsent(&send_queue, &mut pending);
+        let ids: Vec<u64> = pending
+            .iter()
+            .map(|message| message.header.as_ref().unwrap().id)
+            .collect();
+        assert_eq!(ids, vec![2, 3, 4]);
+        assert!(send_queue_tx.try_send(ping(&client_sp, 5)).is_err());
+    }
+
+    #[tokio::test]
+    async fn client_reconnects_and_pushes_services_again() {
+        let addr = free_addr();
+        let mut connections_rx = start_fake_swbusd(addr);
+        let client_sp = sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0");
+        let service = sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni0");
+        let (processor_tx, mut processor_rx) = mpsc::channel(16);
+        let mut client = SwbusCoreClient::new(format!("http://{}", addr), client_sp.clone(), processor_tx)
+            .with_reconnect_backoff(Duration::from_millis(10), Duration::from_millis(50));
+        client.register_svc(service.clone());
+        let mut state_rx = client.watch_connection_state();
+
+        client.start().await.unwrap();
+        let mut conn = next_connection(&mut connections_rx).await;
+        assert_services_pushed(next_message(&mut conn).await, std::slice::from_ref(&service));
+        state_rx
+            .wait_for(|state| *state == SwbusConnectionState::Connected)
+            .await
+            .unwrap();
+
+        // swbusd goes away, and the messages sent in the meantime are delivered after reconnecting
+        drop(conn);
+        state_rx
+            .wait_for(|state| *state != SwbusConnectionState::Connected)
+            .await
+            .unwrap();
+        let ping = ping(&client_sp, 1);
+        client.send(ping.clone()).await.unwrap();
+
+        let mut conn = next_connection(&mut connections_rx).await;
+        assert_services_pushed(next_message(&mut conn).await, std::slice::from_ref(&service));
+        assert_eq!(next_message(&mut conn).await, ping);
+        state_rx
+            .wait_for(|state| *state == SwbusConnectionState::Connected)
+            .await
+            .unwrap();
+
+        // messages from swbusd are received over the new connection
+        conn.1.send(Ok(ping.clone())).await.unwrap();
+        let received = timeout(Duration::from_secs(5), processor_rx.recv()).await.unwrap();
+        assert_eq!(received, Some(ping));
+    }
+
+    #[tokio::test]
+    async fn client_reconnects_when_swbusd_goes_away_while_the_queue_is_full() {
+        let addr = free_addr();
+        let mut connections_rx = start_fake_swbusd(addr);
+        let client_sp = sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0");
+        let (processor_tx, _processor_rx) = mpsc::channel(16);
+        let mut client = SwbusCoreClient::new(format!("http://{}", addr), client_sp.clone(), processor_tx)
+            .with_reconnect_backoff(Duration::from_millis(10), Duration::from_millis(50));
+        let mut state_rx = client.watch_connection_state();
+
+        client.start().await.unwrap();
+        let mut conn = next_connection(&mut connections_rx).await;
+        assert_services_pushed(next_message(&mut conn).await, &[]);
+        state_rx
+            .wait_for(|state| *state == SwbusConnectionState::Connected)
+            .await
+            .unwrap();
+
+        // swbusd stops reading, until the connection queue and the stream are full, and then goes away
+        let data = |id| {
+            SwbusMessage::new(
+                SwbusMessageHeader::new(client_sp.clone(), sp("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0"), id),
+                swbus_message::Body::DataRequest(DataRequest::new(vec![0; 16 * 1024])),
+            )
+        };
+        for id in 1..=1000 {
+            client.send(data(id)).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        drop(conn);
+
+        // the client reconnects, and the messages swbusd didn't get are sent again
+        let mut conn = next_connection(&mut connections_rx).await;
+        assert_services_pushed(next_message(&mut conn).await, &[]);
+        while next_message(&mut conn).await.header.unwrap().id != 1000 {}
+        state_rx
+            .wait_for(|state| *state == SwbusConnectionState::Connected)
+            .await
+            .unwrap();
+    }
+}
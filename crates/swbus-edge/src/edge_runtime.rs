@@ -1,12 +1,18 @@
-use crate::core_client::SwbusCoreClient;
+use crate::core_client::{SwbusConnectionState, SwbusCoreClient};
 use crate::message_handler_proxy::SwbusMessageHandlerProxy;
-use crate::message_router::SwbusMessageRouter;
+use crate::message_router::{SwbusMessageRouter, SwbusMessageRoutes};
+use dashmap::DashSet;
 use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use swbus_proto::message_id_generator::MessageIdGenerator;
 use swbus_proto::result::*;
+use swbus_proto::service_path_pattern::ServicePathPattern;
 use swbus_proto::swbus::*;
 use tokio::sync::mpsc::channel;
 use tokio::sync::mpsc::Sender;
-use tracing::info;
+use tokio::sync::watch;
+use tracing::{info, warn};
 
 pub(crate) const SWBUS_RECV_QUEUE_SIZE: usize = 10000;
 
@@ -14,33 +20,125 @@ pub struct SwbusEdgeRuntime {
     swbus_uri: String,
     message_router: SwbusMessageRouter,
     sender_to_message_router: Sender<SwbusMessage>,
+    local_services: Arc<DashSet<ServicePath>>,
+    sp: ServicePath,
+    id_generator: Arc<MessageIdGenerator>,
+    connection_state_rx: watch::Receiver<SwbusConnectionState>,
+    started: Arc<AtomicBool>,
 }
 
 impl SwbusEdgeRuntime {
     pub fn new(swbus_uri: String, sp: ServicePath) -> Self {
-        let (recv_queue_tx, recv_queue_rx) = channel::<SwbusMessage>(SWBUS_RECV_QUEUE_SIZE);
-        let sender_to_message_router = recv_queue_tx.clone();
-        let swbus_client = SwbusCoreClient::new(swbus_uri.clone(), sp, recv_queue_tx);
-        let message_router = SwbusMessageRouter::new(swbus_client, recv_queue_rx);
+        let (sender_to_message_router, recv_queue_rx) = channel::<SwbusMessage>(SWBUS_RECV_QUEUE_SIZE);
+        let (incoming_queue_tx, incoming_queue_rx) = channel::<SwbusMessage>(SWBUS_RECV_QUEUE_SIZE);
+        let swbus_client = SwbusCoreClient::new(swbus_uri.clone(), sp.clone(), incoming_queue_tx);
+        let local_services = swbus_client.local_services();
+        let id_generator = swbus_client.id_generator();
+        let connection_state_rx = swbus_client.watch_connection_state();
+        let message_router = SwbusMessageRouter::new(swbus_client, recv_queue_rx, incoming_queue_rx);
 
         Self {
             swbus_uri,
             message_router,
             sender_to_message_router,
+            local_services,
+            sp,
+            id_generator,
+            connection_state_rx,
+            started: Arc::new(AtomicBool::new(false)),
         }
     }
 
+    /// Compress the message streams to swbusd with the given gRPC compression.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.message_router.set_compression(compression);
+        self
+    }
+
+    /// Create a runtime that is not connected to swbusd, and hands the messages sent through it to the returned
+    /// receiver.
+    #[cfg(test)]
+    pub(crate) fn new_with_sent_messages(sp: ServicePath) -> (Self, tokio::sync::mpsc::Receiver<SwbusMessage>) {
+        let (sent_tx, sent_rx) = channel::<SwbusMessage>(SWBUS_RECV_QUEUE_SIZE);
+        let mut rt = Self::new(String::new(), sp);
+        rt.sender_to_message_router = sent_tx;
+        (rt, sent_rx)
+    }
+
+    /// Start routing the messages, and connect to swbusd in the background. Once started, the runtime reconnects by
+    /// itself when the connection to swbusd is lost, see [`SwbusCoreClient::start`].
     pub async fn start(&mut self) -> Result<()> {
         info!("Starting edge runtime with URI: {}", self.swbus_uri);
-        self.message_router.start().await
+        self.message_router.start().await?;
+        self.started.store(true, Ordering::Release);
+        Ok(())
     }
 
-    pub async fn add_handler(&self, svc_path: ServicePath, handler_tx: Sender<SwbusMessage>) -> Result<()> {
+    /// Start routing the messages without connecting to swbusd. Returns the receiver of the messages sent to swbusd,
+    /// and the sender of the messages received from it, see [`crate::mock::MockSwbus`].
+    #[cfg(any(test, feature = "test-util"))]
+    pub(crate) fn start_detached(&mut self) -> (tokio::sync::mpsc::Receiver<SwbusMessage>, Sender<SwbusMessage>) {
+        let channels = self.message_router.start_detached();
+        self.started.store(true, Ordering::Release);
+        channels
+    }
+
+    /// Subscribe to the state of the connection to swbusd, e.g. to resync the application state after reconnecting.
+    pub fn watch_connection_state(&self) -> watch::Receiver<SwbusConnectionState> {
+        self.connection_state_rx.clone()
+    }
+
+    /// Add a handler for a service path, or for all service paths matching a [`ServicePathPattern`].
+    ///
+    /// Handlers of exact service paths under the service of the runtime are registered as local services, and listed in
+    /// the answer to registration queries. swbusd doesn't accept the registration of other services, so the handlers
+    /// of other service paths only receive the messages sent within this runtime. The local services are pushed to
+    /// swbusd when the runtime connects, and one by one once connected, so swbusd routes the messages to each of them
+    /// to this runtime.
+    ///
+    /// The handler stays until the returned handle is dropped, or another handler is added for the same service path.
+    /// Messages received from swbusd for a service path without handler are answered with `SERVICE_NOT_FOUND`, or
+    /// `RESOURCE_NOT_FOUND` if its service has a handler.
+    pub async fn add_handler(
+        &self,
+        svc_path: impl Into<ServicePathPattern>,
+        handler_tx: Sender<SwbusMessage>,
+    ) -> Result<SwbusHandlerHandle> {
+        let (handle, new_service) = self.insert_handler(svc_path.into(), handler_tx);
+        if let Some(svc_path) = new_service.filter(|_| self.started.load(Ordering::Acquire)) {
+            let request = RegistrationRequest::register(vec![svc_path]);
+            let message = SwbusCoreClient::registration_message(&self.sp, self.id_generator.generate(), request);
+            self.send(message).await?;
+        }
+        Ok(handle)
+    }
+
+    /// Add the route to a handler, and return its handle and the local service it adds, if any.
+    pub(crate) fn insert_handler(
+        &self,
+        pattern: ServicePathPattern,
+        handler_tx: Sender<SwbusMessage>,
+    ) -> (SwbusHandlerHandle, Option<ServicePath>) {
         // Create MessageHandlerProxy
         let proxy = SwbusMessageHandlerProxy::new(handler_tx);
+        let handle = SwbusHandlerHandle {
+            pattern: pattern.clone(),
+            handler_id: proxy.id(),
+            routes: self.message_router.routes(),
+            local_services: self.local_services.clone(),
+            sender_to_message_router: self.sender_to_message_router.clone(),
+            sp: self.sp.clone(),
+            id_generator: self.id_generator.clone(),
+            started: self.started.clone(),
+        };
 
-        self.message_router.add_route(svc_path, proxy);
-        Ok(())
+        let service_prefix = self.sp.to_service_prefix();
+        let new_service = pattern
+            .as_exact()
+            .filter(|svc_path| svc_path.to_service_prefix() == service_prefix)
+            .filter(|svc_path| self.local_services.insert(svc_path.clone()));
+        self.message_router.add_route(pattern, proxy);
+        (handle, new_service)
     }
 
     pub async fn send(&self, message: SwbusMessage) -> Result<()> {
@@ -57,3 +155,111 @@ impl SwbusEdgeRuntime {
         }
     }
 }
+
+/// Handle of a handler added by [`SwbusEdgeRuntime::add_handler`]. Dropping it removes the handler, and unregisters its
+/// service from swbusd.
+#[must_use = "the handler is removed when its handle is dropped"]
+pub struct SwbusHandlerHandle {
+    pattern: ServicePathPattern,
+    handler_id: u64,
+    routes: Arc<SwbusMessageRoutes>,
+    local_services: Arc<DashSet<ServicePath>>,
+    sender_to_message_router: Sender<SwbusMessage>,
+    sp: ServicePath,
+    id_generator: Arc<MessageIdGenerator>,
+    started: Arc<AtomicBool>,
+}
+
+impl SwbusHandlerHandle {
+    /// The service path, or pattern of service paths, of the handler.
+    pub fn pattern(&self) -> &ServicePathPattern {
+        &self.pattern
+    }
+}
+
+impl Drop for SwbusHandlerHandle {
+    fn drop(&mut self) {
+        // A handler added later for the same service path is kept, along with its service.
+        if !self.routes.remove(&self.pattern, self.handler_id) {
+            return;
+        }
+        let Some(svc_path) = self.pattern.as_exact() else {
+            return;
+        };
+        if self.local_services.remove(&svc_path).is_none() || !self.started.load(Ordering::Acquire) {
+            return;
+        }
+
+        // Drop can't wait for room in the queue. If the unregistration is lost, swbusd keeps routing the messages to
+        // the service here, and the router refuses them.
+        let request = RegistrationRequest::unregister(vec![svc_path.clone()]);
+        let message = SwbusCoreClient::registration_message(&self.sp, self.id_generator.generate(), request);
+        if let Err(e) = self.sender_to_message_router.try_send(message) {
+            warn!("Failed to unregister service {}: {}", svc_path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sp(s: &str) -> ServicePath {
+        ServicePath::from_string(s).unwrap()
+    }
+
+    fn registration_request(message: SwbusMessage) -> RegistrationRequest {
+        match message.body {
+            Some(swbus_message::Body::RegistrationRequest(request)) => request,
+            body => panic!("Expected registration request, got {:?}", body),
+        }
+    }
+
+    #[tokio::test]
+    async fn dropping_handle_removes_handler_and_unregisters_service() {
+        let (rt, mut sent_rx) =
+            SwbusEdgeRuntime::new_with_sent_messages(sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0"));
+        rt.started.store(true, Ordering::Release);
+        let svc = sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni0");
+
+        let (handler_tx, _handler_rx) = channel(1);
+        let first = rt.add_handler(svc.clone(), handler_tx.clone()).await.unwrap();
+        assert_eq!(
+            registration_request(sent_rx.try_recv().unwrap()).register,
+            vec![svc.clone()]
+        );
+
+        // the service is already registered by the first handler, which is replaced
+        let second = rt.add_handler(svc.clone(), handler_tx).await.unwrap();
+        assert!(sent_rx.try_recv().is_err());
+        drop(first);
+        assert!(sent_rx.try_recv().is_err());
+        assert!(rt.local_services.contains(&svc));
+        assert!(rt.message_router.routes().find(&svc).is_some());
+
+        drop(second);
+        assert_eq!(
+            registration_request(sent_rx.try_recv().unwrap()).unregister,
+            vec![svc.clone()]
+        );
+        assert!(!rt.local_services.contains(&svc));
+        assert!(rt.message_router.routes().find(&svc).is_none());
+    }
+
+    #[tokio::test]
+    async fn handlers_of_other_services_are_not_registered() {
+        let (rt, mut sent_rx) =
+            SwbusEdgeRuntime::new_with_sent_messages(sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0"));
+        rt.started.store(true, Ordering::Release);
+        let svc = sp("region-a.cluster-a.10.0.0.1-dpu0/swss/0");
+
+        let (handler_tx, _handler_rx) = channel(1);
+        let handle = rt.add_handler(svc.clone(), handler_tx).await.unwrap();
+        assert!(sent_rx.try_recv().is_err());
+        assert!(!rt.local_services.contains(&svc));
+        assert!(rt.message_router.routes().find(&svc).is_some());
+
+        drop(handle);
+        assert!(sent_rx.try_recv().is_err());
+    }
+}
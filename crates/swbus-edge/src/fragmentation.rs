@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use swbus_proto::result::*;
+use swbus_proto::swbus::{DataFragment, DataRequest, ServicePath, SwbusErrorCode};
+use tracing::warn;
+
+/// Default maximum payload size of a single message. Larger data requests are split into fragments. It leaves room
+/// for the header under the 4MiB default gRPC message size limit.
+pub const DEFAULT_MAX_FRAGMENT_SIZE: usize = 1024 * 1024;
+
+/// Default time to receive all fragments of a data request, counted from the first fragment received.
+pub const DEFAULT_REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Maximum number of payload bytes buffered for reassembly, across all incomplete data requests.
+const MAX_REASSEMBLY_BYTES: usize = 256 * 1024 * 1024;
+
+/// Maximum number of fragments of a single data request. The fragment count comes from the peer, so it is checked
+/// before anything is allocated for it.
+pub(crate) const MAX_FRAGMENTS: u32 = 4096;
+
+/// Split the payload of a data request into fragments of at most `max_fragment_size` bytes. Every fragment carries the
+/// reliable sequence and the content type of the data request.
+pub(crate) fn fragment_payload(message_id: u64, request: &DataRequest, max_fragment_size: usize) -> Vec<DataFragment> {
+    let chunks: Vec<&[u8]> = request.payload.chunks(max_fragment_size.max(1)).collect();
+    let count = chunks.len() as u32;
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk)| DataFragment {
+            message_id,
+            index: index as u32,
+            count,
+            payload: chunk.to_vec(),
+            reliable: request.reliable,
+            content_type: request.content_type,
+        })
+        .collect()
+}
+
+struct ReassemblyBuffer {
+    fragments: Vec<Option<Vec<u8>>>,
+    received: usize,
+    bytes: usize,
+    started: Instant,
+}
+
+/// Reassembles the payloads of fragmented data requests.
+pub(crate) struct Reassembler {
+    buffers: HashMap<(ServicePath, u64), ReassemblyBuffer>,
+    // Data requests refused by the time they were refused, whose remaining fragments are ignored.
+    refused: HashMap<(ServicePath, u64), Instant>,
+    timeout: Duration,
+    bytes: usize,
+}
+
+impl Reassembler {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            buffers: HashMap::new(),
+            refused: HashMap::new(),
+            timeout,
+            bytes: 0,
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Add a fragment received from `source`. Returns the payload once all fragments of the data request are received.
+    ///
+    /// The data request is refused on the first error, so the error is returned once, and the fragments still to come
+    /// are ignored.
+    pub fn add(&mut self, source: &ServicePath, fragment: DataFragment, now: Instant) -> Result<Option<Vec<u8>>> {
+        self.expire(now);
+
+        let message_id = fragment.message_id;
+        if self.refused.contains_key(&(source.clone(), message_id)) {
+            return Ok(None);
+        }
+        self.add_fragment(source, fragment, now).inspect_err(|_| {
+            self.refuse(source, message_id, now);
+        })
+    }
+
+    /// Refuse a data request: its fragments are dropped, and the ones still to come are ignored. Returns false if the
+    /// data request was already refused.
+    pub fn refuse(&mut self, source: &ServicePath, message_id: u64, now: Instant) -> bool {
+        let key = (source.clone(), message_id);
+        if let Some(buffer) = self.buffers.remove(&key) {
+            self.bytes -= buffer.bytes;
+        }
+        self.refused.insert(key, now).is_none()
+    }
+
+    fn add_fragment(&mut self, source: &ServicePath, fragment: DataFragment, now: Instant) -> Result<Option<Vec<u8>>> {
+        let DataFragment {
+            message_id,
+            index,
+            count,
+            payload,
+            ..
+        } = fragment;
+        if count == 0 || count > MAX_FRAGMENTS || index >= count {
+            return Err(SwbusError::input(
+                SwbusErrorCode::InvalidPayload,
+                format!("Invalid fragment {} of {} for message {}", index, count, message_id),
+            ));
+        }
+
+        let key = (source.clone(), message_id);
+        let buffer = self.buffers.entry(key.clone()).or_insert_with(|| ReassemblyBuffer {
+            fragments: vec![None; count as usize],
+            received: 0,
+            bytes: 0,
+            started: now,
+        });
+        if buffer.fragments.len() != count as usize {
+            return Err(SwbusError::input(
+                SwbusErrorCode::InvalidPayload,
+                format!(
+                    "Fragment count {} of message {} doesn't match the previous fragments",
+                    count, message_id
+                ),
+            ));
+        }
+        if buffer.fragments[index as usize].is_some() {
+            // duplicated fragment, e.g. resent by the sender
+            return Ok(None);
+        }
+        if self.bytes + payload.len() > MAX_REASSEMBLY_BYTES {
+            return Err(SwbusError::route(
+                SwbusErrorCode::QueueFull,
+                format!(
+                    "Reassembly buffers are full, dropping fragment of message {}",
+                    message_id
+                ),
+            ));
+        }
+
+        self.bytes += payload.len();
+        buffer.bytes += payload.len();
+        buffer.received += 1;
+        buffer.fragments[index as usize] = Some(payload);
+        if buffer.received < buffer.fragments.len() {
+            return Ok(None);
+        }
+
+        let buffer = self.buffers.remove(&key).expect("buffer must exist");
+        self.bytes -= buffer.bytes;
+        Ok(Some(buffer.fragments.into_iter().flatten().flatten().collect()))
+    }
+
+    /// Drop the data requests whose fragments didn't all arrive in time.
+    pub fn expire(&mut self, now: Instant) {
+        let timeout = self.timeout;
+        let mut expired_bytes = 0;
+        self.buffers.retain(|(source, message_id), buffer| {
+            let alive = now.duration_since(buffer.started) < timeout;
+            if !alive {
+                warn!(
+                    "Dropping message {} from {}: received {} of {} fragments in {:?}",
+                    message_id,
+                    source,
+                    buffer.received,
+                    buffer.fragments.len(),
+                    timeout
+                );
+                expired_bytes += buffer.bytes;
+            }
+            alive
+        });
+        self.bytes -= expired_bytes;
+        self.refused.retain(|_, refused| now.duration_since(*refused) < timeout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use swbus_proto::swbus::ContentType;
+
+    fn source() -> ServicePath {
+        ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0").unwrap()
+    }
+
+    #[test]
+    fn payload_can_be_fragmented_and_reassembled() {
+        let payload: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        let request = DataRequest {
+            content_type: ContentType::Json as i32,
+            ..DataRequest::new(payload.clone())
+        };
+        let mut fragments = fragment_payload(7, &request, 300);
+        assert_eq!(fragments.len(), 4);
+        assert!(fragments
+            .iter()
+            .all(|f| f.message_id == 7 && f.count == 4 && f.content_type() == ContentType::Json));
+        assert_eq!(fragments[3].payload.len(), 100);
+
+        // out of order and duplicated fragments
+        fragments.swap(0, 2);
+        fragments.insert(1, fragments[0].clone());
+
+        let mut reassembler = Reassembler::new(DEFAULT_REASSEMBLY_TIMEOUT);
+        let now = Instant::now();
+        let last = fragments.pop().unwrap();
+        for fragment in fragments {
+            assert_eq!(reassembler.add(&source(), fragment, now).unwrap(), None);
+        }
+        assert_eq!(reassembler.add(&source(), last, now).unwrap(), Some(payload));
+        assert!(reassembler.buffers.is_empty());
+        assert_eq!(reassembler.bytes, 0);
+    }
+
+    #[test]
+    fn invalid_fragments_are_rejected() {
+        let mut reassembler = Reassembler::new(DEFAULT_REASSEMBLY_TIMEOUT);
+        let now = Instant::now();
+        let fragment = |message_id, index, count| DataFragment {
+            message_id,
+            index,
+            count,
+            payload: vec![0; 10],
+            ..Default::default()
+        };
+
+        assert!(reassembler.add(&source(), fragment(1, 0, 0), now).is_err());
+        assert!(reassembler.add(&source(), fragment(2, 2, 2), now).is_err());
+        assert_eq!(reassembler.add(&source(), fragment(3, 0, 2), now).unwrap(), None);
+        let error = reassembler.add(&source(), fragment(3, 1, 3), now).unwrap_err();
+        assert_eq!(error.code(), SwbusErrorCode::InvalidPayload);
+    }
+
+    #[test]
+    fn refused_message_is_refused_once() {
+        let mut reassembler = Reassembler::new(Duration::from_secs(5));
+        let now = Instant::now();
+        let fragment = |index, count| DataFragment {
+            message_id: 1,
+            index,
+            count,
+            payload: vec![0; 10],
+            ..Default::default()
+        };
+
+        // the fragments received before the error are dropped, and the ones after it are ignored
+        assert_eq!(reassembler.add(&source(), fragment(0, 3), now).unwrap(), None);
+        assert!(reassembler.add(&source(), fragment(1, 2), now).is_err());
+        assert!(reassembler.buffers.is_empty());
+        assert_eq!(reassembler.bytes, 0);
+        assert_eq!(reassembler.add(&source(), fragment(1, 3), now).unwrap(), None);
+        assert_eq!(reassembler.add(&source(), fragment(2, 3), now).unwrap(), None);
+        assert!(reassembler.buffers.is_empty());
+        assert!(!reassembler.refuse(&source(), 1, now));
+
+        // the refusal is forgotten with the reassembly timeout
+        let later = now + Duration::from_secs(5);
+        assert_eq!(reassembler.add(&source(), fragment(0, 2), later).unwrap(), None);
+        assert_eq!(reassembler.buffers.len(), 1);
+    }
+
+    #[test]
+    fn huge_fragment_count_is_rejected_before_allocating() {
+        let mut reassembler = Reassembler::new(DEFAULT_REASSEMBLY_TIMEOUT);
+        let fragment = DataFragment {
+            message_id: 1,
+            index: 0,
+            count: u32::MAX,
+            payload: vec![0; 10],
+            ..Default::default()
+        };
+
+        let error = reassembler.add(&source(), fragment, Instant::now()).unwrap_err();
+        assert_eq!(error.code(), SwbusErrorCode::InvalidPayload);
+        assert!(reassembler.buffers.is_empty());
+    }
+
+    #[test]
+    fn incomplete_message_expires() {
+        let mut reassembler = Reassembler::new(Duration::from_secs(5));
+        let now = Instant::now();
+        let fragments = fragment_payload(1, &DataRequest::new(vec![1, 2, 3, 4]), 2);
+
+        assert_eq!(reassembler.add(&source(), fragments[0].clone(), now).unwrap(), None);
+        assert_eq!(reassembler.bytes, 2);
+
+        // the first fragment is dropped with the expired buffer, so the message can't complete anymore
+        let later = now + Duration::from_secs(5);
+        assert_eq!(reassembler.add(&source(), fragments[1].clone(), later).unwrap(), None);
+        assert_eq!(reassembler.bytes, 2);
+        assert_eq!(reassembler.buffers.len(), 1);
+    }
+}
@@ -1,7 +1,11 @@
 pub mod core_client;
 pub mod edge_runtime;
+pub mod fragmentation;
 mod message_handler_proxy;
 mod message_router;
+#[cfg(any(test, feature = "test-util"))]
+pub mod mock;
+pub mod reliable;
 pub mod simple_client;
 
 pub use edge_runtime::SwbusEdgeRuntime;
@@ -1,16 +1,29 @@
 use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use swbus_proto::result::*;
 use swbus_proto::swbus::*;
 use tokio::sync::mpsc::Sender;
 
+static NEXT_HANDLER_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Clone)]
 pub struct SwbusMessageHandlerProxy {
+    id: u64,
     tx: Sender<SwbusMessage>,
 }
 
 impl SwbusMessageHandlerProxy {
     pub fn new(tx: Sender<SwbusMessage>) -> Self {
-        Self { tx }
+        Self {
+            id: NEXT_HANDLER_ID.fetch_add(1, Ordering::Relaxed),
+            tx,
+        }
+    }
+
+    /// Unique id of the handler, to tell it from a handler added later for the same service path.
+    pub fn id(&self) -> u64 {
+        self.id
     }
 
     pub async fn send(&self, message: SwbusMessage) -> Result<()> {
@@ -2,58 +2,115 @@ use crate::core_client::SwbusCoreClient;
 use crate::message_handler_proxy::SwbusMessageHandlerProxy;
 use dashmap::DashMap;
 use std::sync::Arc;
+use swbus_proto::message_flags::SwbusMessageFlags;
+use swbus_proto::message_id_generator::MessageIdGenerator;
 use swbus_proto::result::*;
+use swbus_proto::service_path_pattern::ServicePathPattern;
 use swbus_proto::swbus::*;
 use tokio::sync::mpsc::Receiver;
 use tokio::task;
-use tracing::error;
+use tracing::{debug, error, info};
 
+/// Routes the messages of an edge runtime: the messages received from swbusd go to the local handlers, and the messages
+/// sent by the local handlers go to swbusd.
+///
+/// Messages from a local handler to another local handler are delivered in process, with the semantics of swbusd
+/// forwarding them to a client, where the runtime counts as one hop:
+/// - The TTL is decremented, and a message whose TTL runs out is answered with `UNREACHABLE`.
+/// - A message whose deadline has passed is answered with `TIMEOUT`.
+/// - A message to a handler that doesn't receive anymore is answered with `NO_ROUTE`.
+/// - Ping and traceroute requests are delivered to the destination handler, which answers them. Like swbusd, the
+///   runtime doesn't answer traceroute requests as a hop.
+///
+/// The error responses come from the service path of the runtime, like the ones of swbusd come from its own. They
+/// are not sent for responses and messages flagged with [`SwbusMessageFlags::NO_RESPONSE_ON_ERROR`]. Messages to
+/// group destinations are always sent to swbusd.
 pub struct SwbusMessageRouter {
-    routes: Arc<DashMap<ServicePath, SwbusMessageHandlerProxy>>,
+    routes: Arc<SwbusMessageRoutes>,
+    id_generator: Arc<MessageIdGenerator>,
 
     // Route task related parameters
     route_task: Option<tokio::task::JoinHandle<()>>,
     swbus_client: Option<SwbusCoreClient>,
+    // Messages sent by the local handlers.
     recv_rx: Option<Receiver<SwbusMessage>>,
+    // Messages received from swbusd.
+    incoming_rx: Option<Receiver<SwbusMessage>>,
 }
 
 impl SwbusMessageRouter {
-    pub fn new(swbus_client: SwbusCoreClient, recv_rx: Receiver<SwbusMessage>) -> Self {
+    pub fn new(
+        swbus_client: SwbusCoreClient,
+        recv_rx: Receiver<SwbusMessage>,
+        incoming_rx: Receiver<SwbusMessage>,
+    ) -> Self {
         Self {
-            routes: Arc::new(DashMap::new()),
+            routes: Arc::new(SwbusMessageRoutes::default()),
+            id_generator: swbus_client.id_generator(),
             route_task: None,
             swbus_client: Some(swbus_client),
             recv_rx: Some(recv_rx),
+            incoming_rx: Some(incoming_rx),
         }
     }
 }
 
 impl SwbusMessageRouter {
+    /// Compress the message streams of the client to swbusd, see [`SwbusCoreClient::with_compression`].
+    pub(crate) fn set_compression(&mut self, compression: Compression) {
+        let swbus_client = self.swbus_client.take().expect("router is already started");
+        self.swbus_client = Some(swbus_client.with_compression(compression));
+    }
+
     pub async fn start(&mut self) -> Result<()> {
-        let routes = self.routes.clone();
-        let mut recv_rx = self.recv_rx.take().unwrap();
         let mut swbus_client = self.swbus_client.take().unwrap();
         swbus_client.start().await?;
+        self.spawn_route_task(swbus_client);
+        Ok(())
+    }
 
+    /// Start routing the messages without connecting to swbusd, see [`SwbusCoreClient::start_detached`].
+    #[cfg(any(test, feature = "test-util"))]
+    pub(crate) fn start_detached(&mut self) -> (Receiver<SwbusMessage>, tokio::sync::mpsc::Sender<SwbusMessage>) {
+        let mut swbus_client = self.swbus_client.take().unwrap();
+        let channels = swbus_client.start_detached();
+        self.spawn_route_task(swbus_client);
+        channels
+    }
+
+    fn spawn_route_task(&mut self, mut swbus_client: SwbusCoreClient) {
+        let routes = self.routes.clone();
+        let id_generator = self.id_generator.clone();
+        let mut recv_rx = self.recv_rx.take().unwrap();
+        let mut incoming_rx = self.incoming_rx.take().unwrap();
         let route_task = task::spawn(async move {
-            while let Some(message) = recv_rx.recv().await {
-                // Route the received message from core_client to the appropriate handler.
-                Self::route_message(&mut swbus_client, &routes, message).await;
+            loop {
+                let (message, from_swbusd) = tokio::select! {
+                    Some(message) = recv_rx.recv() => (message, false),
+                    Some(message) = incoming_rx.recv() => (message, true),
+                    else => break,
+                };
+                // Route the message to the appropriate handler, or to swbusd.
+                Self::route_message(&mut swbus_client, &routes, &id_generator, message, from_swbusd).await;
             }
         });
         self.route_task = Some(route_task);
+    }
 
-        Ok(())
+    pub fn add_route(&self, pattern: ServicePathPattern, handler: SwbusMessageHandlerProxy) {
+        self.routes.add(pattern, handler);
     }
 
-    pub fn add_route(&self, svc_path: ServicePath, handler: SwbusMessageHandlerProxy) {
-        self.routes.insert(svc_path, handler);
+    pub(crate) fn routes(&self) -> Arc<SwbusMessageRoutes> {
+        self.routes.clone()
     }
 
     async fn route_message(
         swbus_client: &mut SwbusCoreClient,
-        routes: &Arc<DashMap<ServicePath, SwbusMessageHandlerProxy>>,
+        routes: &SwbusMessageRoutes,
+        id_generator: &MessageIdGenerator,
         message: SwbusMessage,
+        from_swbusd: bool,
     ) {
         // Route the message via routes, then default to the core client.
         let header = match message.header {
@@ -70,18 +127,451 @@ impl SwbusMessageRouter {
                 return;
             }
         };
-        // If the route entry doesn't exist, send to swbus_client.
-        match routes.get(destination) {
-            Some(handler) => {
-                if let Err(swbus_err) = handler.send(message).await {
-                    error!("Failed to send message to handler: {:?}", swbus_err);
+
+        // Registration queries to the runtime itself are answered with the services registered in it.
+        if destination == swbus_client.sp()
+            && matches!(message.body, Some(swbus_message::Body::RegistrationQueryRequest(_)))
+        {
+            debug!("Received registration query");
+            let mut entries: Vec<RegistrationEntry> = swbus_client
+                .local_services()
+                .iter()
+                .map(|svc| RegistrationEntry {
+                    service_path: Some(svc.key().clone()),
+                    edge_service_path: Some(swbus_client.sp().clone()),
+                    conn_id: String::new(),
+                })
+                .collect();
+            entries.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let response = SwbusMessage::new(
+                SwbusMessageHeader::new(
+                    destination.clone(),
+                    header.source.clone().unwrap_or_default(),
+                    id_generator.generate(),
+                ),
+                swbus_message::Body::RegistrationQueryResponse(RegistrationQueryResponse::new(header.id, entries)),
+            );
+            Self::deliver(swbus_client, routes, id_generator, response, false).await;
+            return;
+        }
+
+        Self::deliver(swbus_client, routes, id_generator, message, from_swbusd).await;
+    }
+
+    /// Deliver the message to the handler of its destination, or send it to swbusd. The messages received from swbusd
+    /// for a destination without handler are refused, see [`refuse_missing_handler`](Self::refuse_missing_handler).
+    async fn deliver(
+        swbus_client: &mut SwbusCoreClient,
+        routes: &SwbusMessageRoutes,
+        id_generator: &MessageIdGenerator,
+        message: SwbusMessage,
+        from_swbusd: bool,
+    ) {
+        let Some(header) = message.header.clone() else {
+            return;
+        };
+        let Some(destination) = header.destination.as_ref() else {
+            return;
+        };
+        let is_response = matches!(message.body, Some(swbus_message::Body::Response(_)));
+        // If the route entry doesn't exist, send to swbus_client. Group messages are always sent to swbusd, which
+        // replicates them to every member, including the ones in this process.
+        let handler = match destination.is_group() {
+            true => None,
+            false => routes.find(destination),
+        };
+        let refusal = match handler {
+            Some(handler) if from_swbusd => match handler.send(message).await {
+                Ok(_) => None,
+                // The handler is being removed.
+                Err(swbus_err) => {
+                    debug!("Failed to send message to handler: {:?}", swbus_err);
+                    Self::refuse_missing_handler(routes, id_generator, &header, is_response)
                 }
-            }
+            },
+            Some(handler) => Self::deliver_locally(&handler, swbus_client.sp(), id_generator, message).await,
+            // Sending the messages to the runtime itself to swbusd would bring them back here.
+            None if destination == swbus_client.sp() => match message.body {
+                Some(swbus_message::Body::Response(ref response))
+                    if response.error_code != SwbusErrorCode::Ok as i32 =>
+                {
+                    error!(
+                        "Request {} of the edge runtime failed: {}",
+                        response.request_id, response.error_message
+                    );
+                    None
+                }
+                _ => {
+                    debug!("Dropping message without handler to the edge runtime");
+                    None
+                }
+            },
+            // Sending the messages from swbusd back to it would make them loop until their TTL expires.
+            None if from_swbusd => Self::refuse_missing_handler(routes, id_generator, &header, is_response),
             None => {
                 if let Err(swbus_err) = swbus_client.send(message).await {
                     error!("Failed to send message to core client: {:?}", swbus_err);
                 }
+                None
+            }
+        };
+        if let Some(response) = refusal {
+            Box::pin(Self::deliver(swbus_client, routes, id_generator, response, false)).await;
+        }
+    }
+
+    /// Deliver a message from a local handler to another one as swbusd would, see [`SwbusMessageRouter`]. Returns the
+    /// error response to send back, if any.
+    async fn deliver_locally(
+        handler: &SwbusMessageHandlerProxy,
+        runtime_sp: &ServicePath,
+        id_generator: &MessageIdGenerator,
+        mut message: SwbusMessage,
+    ) -> Option<SwbusMessage> {
+        let is_response = matches!(message.body, Some(swbus_message::Body::Response(_)));
+        let header = message.header.as_mut()?;
+        if header.has_flags(SwbusMessageFlags::TRACE) {
+            info!(
+                message_id = header.id,
+                source = header.source.as_ref().map(|source| source.to_longest_path()),
+                destination = header
+                    .destination
+                    .as_ref()
+                    .map(|destination| destination.to_longest_path()),
+                "Delivering traced message in process"
+            );
+        }
+
+        let error = match header.is_expired() {
+            true => Some((SwbusErrorCode::Timeout, "Message deadline exceeded")),
+            false => {
+                header.ttl = header.ttl.saturating_sub(1);
+                (header.ttl == 0).then_some((SwbusErrorCode::Unreachable, "TTL expired"))
             }
         };
+        let header = header.clone();
+        let (error_code, error_message) = match error {
+            Some(error) => error,
+            None => match handler.send(message).await {
+                Ok(_) => return None,
+                Err(swbus_err) => {
+                    debug!("Failed to send message to handler: {:?}", swbus_err);
+                    (SwbusErrorCode::NoRoute, "Route not found")
+                }
+            },
+        };
+        Self::error_response(
+            runtime_sp,
+            id_generator,
+            &header,
+            is_response,
+            error_code,
+            error_message,
+        )
+    }
+
+    /// Build the error response to a message received from swbusd for a destination without handler, e.g. when its
+    /// handler was removed before swbusd processed the unregistration. The response is `RESOURCE_NOT_FOUND` if the
+    /// service of the destination has a handler, and `SERVICE_NOT_FOUND` otherwise.
+    fn refuse_missing_handler(
+        routes: &SwbusMessageRoutes,
+        id_generator: &MessageIdGenerator,
+        header: &SwbusMessageHeader,
+        is_response: bool,
+    ) -> Option<SwbusMessage> {
+        let destination = header.destination.as_ref()?;
+        let mut service = destination.clone();
+        service.resource_type.clear();
+        service.resource_id.clear();
+        let (error_code, error_message) = match service != *destination && routes.find(&service).is_some() {
+            true => (SwbusErrorCode::ResourceNotFound, "Resource not found"),
+            false => (SwbusErrorCode::ServiceNotFound, "Service not found"),
+        };
+        Self::error_response(
+            destination,
+            id_generator,
+            header,
+            is_response,
+            error_code,
+            error_message,
+        )
+    }
+
+    /// Build the error response from `responder` to the message with `header`, unless it is a response or its sender
+    /// doesn't want error responses.
+    fn error_response(
+        responder: &ServicePath,
+        id_generator: &MessageIdGenerator,
+        header: &SwbusMessageHeader,
+        is_response: bool,
+        error_code: SwbusErrorCode,
+        error_message: &str,
+    ) -> Option<SwbusMessage> {
+        let source = header.source.as_ref()?;
+        if is_response || header.has_flags(SwbusMessageFlags::NO_RESPONSE_ON_ERROR) {
+            debug!(%error_code, "Dropping message without response");
+            return None;
+        }
+        Some(SwbusMessage::new(
+            SwbusMessageHeader::new(responder.clone(), source.clone(), id_generator.generate()),
+            swbus_message::Body::Response(RequestResponse::infra_error(header.id, error_code, error_message)),
+        ))
+    }
+}
+
+/// Handlers of the local services, keyed by exact service paths or by service path patterns.
+#[derive(Default)]
+pub(crate) struct SwbusMessageRoutes {
+    exact: DashMap<ServicePath, SwbusMessageHandlerProxy>,
+    patterns: DashMap<ServicePathPattern, SwbusMessageHandlerProxy>,
+}
+
+impl SwbusMessageRoutes {
+    fn add(&self, pattern: ServicePathPattern, handler: SwbusMessageHandlerProxy) {
+        match pattern.as_exact() {
+            Some(svc_path) => {
+                self.exact.insert(svc_path, handler);
+            }
+            None => {
+                self.patterns.insert(pattern, handler);
+            }
+        }
+    }
+
+    /// Remove the handler of a pattern, unless it was replaced by another handler since. Returns whether it was
+    /// removed.
+    pub(crate) fn remove(&self, pattern: &ServicePathPattern, handler_id: u64) -> bool {
+        match pattern.as_exact() {
+            Some(svc_path) => self
+                .exact
+                .remove_if(&svc_path, |_, handler| handler.id() == handler_id)
+                .is_some(),
+            None => self
+                .patterns
+                .remove_if(pattern, |_, handler| handler.id() == handler_id)
+                .is_some(),
+        }
+    }
+
+    /// Find the handler of the destination. An exact route wins over patterns, otherwise the most specific
+    /// matching pattern is used.
+    pub(crate) fn find(&self, destination: &ServicePath) -> Option<SwbusMessageHandlerProxy> {
+        if let Some(handler) = self.exact.get(destination) {
+            return Some(handler.clone());
+        }
+        self.patterns
+            .iter()
+            .filter(|entry| entry.key().matches(destination))
+            .max_by(|a, b| a.key().cmp_specificity(b.key()))
+            .map(|entry| entry.value().clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::{channel, Receiver};
+
+    fn sp(s: &str) -> ServicePath {
+        ServicePath::from_string(s).unwrap()
+    }
+
+    fn add_route(routes: &SwbusMessageRoutes, pattern: &str) -> Receiver<SwbusMessage> {
+        let (tx, rx) = channel(4);
+        routes.add(
+            ServicePathPattern::from_string(pattern).unwrap(),
+            SwbusMessageHandlerProxy::new(tx),
+        );
+        rx
+    }
+
+    async fn route_to(routes: &SwbusMessageRoutes, destination: &str) -> bool {
+        let destination = sp(destination);
+        let message = SwbusMessage::new(
+            SwbusMessageHeader::new(sp("region-a.cluster-a.10.0.0.2-dpu0/testsvc/0"), destination.clone(), 1),
+            swbus_message::Body::PingRequest(PingRequest::new()),
+        );
+        match routes.find(&destination) {
+            Some(handler) => handler.send(message).await.is_ok(),
+            None => false,
+        }
+    }
+
+    #[tokio::test]
+    async fn message_is_routed_to_the_most_specific_handler() {
+        let routes = SwbusMessageRoutes::default();
+        let mut exact_rx = add_route(&routes, "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni0");
+        let mut family_rx = add_route(&routes, "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/*");
+        let mut service_rx = add_route(&routes, "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/**");
+
+        assert!(route_to(&routes, "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni0").await);
+        assert!(exact_rx.try_recv().is_ok());
+
+        assert!(route_to(&routes, "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni1").await);
+        assert!(family_rx.try_recv().is_ok());
+
+        assert!(route_to(&routes, "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0").await);
+        assert!(route_to(&routes, "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/haset/set0").await);
+        assert!(service_rx.try_recv().is_ok());
+        assert!(service_rx.try_recv().is_ok());
+
+        assert!(!route_to(&routes, "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/1").await);
+        assert!(exact_rx.try_recv().is_err());
+        assert!(family_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn registration_query_is_answered_with_local_services() {
+        let edge_sp = sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0");
+        let (core_tx, _core_rx) = channel(4);
+        let mut swbus_client = SwbusCoreClient::new(String::new(), edge_sp.clone(), core_tx);
+        swbus_client.register_svc(sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni1"));
+        swbus_client.register_svc(sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni0"));
+
+        let routes = SwbusMessageRoutes::default();
+        let mut cli_rx = add_route(&routes, "region-a.cluster-a.10.0.0.1-dpu0/cli/0");
+        let query = SwbusMessage::new(
+            SwbusMessageHeader::new(sp("region-a.cluster-a.10.0.0.1-dpu0/cli/0"), edge_sp.clone(), 7),
+            swbus_message::Body::RegistrationQueryRequest(RegistrationQueryRequest::default()),
+        );
+        SwbusMessageRouter::route_message(&mut swbus_client, &routes, &MessageIdGenerator::new(), query, false).await;
+
+        let response = match cli_rx.try_recv().unwrap().body.unwrap() {
+            swbus_message::Body::RegistrationQueryResponse(response) => response,
+            body => panic!("Expected registration query response, got {:?}", body),
+        };
+        assert_eq!(response.request_id, 7);
+        let services: Vec<String> = response
+            .entries
+            .iter()
+            .map(|entry| {
+                assert_eq!(entry.edge_service_path.as_ref(), Some(&edge_sp));
+                entry.service_path.as_ref().unwrap().to_longest_path()
+            })
+            .collect();
+        assert_eq!(
+            services,
+            vec![
+                "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni0",
+                "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni1"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn message_from_swbusd_without_handler_is_refused() {
+        let edge_sp = sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0");
+        let (core_tx, _core_rx) = channel(4);
+        let mut swbus_client = SwbusCoreClient::new(String::new(), edge_sp.clone(), core_tx);
+        let routes = SwbusMessageRoutes::default();
+        let mut cli_rx = add_route(&routes, "region-a.cluster-a.10.0.0.1-dpu0/cli/0");
+        let _hamgrd_rx = add_route(&routes, "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0");
+
+        let cases = [
+            (
+                "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni0",
+                SwbusErrorCode::ResourceNotFound,
+            ),
+            (
+                "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/1",
+                SwbusErrorCode::ServiceNotFound,
+            ),
+            (
+                "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/1/hascope/eni0",
+                SwbusErrorCode::ServiceNotFound,
+            ),
+        ];
+        for (id, (destination, error_code)) in cases.into_iter().enumerate() {
+            let message = SwbusMessage::new(
+                SwbusMessageHeader::new(sp("region-a.cluster-a.10.0.0.1-dpu0/cli/0"), sp(destination), id as u64),
+                swbus_message::Body::PingRequest(PingRequest::new()),
+            );
+            SwbusMessageRouter::route_message(&mut swbus_client, &routes, &MessageIdGenerator::new(), message, true)
+                .await;
+
+            let response = cli_rx.try_recv().unwrap();
+            assert_eq!(response.header.as_ref().unwrap().source, Some(sp(destination)));
+            match response.body.unwrap() {
+                swbus_message::Body::Response(response) => {
+                    assert_eq!(response.request_id, id as u64);
+                    assert_eq!(response.error_code, error_code as i32);
+                }
+                body => panic!("Expected response, got {:?}", body),
+            }
+        }
+
+        // responses are never answered, so two runtimes can't bounce them between each other
+        let response = SwbusMessage::new(
+            SwbusMessageHeader::new(
+                sp("region-a.cluster-a.10.0.0.1-dpu0/cli/0"),
+                sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/1"),
+                10,
+            ),
+            swbus_message::Body::Response(RequestResponse::ok(1)),
+        );
+        SwbusMessageRouter::route_message(&mut swbus_client, &routes, &MessageIdGenerator::new(), response, true).await;
+        assert!(cli_rx.try_recv().is_err());
+    }
+
+    fn local_message(destination: &str, ttl: u32, flags: SwbusMessageFlags, body: swbus_message::Body) -> SwbusMessage {
+        let mut header =
+            SwbusMessageHeader::new(sp("region-a.cluster-a.10.0.0.1-dpu0/cli/0"), sp(destination), 1).with_flags(flags);
+        header.ttl = ttl;
+        SwbusMessage::new(header, body)
+    }
+
+    fn error_code_of(message: SwbusMessage) -> SwbusErrorCode {
+        match message.body.unwrap() {
+            swbus_message::Body::Response(response) => SwbusErrorCode::try_from(response.error_code).unwrap(),
+            body => panic!("Expected response, got {:?}", body),
+        }
+    }
+
+    #[tokio::test]
+    async fn local_message_is_delivered_in_process_like_swbusd() {
+        let edge_sp = sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0");
+        let (core_tx, _core_rx) = channel(4);
+        let mut swbus_client = SwbusCoreClient::new(String::new(), edge_sp.clone(), core_tx);
+        let routes = SwbusMessageRoutes::default();
+        let mut cli_rx = add_route(&routes, "region-a.cluster-a.10.0.0.1-dpu0/cli/0");
+        let mut hamgrd_rx = add_route(&routes, "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni0");
+        let id_generator = MessageIdGenerator::new();
+        let destination = "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni0";
+        let ping = || swbus_message::Body::PingRequest(PingRequest::new());
+
+        // ping and traceroute requests reach the handler, one hop further
+        for body in [
+            ping(),
+            swbus_message::Body::TraceRouteRequest(TraceRouteRequest::new("trace")),
+        ] {
+            let message = local_message(destination, 64, SwbusMessageFlags::empty(), body);
+            SwbusMessageRouter::route_message(&mut swbus_client, &routes, &id_generator, message, false).await;
+            assert_eq!(hamgrd_rx.try_recv().unwrap().header.unwrap().ttl, 63);
+        }
+        assert!(cli_rx.try_recv().is_err());
+
+        // TTL running out
+        let message = local_message(destination, 1, SwbusMessageFlags::empty(), ping());
+        SwbusMessageRouter::route_message(&mut swbus_client, &routes, &id_generator, message, false).await;
+        let response = cli_rx.try_recv().unwrap();
+        assert_eq!(response.header.as_ref().unwrap().source, Some(edge_sp.clone()));
+        assert_eq!(error_code_of(response), SwbusErrorCode::Unreachable);
+
+        // expired deadline
+        let mut message = local_message(destination, 64, SwbusMessageFlags::empty(), ping());
+        message.header = message
+            .header
+            .map(|header| header.with_deadline(std::time::SystemTime::now()));
+        SwbusMessageRouter::route_message(&mut swbus_client, &routes, &id_generator, message, false).await;
+        assert_eq!(error_code_of(cli_rx.try_recv().unwrap()), SwbusErrorCode::Timeout);
+
+        // handler not receiving anymore, with and without error response
+        drop(hamgrd_rx);
+        let message = local_message(destination, 64, SwbusMessageFlags::NO_RESPONSE_ON_ERROR, ping());
+        SwbusMessageRouter::route_message(&mut swbus_client, &routes, &id_generator, message, false).await;
+        assert!(cli_rx.try_recv().is_err());
+        let message = local_message(destination, 64, SwbusMessageFlags::empty(), ping());
+        SwbusMessageRouter::route_message(&mut swbus_client, &routes, &id_generator, message, false).await;
+        assert_eq!(error_code_of(cli_rx.try_recv().unwrap()), SwbusErrorCode::NoRoute);
     }
 }
@@ -0,0 +1,580 @@
+use crate::edge_runtime::SwbusEdgeRuntime;
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use swbus_proto::message_flags::SwbusMessageFlags;
+use swbus_proto::message_id_generator::MessageIdGenerator;
+use swbus_proto::service_path_pattern::ServicePathPattern;
+use swbus_proto::swbus::swbus_message::Body;
+use swbus_proto::swbus::*;
+use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender};
+use tokio::sync::{watch, Notify};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+/// Time the `expect_*` helpers of [`MockSwbus`] wait for a message to be sent.
+pub const DEFAULT_EXPECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// An emulated swbusd, to unit test the services built on [`SwbusEdgeRuntime`] without running a `SwbusServiceHost`.
+///
+/// The mock owns a started runtime, whose messages to swbusd are recorded and routed the way swbusd would:
+/// - Registration requests update the services registered by the runtime, and are acknowledged on request.
+/// - Ping requests to swbusd itself, i.e. the `local-mgmt` service of the node, are answered with OK.
+/// - Messages to the runtime or to its registered services are delivered back to the runtime.
+/// - Messages to the service paths added with [`add_route`](Self::add_route) go to a remote endpoint, one hop away:
+///   their TTL is decremented, and ping and traceroute requests are answered on behalf of the endpoint. The other
+///   messages are left to the test, which answers them with [`inject`](Self::inject) if needed.
+/// - Other messages are answered with `NO_ROUTE`, unless they are responses or flagged `NO_RESPONSE_ON_ERROR`. The
+///   messages flagged `HOLD_IF_UNREACHABLE` are held instead, and routed once a matching route is added.
+///
+/// The messages other than registrations are also queued for the `expect_*` assertion helpers, in the order they are
+/// sent.
+pub struct MockSwbus {
+    runtime: Arc<SwbusEdgeRuntime>,
+    swbusd_sp: ServicePath,
+    incoming_tx: Sender<SwbusMessage>,
+    state: Arc<Mutex<MockSwbusState>>,
+    route_added: Arc<Notify>,
+    registered_rx: watch::Receiver<BTreeSet<ServicePath>>,
+    unchecked_rx: tokio::sync::Mutex<UnboundedReceiver<SwbusMessage>>,
+    task: JoinHandle<()>,
+}
+
+#[derive(Default)]
+struct MockSwbusState {
+    routes: Vec<ServicePathPattern>,
+    sent: Vec<SwbusMessage>,
+    /// Messages flagged `HOLD_IF_UNREACHABLE` waiting for a route to their destination.
+    held: Vec<SwbusMessage>,
+}
+
+impl MockSwbus {
+    /// Create a started runtime for `sp`, connected to an emulated swbusd of the same node. Must be called in a
+    /// tokio runtime.
+    pub fn new(sp: ServicePath) -> Self {
+        let mut runtime = SwbusEdgeRuntime::new(String::new(), sp.clone());
+        let (sent_rx, incoming_tx) = runtime.start_detached();
+        let (unchecked_tx, unchecked_rx) = mpsc::unbounded_channel();
+        let state = Arc::new(Mutex::new(MockSwbusState::default()));
+        let route_added = Arc::new(Notify::new());
+        let (registered_tx, registered_rx) = watch::channel(BTreeSet::new());
+        let swbusd = EmulatedSwbusd {
+            runtime_sp: sp.clone(),
+            swbusd_sp: sp.clone_for_local_mgmt(),
+            state: state.clone(),
+            route_added: route_added.clone(),
+            registered_tx,
+            id_generator: MessageIdGenerator::new(),
+            unchecked_tx,
+        };
+        let task = tokio::spawn(swbusd.run(sent_rx, incoming_tx.clone()));
+
+        Self {
+            runtime: Arc::new(runtime),
+            swbusd_sp: sp.clone_for_local_mgmt(),
+            incoming_tx,
+            state,
+            route_added,
+            registered_rx,
+            unchecked_rx: tokio::sync::Mutex::new(unchecked_rx),
+            task,
+        }
+    }
+
+    /// The runtime connected to the mock, to create the clients and services under test with.
+    pub fn runtime(&self) -> Arc<SwbusEdgeRuntime> {
+        self.runtime.clone()
+    }
+
+    /// The service path of the emulated swbusd, which answers pings and is the source of its error responses.
+    pub fn swbusd_sp(&self) -> &ServicePath {
+        &self.swbusd_sp
+    }
+
+    /// Make the service paths matching `pattern` reachable through the emulated swbusd. The messages held for them
+    /// are routed asynchronously, the same way swbusd releases its held messages when a connection is established.
+    pub fn add_route(&self, pattern: impl Into<ServicePathPattern>) {
+        self.state.lock().unwrap().routes.push(pattern.into());
+        self.route_added.notify_one();
+    }
+
+    /// Make the service paths matching `pattern` unreachable again, so messages to them get `NO_ROUTE`.
+    pub fn remove_route(&self, pattern: &ServicePathPattern) {
+        self.state.lock().unwrap().routes.retain(|route| route != pattern);
+    }
+
+    /// The services registered by the runtime so far, in order. The runtime registers the services of its handlers
+    /// asynchronously, see [`expect_registered`](Self::expect_registered) to wait for them.
+    pub fn registered_services(&self) -> Vec<ServicePath> {
+        self.registered_rx.borrow().iter().cloned().collect()
+    }
+
+    /// Expect `service_path` to be registered by the runtime.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the service isn't registered within [`DEFAULT_EXPECT_TIMEOUT`].
+    pub async fn expect_registered(&self, service_path: &ServicePath) {
+        self.expect_registration(service_path, true).await
+    }
+
+    /// Expect `service_path` to be unregistered by the runtime, e.g. once its handler is removed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the service is still registered after [`DEFAULT_EXPECT_TIMEOUT`].
+    pub async fn expect_unregistered(&self, service_path: &ServicePath) {
+        self.expect_registration(service_path, false).await
+    }
+
+    async fn expect_registration(&self, service_path: &ServicePath, registered: bool) {
+        let mut registered_rx = self.registered_rx.clone();
+        let wait = registered_rx.wait_for(|services| services.contains(service_path) == registered);
+        if !matches!(timeout(DEFAULT_EXPECT_TIMEOUT, wait).await, Ok(Ok(_))) {
+            panic!(
+                "{} is still {} after {:?}",
+                service_path,
+                if registered { "unregistered" } else { "registered" },
+                DEFAULT_EXPECT_TIMEOUT
+            );
+        }
+    }
+
+    /// Deliver `message` to the runtime, as if swbusd received it from another endpoint.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the runtime no longer routes messages.
+    pub async fn inject(&self, message: SwbusMessage) {
+        self.incoming_tx
+            .send(message)
+            .await
+            .expect("message router of the runtime is stopped");
+    }
+
+    /// All the messages sent to swbusd so far, registrations included.
+    pub fn sent(&self) -> Vec<SwbusMessage> {
+        self.state.lock().unwrap().sent.clone()
+    }
+
+    /// Wait up to `wait` for the next message sent to swbusd, skipping registrations.
+    pub async fn next_sent(&self, wait: Duration) -> Option<SwbusMessage> {
+        let mut unchecked_rx = self.unchecked_rx.lock().await;
+        timeout(wait, unchecked_rx.recv()).await.ok().flatten()
+    }
+
+    /// Expect the next message sent to swbusd, skipping registrations.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no message is sent within [`DEFAULT_EXPECT_TIMEOUT`].
+    pub async fn expect_sent(&self) -> SwbusMessage {
+        match self.next_sent(DEFAULT_EXPECT_TIMEOUT).await {
+            Some(message) => message,
+            None => panic!("No message sent to swbusd in {:?}", DEFAULT_EXPECT_TIMEOUT),
+        }
+    }
+
+    /// Expect the next message sent to swbusd to be for `destination`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no message is sent within [`DEFAULT_EXPECT_TIMEOUT`], or if it is for another destination.
+    pub async fn expect_sent_to(&self, destination: &ServicePath) -> SwbusMessage {
+        let message = self.expect_sent().await;
+        let header = message.header.as_ref().expect("missing message header");
+        assert_eq!(
+            header.destination.as_ref(),
+            Some(destination),
+            "Unexpected destination of {:?}",
+            message
+        );
+        message
+    }
+
+    /// Expect the next message sent to swbusd to be a data request for `destination`, and return its payload.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no message is sent within [`DEFAULT_EXPECT_TIMEOUT`], or if it is not a data request for
+    /// `destination`.
+    pub async fn expect_data_request(&self, destination: &ServicePath) -> Vec<u8> {
+        match self.expect_sent_to(destination).await.body {
+            Some(Body::DataRequest(request)) => request.payload,
+            body => panic!("Expected data request to {}, got {:?}", destination, body),
+        }
+    }
+
+    /// Expect the next message sent to swbusd to be the response to the request `request_id`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no message is sent within [`DEFAULT_EXPECT_TIMEOUT`], or if it is not the response to the request.
+    pub async fn expect_response(&self, request_id: u64) -> RequestResponse {
+        match self.expect_sent().await.body {
+            Some(Body::Response(response)) if response.request_id == request_id => response,
+            body => panic!("Expected response to request {}, got {:?}", request_id, body),
+        }
+    }
+
+    /// Assert that no message other than registrations is sent to swbusd within `wait`.
+    ///
+    /// # Panics
+    ///
+    /// Panics with the first message sent.
+    pub async fn assert_nothing_sent(&self, wait: Duration) {
+        if let Some(message) = self.next_sent(wait).await {
+            panic!("Unexpected message sent to swbusd: {:?}", message);
+        }
+    }
+}
+
+impl Drop for MockSwbus {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Routes the messages sent by the runtime of a [`MockSwbus`].
+struct EmulatedSwbusd {
+    runtime_sp: ServicePath,
+    swbusd_sp: ServicePath,
+    state: Arc<Mutex<MockSwbusState>>,
+    route_added: Arc<Notify>,
+    registered_tx: watch::Sender<BTreeSet<ServicePath>>,
+    id_generator: MessageIdGenerator,
+    unchecked_tx: UnboundedSender<SwbusMessage>,
+}
+
+impl EmulatedSwbusd {
+    async fn run(self, mut sent_rx: Receiver<SwbusMessage>, incoming_tx: Sender<SwbusMessage>) {
+        loop {
+            let delivered = tokio::select! {
+                message = sent_rx.recv() => {
+                    let Some(message) = message else {
+                        break;
+                    };
+                    self.state.lock().unwrap().sent.push(message.clone());
+                    self.route_message(message).into_iter().collect()
+                }
+                _ = self.route_added.notified() => self.release_held_messages(),
+            };
+            for message in delivered {
+                if incoming_tx.send(message).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Route a message sent by the runtime, and return the message to deliver back to it, if any.
+    fn route_message(&self, message: SwbusMessage) -> Option<SwbusMessage> {
+        let header = message.header.as_ref()?;
+        if header.source.is_none() || header.destination.is_none() {
+            return None;
+        }
+        if header.is_expired() {
+            return self.error_response(&message, SwbusErrorCode::Timeout, "Message expired");
+        }
+
+        if let Some(Body::RegistrationRequest(request)) = &message.body {
+            return match self.register(request) {
+                Ok(()) if message.has_flags(SwbusMessageFlags::ACK_REQUESTED) => {
+                    Some(self.response(&message, SwbusErrorCode::Ok, ""))
+                }
+                Ok(()) => None,
+                Err(error_message) => self.error_response(&message, SwbusErrorCode::InvalidArgs, &error_message),
+            };
+        }
+        let _ = self.unchecked_tx.send(message.clone());
+        self.forward_message(message)
+    }
+
+    /// Route the held messages whose destination is reachable now, and answer the expired ones. Return the messages
+    /// to deliver back to the runtime.
+    fn release_held_messages(&self) -> Vec<SwbusMessage> {
+        let released: Vec<SwbusMessage> = {
+            let mut state = self.state.lock().unwrap();
+            let held = std::mem::take(&mut state.held);
+            let (released, still_held) = held.into_iter().partition(|message| {
+                message.is_expired()
+                    || message
+                        .header
+                        .as_ref()
+                        .and_then(|header| header.destination.as_ref())
+                        .is_some_and(|destination| Self::has_route(&state, destination))
+            });
+            state.held = still_held;
+            released
+        };
+        released
+            .into_iter()
+            .filter_map(|message| match message.is_expired() {
+                true => self.error_response(&message, SwbusErrorCode::Timeout, "Message expired"),
+                false => self.forward_message(message),
+            })
+            .collect()
+    }
+
+    fn has_route(state: &MockSwbusState, destination: &ServicePath) -> bool {
+        state.routes.iter().any(|route| route.matches(destination))
+    }
+
+    /// Route a message that isn't a registration, and return the message to deliver back to the runtime, if any.
+    fn forward_message(&self, mut message: SwbusMessage) -> Option<SwbusMessage> {
+        let header = message.header.as_ref()?;
+        let (id, source, destination) = (header.id, header.source.clone()?, header.destination.clone()?);
+        if destination == self.swbusd_sp {
+            return match message.body {
+                Some(Body::PingRequest(_)) => Some(self.response(&message, SwbusErrorCode::Ok, "")),
+                _ => self.error_response(&message, SwbusErrorCode::ServiceNotFound, "Invalid message type"),
+            };
+        }
+        if destination == self.runtime_sp || self.registered_tx.borrow().contains(&destination) {
+            return Some(message);
+        }
+        let mut state = self.state.lock().unwrap();
+        if !Self::has_route(&state, &destination) {
+            // like swbusd, messages to group destinations are not held
+            if message.has_flags(SwbusMessageFlags::HOLD_IF_UNREACHABLE) && !destination.is_group() {
+                state.held.push(message);
+                return None;
+            }
+            drop(state);
+            return self.error_response(&message, SwbusErrorCode::NoRoute, "Route not found");
+        }
+        drop(state);
+
+        let header = message.header.as_mut().unwrap();
+        header.ttl = header.ttl.saturating_sub(1);
+        if header.ttl == 0 {
+            return self.error_response(&message, SwbusErrorCode::Unreachable, "TTL expired");
+        }
+        let mut response = match &message.body {
+            Some(Body::PingRequest(_)) => SwbusMessage::new(
+                SwbusMessageHeader::new(destination, source, self.id_generator.generate()),
+                Body::Response(RequestResponse::ok(id)),
+            ),
+            Some(Body::TraceRouteRequest(request)) => SwbusMessage::new(
+                SwbusMessageHeader::new(destination, source, self.id_generator.generate()),
+                Body::TraceRouteResponse(TraceRouteResponse::new(&request.trace_id)),
+            ),
+            _ => return None,
+        };
+        // the response comes back over the same hop
+        response.header.as_mut().unwrap().ttl -= 1;
+        Some(response)
+    }
+
+    fn register(&self, request: &RegistrationRequest) -> Result<(), String> {
+        let service_prefix = self.runtime_sp.to_service_prefix();
+        if let Some(service_path) = request
+            .register
+            .iter()
+            .chain(&request.unregister)
+            .find(|service_path| service_path.to_service_prefix() != service_prefix)
+        {
+            return Err(format!(
+                "{} is not under the service of the edge runtime {}",
+                service_path, service_prefix
+            ));
+        }
+
+        self.registered_tx.send_modify(|registered| {
+            if request.replace {
+                registered.clear();
+            }
+            for service_path in &request.unregister {
+                registered.remove(service_path);
+            }
+            registered.extend(request.register.iter().cloned());
+        });
+        Ok(())
+    }
+
+    /// Response of swbusd to `request`.
+    fn response(&self, request: &SwbusMessage, code: SwbusErrorCode, error_message: &str) -> SwbusMessage {
+        SwbusMessage::new_response(
+            request,
+            Some(&self.swbusd_sp),
+            code,
+            error_message,
+            self.id_generator.generate(),
+            None,
+        )
+    }
+
+    /// Error response of swbusd to `request`, unless it is a response or flagged `NO_RESPONSE_ON_ERROR`.
+    fn error_response(
+        &self,
+        request: &SwbusMessage,
+        code: SwbusErrorCode,
+        error_message: &str,
+    ) -> Option<SwbusMessage> {
+        if matches!(request.body, Some(Body::Response(_))) || request.has_flags(SwbusMessageFlags::NO_RESPONSE_ON_ERROR)
+        {
+            return None;
+        }
+        Some(self.response(request, code, error_message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_client::{MessageBody, OutgoingMessage, SimpleSwbusEdgeClient};
+
+    fn sp(s: &str) -> ServicePath {
+        ServicePath::from_string(s).unwrap()
+    }
+
+    fn message(source: &ServicePath, destination: &ServicePath, id: u64, body: Body) -> SwbusMessage {
+        SwbusMessage::new(SwbusMessageHeader::new(source.clone(), destination.clone(), id), body)
+    }
+
+    fn ping(source: &ServicePath, destination: &ServicePath, id: u64) -> SwbusMessage {
+        message(source, destination, id, Body::PingRequest(PingRequest::new()))
+    }
+
+    #[tokio::test]
+    async fn mock_routes_messages_like_swbusd() {
+        let mock = MockSwbus::new(sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0"));
+        let rt = mock.runtime();
+        let source = sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni0");
+        let remote = sp("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0/hascope/eni0");
+        let (handler_tx, mut handler_rx) = mpsc::channel(16);
+        let handle = rt.add_handler(source.clone(), handler_tx).await.unwrap();
+        mock.expect_registered(&source).await;
+        assert_eq!(mock.registered_services(), vec![source.clone()]);
+
+        // swbusd answers pings itself
+        rt.send(ping(&source, mock.swbusd_sp(), 1)).await.unwrap();
+        mock.expect_sent_to(mock.swbusd_sp()).await;
+        let response = handler_rx.recv().await.unwrap();
+        assert_eq!(response.header.unwrap().source.as_ref(), Some(mock.swbusd_sp()));
+        assert!(
+            matches!(response.body, Some(Body::Response(r)) if r.request_id == 1 && r.error_code() == SwbusErrorCode::Ok)
+        );
+
+        // the remote endpoint is unreachable until a route is added
+        rt.send(ping(&source, &remote, 2)).await.unwrap();
+        mock.expect_sent_to(&remote).await;
+        match handler_rx.recv().await.unwrap().body {
+            Some(Body::Response(response)) => {
+                assert_eq!(response.request_id, 2);
+                assert_eq!(response.error_code(), SwbusErrorCode::NoRoute);
+            }
+            body => panic!("Expected NO_ROUTE response, got {:?}", body),
+        }
+
+        mock.add_route(remote.clone());
+        let trace = message(
+            &source,
+            &remote,
+            3,
+            Body::TraceRouteRequest(TraceRouteRequest::new("trace-1")),
+        );
+        rt.send(trace).await.unwrap();
+        // the messages are recorded as sent, the response comes back over one hop
+        assert_eq!(mock.expect_sent_to(&remote).await.header.unwrap().ttl, 64);
+        let response = handler_rx.recv().await.unwrap();
+        let header = response.header.unwrap();
+        assert_eq!(header.source, Some(remote.clone()));
+        assert_eq!(header.ttl, 63);
+        assert!(matches!(response.body, Some(Body::TraceRouteResponse(r)) if r.trace_id == "trace-1"));
+
+        mock.remove_route(&remote.clone().into());
+        let mut silent = ping(&source, &remote, 4);
+        silent
+            .header
+            .as_mut()
+            .unwrap()
+            .set_flags(SwbusMessageFlags::NO_RESPONSE_ON_ERROR);
+        rt.send(silent).await.unwrap();
+        mock.expect_sent_to(&remote).await;
+
+        drop(handle);
+        mock.assert_nothing_sent(Duration::from_millis(50)).await;
+        assert!(handler_rx.try_recv().is_err());
+        mock.expect_unregistered(&source).await;
+        assert_eq!(mock.sent().len(), 6);
+    }
+
+    #[tokio::test]
+    async fn injected_messages_reach_clients() {
+        let mock = MockSwbus::new(sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0"));
+        let source = sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni0");
+        let remote = sp("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0/hascope/eni0");
+        mock.add_route(remote.clone());
+        let client = SimpleSwbusEdgeClient::new(mock.runtime(), source.clone())
+            .await
+            .unwrap();
+
+        // messages to services without handler are refused
+        let unknown = sp("region-a.cluster-a.10.0.0.1-dpu0/unknown/0");
+        mock.inject(ping(&remote, &unknown, 7)).await;
+        assert_eq!(
+            mock.expect_response(7).await.error_code(),
+            SwbusErrorCode::ServiceNotFound
+        );
+
+        // the client answers pings while it receives messages
+        mock.inject(ping(&remote, &source, 8)).await;
+        let response = tokio::select! {
+            message = client.recv() => panic!("Unexpected message {:?}", message),
+            response = mock.expect_response(8) => response,
+        };
+        assert_eq!(response.error_code(), SwbusErrorCode::Ok);
+
+        // the data requests to remote endpoints are left to the test
+        let id = client
+            .send(OutgoingMessage {
+                destination: remote.clone(),
+                flags: SwbusMessageFlags::empty(),
+                timeout: None,
+                body: MessageBody::Request(DataRequest::new(b"hello".to_vec())),
+            })
+            .await
+            .unwrap();
+        assert_eq!(mock.expect_data_request(&remote).await, b"hello");
+        mock.inject(message(&remote, &source, 9, Body::Response(RequestResponse::ok(id))))
+            .await;
+        match client.recv().await.unwrap().body {
+            MessageBody::Response(response) => assert_eq!(response.request_id, id),
+            body => panic!("Expected response, got {:?}", body),
+        }
+    }
+
+    #[tokio::test]
+    async fn held_messages_are_routed_once_the_route_is_added() {
+        let mock = MockSwbus::new(sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0"));
+        let rt = mock.runtime();
+        let source = sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni0");
+        let remote = sp("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0/hascope/eni0");
+        let (handler_tx, mut handler_rx) = mpsc::channel(16);
+        let _handle = rt.add_handler(source.clone(), handler_tx).await.unwrap();
+        mock.expect_registered(&source).await;
+
+        let mut held = ping(&source, &remote, 1);
+        held.header
+            .as_mut()
+            .unwrap()
+            .set_flags(SwbusMessageFlags::HOLD_IF_UNREACHABLE);
+        rt.send(held).await.unwrap();
+        mock.expect_sent_to(&remote).await;
+        assert!(timeout(Duration::from_millis(50), handler_rx.recv()).await.is_err());
+
+        // another route doesn't release the message
+        mock.add_route(sp("region-a.cluster-a.10.0.0.3-dpu0"));
+        assert!(timeout(Duration::from_millis(50), handler_rx.recv()).await.is_err());
+
+        mock.add_route(ServicePathPattern::from_string("region-a.cluster-a.*/**").unwrap());
+        let response = handler_rx.recv().await.unwrap();
+        assert_eq!(response.header.unwrap().source, Some(remote.clone()));
+        assert!(
+            matches!(response.body, Some(Body::Response(r)) if r.request_id == 1 && r.error_code() == SwbusErrorCode::Ok)
+        );
+        // the released message was already recorded when it was sent
+        mock.assert_nothing_sent(Duration::from_millis(50)).await;
+    }
+}
@@ -0,0 +1,617 @@
+use crate::simple_client::{IncomingMessage, MessageBody, OutgoingMessage, SimpleSwbusEdgeClient};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use swbus_proto::message_flags::SwbusMessageFlags;
+use swbus_proto::result::*;
+use swbus_proto::swbus::{DataRequest, ReliableAck, ReliableSequence, ServicePath, SwbusErrorCode};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+/// Default time to wait for the acknowledgement of a data request before retransmitting it. It doubles after each
+/// retransmission.
+pub const DEFAULT_RETRANSMIT_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Default maximum time between two retransmissions of a data request.
+pub const DEFAULT_MAX_RETRANSMIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default maximum number of data requests waiting for their acknowledgement, per destination.
+pub const DEFAULT_MAX_UNACKED: usize = 1024;
+
+/// Maximum number of missing requests reported in a single nack.
+const MAX_NACKED_SEQUENCES: usize = 64;
+
+/// How far ahead of the delivered requests the receiver holds the requests received out of order, per source. The
+/// requests further ahead are dropped, and delivered once retransmitted.
+const MAX_HELD_REQUESTS: u64 = 4096;
+
+/// Reliable channels over a [`SimpleSwbusEdgeClient`]: the data requests sent with [`send`](Self::send) are delivered
+/// at least once and in order to the [`ReliableSwbusEdgeClient`] of the destination.
+///
+/// Each request gets the next sequence number of the channel to its destination, and is retransmitted with backoff
+/// until the receiver acknowledges it. The receiver drops the duplicates, holds the requests received out of order
+/// until the missing ones are in, and nacks the missing ones so they are retransmitted right away.
+///
+/// The sequence numbers are scoped by a random session chosen when the client is created, so a restarted sender starts
+/// a new channel: the receiver starts over whenever the session of a source changes. Other messages are passed through
+/// unchanged.
+pub struct ReliableSwbusEdgeClient {
+    client: Arc<SimpleSwbusEdgeClient>,
+    state: Arc<Mutex<ReliableState>>,
+    retransmit_notify: Arc<Notify>,
+    retransmit_task: JoinHandle<()>,
+}
+
+struct ReliableState {
+    session: u64,
+    retransmit_timeout: Duration,
+    max_retransmit_timeout: Duration,
+    max_unacked: usize,
+    send_channels: HashMap<ServicePath, SendChannel>,
+    recv_channels: HashMap<ServicePath, RecvChannel>,
+    /// Messages ready to be returned by `recv`.
+    ready: VecDeque<IncomingMessage>,
+}
+
+#[derive(Default)]
+struct SendChannel {
+    next_sequence: u64,
+    unacked: BTreeMap<u64, Unacked>,
+}
+
+struct Unacked {
+    payload: Vec<u8>,
+    retransmit_at: Instant,
+    timeout: Duration,
+}
+
+#[derive(Default)]
+struct RecvChannel {
+    session: u64,
+    /// All requests up to this sequence number are delivered.
+    delivered: u64,
+    /// Requests received out of order, waiting for the missing ones.
+    held: BTreeMap<u64, IncomingMessage>,
+}
+
+impl ReliableSwbusEdgeClient {
+    pub fn new(client: Arc<SimpleSwbusEdgeClient>) -> Self {
+        let state = Arc::new(Mutex::new(ReliableState {
+            session: rand::random(),
+            retransmit_timeout: DEFAULT_RETRANSMIT_TIMEOUT,
+            max_retransmit_timeout: DEFAULT_MAX_RETRANSMIT_TIMEOUT,
+            max_unacked: DEFAULT_MAX_UNACKED,
+            send_channels: HashMap::new(),
+            recv_channels: HashMap::new(),
+            ready: VecDeque::new(),
+        }));
+        let retransmit_notify = Arc::new(Notify::new());
+        let retransmit_task = tokio::spawn(Self::run_retransmit_task(
+            client.clone(),
+            state.clone(),
+            retransmit_notify.clone(),
+        ));
+        Self {
+            client,
+            state,
+            retransmit_notify,
+            retransmit_task,
+        }
+    }
+
+    /// Set how long to wait for an acknowledgement before retransmitting. The timeout doubles after each
+    /// retransmission of a request, up to `max`. Defaults to [`DEFAULT_RETRANSMIT_TIMEOUT`] and
+    /// [`DEFAULT_MAX_RETRANSMIT_TIMEOUT`].
+    pub fn with_retransmit_timeout(self, initial: Duration, max: Duration) -> Self {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.retransmit_timeout = initial;
+            state.max_retransmit_timeout = max.max(initial);
+        }
+        self
+    }
+
+    /// Set how many requests can wait for their acknowledgement per destination. Defaults to [`DEFAULT_MAX_UNACKED`].
+    pub fn with_max_unacked(self, max_unacked: usize) -> Self {
+        self.state.lock().unwrap().max_unacked = max_unacked;
+        self
+    }
+
+    /// The underlying client, e.g. to send messages that don't need to be reliable.
+    pub fn client(&self) -> &Arc<SimpleSwbusEdgeClient> {
+        &self.client
+    }
+
+    /// Send a data request over the reliable channel to `destination`, and return its sequence number.
+    ///
+    /// The request is retransmitted until it is acknowledged, even if sending it fails now, e.g. because the
+    /// connection to swbusd is down. Fails with `QUEUE_FULL` if too many requests to `destination` wait for their
+    /// acknowledgement.
+    pub async fn send(&self, destination: ServicePath, payload: Vec<u8>) -> Result<u64> {
+        let request = {
+            let mut state = self.state.lock().unwrap();
+            let (timeout, max_unacked) = (state.retransmit_timeout, state.max_unacked);
+            let session = state.session;
+            let channel = state.send_channels.entry(destination.clone()).or_default();
+            if channel.unacked.len() >= max_unacked {
+                return Err(SwbusError::route(
+                    SwbusErrorCode::QueueFull,
+                    format!("Too many unacknowledged requests to {}", destination),
+                ));
+            }
+            channel.next_sequence += 1;
+            let sequence = channel.next_sequence;
+            channel.unacked.insert(
+                sequence,
+                Unacked {
+                    payload: payload.clone(),
+                    retransmit_at: Instant::now() + timeout,
+                    timeout,
+                },
+            );
+            reliable_request(destination, session, channel, sequence, payload)
+        };
+        let sequence = match &request.body {
+            MessageBody::Request(DataRequest {
+                reliable: Some(reliable),
+                ..
+            }) => reliable.sequence,
+            _ => unreachable!("reliable requests are data requests"),
+        };
+        self.retransmit_notify.notify_one();
+        if let Err(e) = self.client.send(request).await {
+            warn!(
+                "Failed to send reliable request {}, it will be retransmitted: {}",
+                sequence, e
+            );
+        }
+        Ok(sequence)
+    }
+
+    /// Receive a message. The data requests of the reliable channels are returned once, in the order they were sent.
+    ///
+    /// Acknowledgements are processed and sent while receiving, so the client must keep receiving for its channels to
+    /// make progress.
+    pub async fn recv(&self) -> Option<IncomingMessage> {
+        loop {
+            if let Some(msg) = self.state.lock().unwrap().ready.pop_front() {
+                return Some(msg);
+            }
+            let msg = self.client.recv().await?;
+            let replies = self.state.lock().unwrap().process(msg);
+            for reply in replies {
+                if let Err(e) = self.client.send(reply).await {
+                    warn!("Failed to send reliable channel message: {}", e);
+                }
+            }
+        }
+    }
+
+    async fn run_retransmit_task(
+        client: Arc<SimpleSwbusEdgeClient>,
+        state: Arc<Mutex<ReliableState>>,
+        retransmit_notify: Arc<Notify>,
+    ) {
+        loop {
+            let next_retransmit = state.lock().unwrap().next_retransmit();
+            let sleep_until = next_retransmit.unwrap_or_else(|| Instant::now() + Duration::from_secs(3600));
+            tokio::select! {
+                _ = retransmit_notify.notified() => continue,
+                _ = tokio::time::sleep_until(sleep_until) => {}
+            }
+            let requests = state.lock().unwrap().take_due(Instant::now());
+            for request in requests {
+                if let Err(e) = client.send(request).await {
+                    warn!("Failed to retransmit reliable request: {}", e);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for ReliableSwbusEdgeClient {
+    fn drop(&mut self) {
+        self.retransmit_task.abort();
+    }
+}
+
+impl ReliableState {
+    /// Process a received message, and return the messages to send in reply.
+    fn process(&mut self, msg: IncomingMessage) -> Vec<OutgoingMessage> {
+        match &msg.body {
+            MessageBody::Request(DataRequest {
+                reliable: Some(reliable),
+                ..
+            }) => {
+                let reliable = *reliable;
+                vec![self.receive_request(msg, reliable)]
+            }
+            MessageBody::ReliableAck(ack) => {
+                let ack = ack.clone();
+                self.receive_ack(&msg.source, ack)
+            }
+            _ => {
+                self.ready.push_back(msg);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Process a reliable request, and return the ack to send in reply, which nacks the missing requests.
+    fn receive_request(&mut self, msg: IncomingMessage, reliable: ReliableSequence) -> OutgoingMessage {
+        let source = msg.source.clone();
+        let channel = self.recv_channels.entry(source.clone()).or_default();
+        if reliable.session != channel.session {
+            // The sender restarted, or this is its first request. Sessions are random, so any change starts over.
+            debug!(
+                %source,
+                session = reliable.session,
+                "Starting new reliable session"
+            );
+            *channel = RecvChannel {
+                session: reliable.session,
+                ..Default::default()
+            };
+        }
+        // The requests before the base are acknowledged already, possibly by an earlier instance of this receiver.
+        if reliable.base_sequence > channel.delivered.saturating_add(1) {
+            channel.delivered = reliable.base_sequence - 1;
+            channel.held = channel.held.split_off(&reliable.base_sequence);
+        }
+
+        if reliable.sequence > channel.delivered && reliable.sequence - channel.delivered <= MAX_HELD_REQUESTS {
+            channel.held.entry(reliable.sequence).or_insert(msg);
+        }
+        while let Some(next) = channel.delivered.checked_add(1) {
+            let Some(msg) = channel.held.remove(&next) else {
+                break;
+            };
+            channel.delivered = next;
+            self.ready.push_back(msg);
+        }
+
+        let nacked_sequences = match channel.held.last_key_value() {
+            Some((&last, _)) => (channel.delivered.saturating_add(1)..last)
+                .filter(|sequence| !channel.held.contains_key(sequence))
+                .take(MAX_NACKED_SEQUENCES)
+                .collect(),
+            None => Vec::new(),
+        };
+        OutgoingMessage {
+            destination: source,
+            flags: SwbusMessageFlags::empty(),
+            timeout: None,
+            body: MessageBody::ReliableAck(ReliableAck {
+                session: channel.session,
+                acked_sequence: channel.delivered,
+                nacked_sequences,
+            }),
+        }
+    }
+
+    fn receive_ack(&mut self, source: &ServicePath, ack: ReliableAck) -> Vec<OutgoingMessage> {
+        if ack.session != self.session {
+            return Vec::new();
+        }
+        let session = self.session;
+        let Some(channel) = self.send_channels.get_mut(source) else {
+            return Vec::new();
+        };
+        channel.unacked = match ack.acked_sequence.checked_add(1) {
+            Some(first_unacked) => channel.unacked.split_off(&first_unacked),
+            None => BTreeMap::new(),
+        };
+
+        let now = Instant::now();
+        let mut retransmits = Vec::new();
+        for sequence in ack.nacked_sequences {
+            let Some(unacked) = channel.unacked.get_mut(&sequence) else {
+                continue;
+            };
+            unacked.retransmit_at = now + unacked.timeout;
+            let payload = unacked.payload.clone();
+            retransmits.push((sequence, payload));
+        }
+        retransmits
+            .into_iter()
+            .map(|(sequence, payload)| reliable_request(source.clone(), session, channel, sequence, payload))
+            .collect()
+    }
+
+    fn next_retransmit(&self) -> Option<Instant> {
+        self.send_channels
+            .values()
+            .flat_map(|channel| channel.unacked.values())
+            .map(|unacked| unacked.retransmit_at)
+            .min()
+    }
+
+    /// Take the requests due for retransmission, and back off their next retransmission.
+    fn take_due(&mut self, now: Instant) -> Vec<OutgoingMessage> {
+        let (session, max_timeout) = (self.session, self.max_retransmit_timeout);
+        let mut requests = Vec::new();
+        for (destination, channel) in self.send_channels.iter_mut() {
+            let mut due = Vec::new();
+            for (&sequence, unacked) in channel.unacked.iter_mut() {
+                if unacked.retransmit_at <= now {
+                    unacked.timeout = (unacked.timeout * 2).min(max_timeout);
+                    unacked.retransmit_at = now + unacked.timeout;
+                    due.push((sequence, unacked.payload.clone()));
+                }
+            }
+            for (sequence, payload) in due {
+                debug!(%destination, sequence, "Retransmitting reliable request");
+                requests.push(reliable_request(
+                    destination.clone(),
+                    session,
+                    channel,
+                    sequence,
+                    payload,
+                ));
+            }
+        }
+        requests
+    }
+}
+
+fn reliable_request(
+    destination: ServicePath,
+    session: u64,
+    channel: &SendChannel,
+    sequence: u64,
+    payload: Vec<u8>,
+) -> OutgoingMessage {
+    let base_sequence = channel.unacked.keys().next().copied().unwrap_or(sequence);
+    OutgoingMessage {
+        destination,
+        flags: SwbusMessageFlags::empty(),
+        timeout: None,
+        body: MessageBody::Request(DataRequest {
+            reliable: Some(ReliableSequence {
+                session,
+                sequence,
+                base_sequence,
+            }),
+            ..DataRequest::new(payload)
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use swbus_proto::swbus::SwbusMessage;
+    use tokio::sync::mpsc::{Receiver, Sender};
+
+    const SENDER: &str = "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0";
+    const RECEIVER: &str = "region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0";
+
+    fn sp(s: &str) -> ServicePath {
+        ServicePath::from_string(s).unwrap()
+    }
+
+    fn new_client(source: &str) -> (ReliableSwbusEdgeClient, Sender<SwbusMessage>, Receiver<SwbusMessage>) {
+        let (client, handler_tx, sent_rx) = SimpleSwbusEdgeClient::new_unconnected(sp(source));
+        let client = ReliableSwbusEdgeClient::new(Arc::new(client))
+            .with_retransmit_timeout(Duration::from_millis(100), Duration::from_millis(200));
+        (client, handler_tx, sent_rx)
+    }
+
+    fn sequence_of(message: &SwbusMessage) -> u64 {
+        match &message.body {
+            Some(swbus_proto::swbus::swbus_message::Body::DataRequest(DataRequest {
+                reliable: Some(reliable),
+                ..
+            })) => reliable.sequence,
+            body => panic!("Expected reliable data request, got {:?}", body),
+        }
+    }
+
+    /// Receive a message, or `None` if nothing is ready within a short time.
+    async fn try_recv(client: &ReliableSwbusEdgeClient) -> Option<IncomingMessage> {
+        tokio::time::timeout(Duration::from_millis(10), client.recv())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    fn payload_of(message: IncomingMessage) -> Vec<u8> {
+        match message.body {
+            MessageBody::Request(request) => request.payload,
+            body => panic!("Expected data request, got {:?}", body),
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_are_delivered_in_order_once() {
+        let (sender, sender_tx, mut sender_sent) = new_client(SENDER);
+        let (receiver, receiver_tx, mut receiver_sent) = new_client(RECEIVER);
+        for i in 1..=3u8 {
+            assert_eq!(sender.send(sp(RECEIVER), vec![i]).await.unwrap(), i as u64);
+        }
+        let sent: Vec<SwbusMessage> = (0..3).map(|_| sender_sent.try_recv().unwrap()).collect();
+
+        // 3 arrives first, twice. It is held until 1 and 2 are in, and the ack nacks them.
+        receiver_tx.send(sent[2].clone()).await.unwrap();
+        receiver_tx.send(sent[2].clone()).await.unwrap();
+        assert!(try_recv(&receiver).await.is_none());
+        let ack = receiver_sent.try_recv().unwrap();
+        match &ack.body {
+            Some(swbus_proto::swbus::swbus_message::Body::ReliableAck(ack)) => {
+                assert_eq!(ack.acked_sequence, 0);
+                assert_eq!(ack.nacked_sequences, vec![1, 2]);
+            }
+            body => panic!("Expected reliable ack, got {:?}", body),
+        }
+
+        // The nack makes the sender retransmit 1 and 2 right away.
+        sender_tx.send(ack).await.unwrap();
+        assert!(try_recv(&sender).await.is_none());
+        let retransmitted: Vec<u64> = (0..2).map(|_| sequence_of(&sender_sent.try_recv().unwrap())).collect();
+        assert_eq!(retransmitted, vec![1, 2]);
+
+        receiver_tx.send(sent[1].clone()).await.unwrap();
+        receiver_tx.send(sent[0].clone()).await.unwrap();
+        receiver_tx.send(sent[0].clone()).await.unwrap();
+        for i in 1..=3u8 {
+            assert_eq!(payload_of(try_recv(&receiver).await.unwrap()), vec![i]);
+        }
+        assert!(try_recv(&receiver).await.is_none());
+
+        // Once everything is acked, nothing is retransmitted.
+        while let Ok(ack) = receiver_sent.try_recv() {
+            sender_tx.send(ack).await.unwrap();
+        }
+        assert!(try_recv(&sender).await.is_none());
+        while sender_sent.try_recv().is_ok() {}
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert!(sender_sent.try_recv().is_err());
+        assert!(sender.state.lock().unwrap().send_channels[&sp(RECEIVER)]
+            .unacked
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn unacked_request_is_retransmitted_with_backoff() {
+        let (sender, _sender_tx, mut sender_sent) = new_client(SENDER);
+        sender.send(sp(RECEIVER), vec![1]).await.unwrap();
+        let first = sender_sent.recv().await.unwrap();
+
+        let start = Instant::now();
+        let retransmitted = sender_sent.recv().await.unwrap();
+        assert_eq!(sequence_of(&retransmitted), sequence_of(&first));
+        assert_ne!(
+            retransmitted.header.as_ref().unwrap().id,
+            first.header.as_ref().unwrap().id
+        );
+        let retransmitted = sender_sent.recv().await.unwrap();
+        assert_eq!(sequence_of(&retransmitted), 1);
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn too_many_unacked_requests_are_refused() {
+        let (sender, _sender_tx, _sender_sent) = new_client(SENDER);
+        let sender = sender.with_max_unacked(1);
+        sender.send(sp(RECEIVER), vec![1]).await.unwrap();
+        let err = sender.send(sp(RECEIVER), vec![2]).await.unwrap_err();
+        assert!(matches!(
+            err,
+            SwbusError::RouteError {
+                code: SwbusErrorCode::QueueFull,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn receivers_follow_new_sessions() {
+        let (sender, sender_tx, mut sender_sent) = new_client(SENDER);
+        let (old_receiver, old_receiver_tx, mut old_receiver_sent) = new_client(RECEIVER);
+        sender.send(sp(RECEIVER), vec![1]).await.unwrap();
+        let first = sender_sent.try_recv().unwrap();
+        old_receiver_tx.send(first.clone()).await.unwrap();
+        assert_eq!(payload_of(try_recv(&old_receiver).await.unwrap()), vec![1]);
+        sender_tx.send(old_receiver_sent.try_recv().unwrap()).await.unwrap();
+        assert!(try_recv(&sender).await.is_none());
+        drop(old_receiver);
+
+        // A restarted receiver starts from the lowest unacked request instead of waiting for the earlier ones.
+        let (receiver, receiver_tx, mut receiver_sent) = new_client(RECEIVER);
+        sender.send(sp(RECEIVER), vec![2]).await.unwrap();
+        receiver_tx.send(sender_sent.try_recv().unwrap()).await.unwrap();
+        assert_eq!(payload_of(try_recv(&receiver).await.unwrap()), vec![2]);
+
+        // A restarted sender starts a new random session, and the receiver starts over with it, even if the new
+        // session is smaller than the old one.
+        let (new_sender, _new_sender_tx, mut new_sender_sent) = new_client(SENDER);
+        let old_session = sender.state.lock().unwrap().session;
+        new_sender.state.lock().unwrap().session = old_session.wrapping_sub(1);
+        new_sender.send(sp(RECEIVER), vec![3]).await.unwrap();
+        receiver_tx.send(new_sender_sent.try_recv().unwrap()).await.unwrap();
+        assert_eq!(payload_of(try_recv(&receiver).await.unwrap()), vec![3]);
+        let ack = std::iter::from_fn(|| receiver_sent.try_recv().ok()).last().unwrap();
+        match &ack.body {
+            Some(swbus_proto::swbus::swbus_message::Body::ReliableAck(ack)) => {
+                assert_eq!(ack.session, old_session.wrapping_sub(1));
+                assert_eq!(ack.acked_sequence, 1);
+            }
+            body => panic!("Expected reliable ack, got {:?}", body),
+        }
+    }
+
+    fn reliable_message(session: u64, sequence: u64, base_sequence: u64) -> IncomingMessage {
+        IncomingMessage {
+            id: sequence,
+            source: sp(SENDER),
+            flags: SwbusMessageFlags::empty(),
+            body: MessageBody::Request(DataRequest {
+                reliable: Some(ReliableSequence {
+                    session,
+                    sequence,
+                    base_sequence,
+                }),
+                ..DataRequest::new(vec![])
+            }),
+        }
+    }
+
+    fn ack_of(reply: &[OutgoingMessage]) -> &ReliableAck {
+        match reply {
+            [OutgoingMessage {
+                body: MessageBody::ReliableAck(ack),
+                ..
+            }] => ack,
+            reply => panic!("Expected a reliable ack, got {:?}", reply.len()),
+        }
+    }
+
+    #[tokio::test]
+    async fn sequences_at_the_limit_do_not_overflow() {
+        let (receiver, _receiver_tx, _receiver_sent) = new_client(RECEIVER);
+        let mut state = receiver.state.lock().unwrap();
+
+        // the last sequence numbers are delivered
+        let reply = state.process(reliable_message(1, u64::MAX, u64::MAX - 1));
+        assert_eq!(ack_of(&reply).acked_sequence, u64::MAX - 2);
+        assert_eq!(ack_of(&reply).nacked_sequences, vec![u64::MAX - 1]);
+        let reply = state.process(reliable_message(1, u64::MAX - 1, u64::MAX - 1));
+        assert_eq!(ack_of(&reply).acked_sequence, u64::MAX);
+        assert_eq!(state.ready.len(), 2);
+        let reply = state.process(reliable_message(1, u64::MAX, u64::MAX));
+        assert_eq!(ack_of(&reply).acked_sequence, u64::MAX);
+        assert_eq!(state.ready.len(), 2);
+
+        // an ack of the last sequence number acknowledges everything
+        let session = state.session;
+        state.send_channels.entry(sp(SENDER)).or_default().unacked.insert(
+            1,
+            Unacked {
+                payload: vec![],
+                retransmit_at: Instant::now(),
+                timeout: Duration::ZERO,
+            },
+        );
+        let ack = ReliableAck {
+            session,
+            acked_sequence: u64::MAX,
+            nacked_sequences: vec![],
+        };
+        assert!(state.receive_ack(&sp(SENDER), ack).is_empty());
+        assert!(state.send_channels[&sp(SENDER)].unacked.is_empty());
+    }
+
+    #[tokio::test]
+    async fn requests_far_ahead_are_not_held() {
+        let (receiver, _receiver_tx, _receiver_sent) = new_client(RECEIVER);
+        let mut state = receiver.state.lock().unwrap();
+
+        let reply = state.process(reliable_message(1, MAX_HELD_REQUESTS + 1, 1));
+        assert_eq!(ack_of(&reply).acked_sequence, 0);
+        assert!(state.recv_channels[&sp(SENDER)].held.is_empty());
+        state.process(reliable_message(1, MAX_HELD_REQUESTS, 1));
+        assert_eq!(state.recv_channels[&sp(SENDER)].held.len(), 1);
+    }
+}
@@ -1,55 +1,327 @@
+use crate::edge_runtime::SwbusHandlerHandle;
+use crate::fragmentation::{
+    fragment_payload, Reassembler, DEFAULT_MAX_FRAGMENT_SIZE, DEFAULT_REASSEMBLY_TIMEOUT, MAX_FRAGMENTS,
+};
 use crate::SwbusEdgeRuntime;
+use futures_core::Stream;
+use futures_sink::Sink;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
 use swbus_proto::{
+    codec::PayloadCodec,
+    compression::{compress_payload, decompress_payload},
+    message_flags::SwbusMessageFlags,
     message_id_generator::MessageIdGenerator,
-    result::Result,
+    result::{Result, SwbusError},
     swbus::{
-        swbus_message::Body, DataRequest, RequestResponse, ServicePath, SwbusMessage, SwbusMessageHeader,
-        TraceRouteRequest, TraceRouteResponse,
+        swbus_message::Body, Compression, DataRequest, PublishRequest, ReliableAck, RequestResponse, ServicePath,
+        SubscribeRequest, SwbusErrorCode, SwbusMessage, SwbusMessageHeader, TraceRouteRequest, TraceRouteResponse,
+        UnsubscribeRequest,
     },
 };
 use tokio::sync::{
     mpsc::{channel, Receiver},
-    Mutex,
+    oneshot, Mutex, Notify,
 };
+use tracing::{debug, info, warn};
 
 /// The type used by Swbus for message ids. Alias for `u64`.
 pub type MessageId = u64;
 
+/// Maximum number of messages kept for [`SimpleSwbusEdgeClient::recv`] while [`SimpleSwbusEdgeClient::request`] waits
+/// for its response.
+pub const MAX_UNCLAIMED_MESSAGES: usize = crate::edge_runtime::SWBUS_RECV_QUEUE_SIZE;
+
 /// Simplified interface to [`SwbusEdgeRuntime`] that does not expose infra messages, message id
 /// generation, raw message construction, and other internal details to Swbus clients.
 pub struct SimpleSwbusEdgeClient {
     rt: Arc<SwbusEdgeRuntime>,
-    handler_rx: Mutex<Receiver<SwbusMessage>>,
+    // Removes the handler of the client from the runtime when the client is dropped.
+    _handler: SwbusHandlerHandle,
+    handler_rx: Mutex<ReceiveQueue>,
+    /// Messages received while waiting for the response to a request, to be returned by `recv`.
+    unclaimed: std::sync::Mutex<VecDeque<IncomingMessage>>,
+    /// Wakes `recv` when `request` adds an unclaimed message.
+    unclaimed_added: Notify,
+    /// Requests sent by `request`, waiting for their response.
+    pending_requests: std::sync::Mutex<HashMap<MessageId, oneshot::Sender<RequestResponse>>>,
     source: ServicePath,
     id_generator: MessageIdGenerator,
+    max_fragment_size: usize,
+    reassembler: std::sync::Mutex<Reassembler>,
+    payload_compression: Compression,
+    min_compressed_payload_size: usize,
 }
 
 impl SimpleSwbusEdgeClient {
-    /// Create and connect a new client.
-    pub async fn new(rt: Arc<SwbusEdgeRuntime>, source: ServicePath) -> Self {
+    /// Create a client receiving the messages sent to `source`. Fails if its handler can't be added to the runtime,
+    /// see [`SwbusEdgeRuntime::add_handler`].
+    pub async fn new(rt: Arc<SwbusEdgeRuntime>, source: ServicePath) -> Result<Self> {
         let (handler_tx, handler_rx) = channel::<SwbusMessage>(crate::edge_runtime::SWBUS_RECV_QUEUE_SIZE);
-        rt.add_handler(source.clone(), handler_tx)
-            .await
-            .expect("failed to add handler to SwbusEdgeRuntime");
-        Self {
+        let handler = rt.add_handler(source.clone(), handler_tx).await?;
+        Ok(Self {
             rt,
-            handler_rx: Mutex::new(handler_rx),
+            _handler: handler,
+            handler_rx: Mutex::new(ReceiveQueue::new(handler_rx)),
+            unclaimed: std::sync::Mutex::new(VecDeque::new()),
+            unclaimed_added: Notify::new(),
+            pending_requests: std::sync::Mutex::new(HashMap::new()),
             source,
             id_generator: MessageIdGenerator::new(),
-        }
+            max_fragment_size: DEFAULT_MAX_FRAGMENT_SIZE,
+            reassembler: std::sync::Mutex::new(Reassembler::new(DEFAULT_REASSEMBLY_TIMEOUT)),
+            payload_compression: Compression::None,
+            min_compressed_payload_size: 0,
+        })
+    }
+
+    /// Create a client that is not connected to swbusd. The messages it sends are handed to the returned receiver, and
+    /// the messages sent to the returned sender are received by it.
+    #[cfg(test)]
+    pub(crate) fn new_unconnected(
+        source: ServicePath,
+    ) -> (Self, tokio::sync::mpsc::Sender<SwbusMessage>, Receiver<SwbusMessage>) {
+        let (rt, sent_rx) = SwbusEdgeRuntime::new_with_sent_messages(source.clone());
+        let (handler_tx, handler_rx) = channel(16);
+        let (handler, _) = rt.insert_handler(source.clone().into(), handler_tx.clone());
+        let client = Self {
+            rt: Arc::new(rt),
+            _handler: handler,
+            handler_rx: Mutex::new(ReceiveQueue::new(handler_rx)),
+            unclaimed: std::sync::Mutex::new(VecDeque::new()),
+            unclaimed_added: Notify::new(),
+            pending_requests: std::sync::Mutex::new(HashMap::new()),
+            source,
+            id_generator: MessageIdGenerator::new(),
+            max_fragment_size: DEFAULT_MAX_FRAGMENT_SIZE,
+            reassembler: std::sync::Mutex::new(Reassembler::new(DEFAULT_REASSEMBLY_TIMEOUT)),
+            payload_compression: Compression::None,
+            min_compressed_payload_size: 0,
+        };
+        (client, handler_tx, sent_rx)
+    }
+
+    /// Set the maximum payload size of a single data request. Larger payloads are sent in fragments.
+    /// Defaults to [`DEFAULT_MAX_FRAGMENT_SIZE`].
+    pub fn with_max_fragment_size(mut self, max_fragment_size: usize) -> Self {
+        self.max_fragment_size = max_fragment_size.max(1);
+        self
+    }
+
+    /// Set how long to wait for all fragments of a data request before dropping it.
+    /// Defaults to [`DEFAULT_REASSEMBLY_TIMEOUT`].
+    pub fn with_reassembly_timeout(self, timeout: Duration) -> Self {
+        self.reassembler.lock().unwrap().set_timeout(timeout);
+        self
+    }
+
+    /// Compress the payload of the data requests of at least `min_size` bytes. The compression is marked in the
+    /// message header, and the receiving [`SimpleSwbusEdgeClient`] decompresses the payload before returning it.
+    /// Payloads that don't get smaller are sent uncompressed.
+    pub fn with_payload_compression(mut self, compression: Compression, min_size: usize) -> Self {
+        self.payload_compression = compression;
+        self.min_compressed_payload_size = min_size;
+        self
     }
 
     /// Receive a message.
     ///
-    /// Returns `None` when no more messages will ever be received.
+    /// Messages flagged with [`SwbusMessageFlags::HIGH_PRIORITY`] are received before the normal priority messages
+    /// already waiting. Messages flagged with [`SwbusMessageFlags::ACK_REQUESTED`] are acknowledged before they are
+    /// returned.
+    ///
+    /// Responses to the requests sent by [`request`](Self::request) are returned by `request` instead.
+    ///
+    /// Returns `None` when no more messages will ever be received. Cancelling the call doesn't lose any message.
     pub async fn recv(&self) -> Option<IncomingMessage> {
         loop {
-            let msg = self.handler_rx.lock().await.recv().await?;
-            match self.handle_received_message(msg) {
-                HandleReceivedMessage::PassToActor(msg) => break Some(msg),
-                HandleReceivedMessage::Respond(msg) => self.rt.send(msg).await.unwrap(),
-                HandleReceivedMessage::Ignore => {}
+            if let Some(msg) = self.unclaimed.lock().unwrap().pop_front() {
+                break Some(msg);
+            }
+            let msg = tokio::select! {
+                // A concurrent `request` may receive the next message meanwhile.
+                _ = self.unclaimed_added.notified() => continue,
+                msg = async { self.handler_rx.lock().await.recv().await } => msg?,
+            };
+            // The message is kept for the next call if this one is cancelled while the acknowledgement is sent.
+            let (msg, reply) = self.sort_received_message(msg);
+            if let Some(msg) = msg {
+                self.unclaimed.lock().unwrap().push_back(msg);
+            }
+            if let Some(reply) = reply {
+                self.send_reply(reply).await;
+            }
+        }
+    }
+
+    /// Receive a message like [`recv`](Self::recv), with the payload of the data requests decoded by codec `C`, e.g.
+    /// `client.recv_typed::<JsonCodec, HaScopeState>()`.
+    ///
+    /// Data requests that can't be decoded, including the ones encoded with another codec, are answered with an
+    /// `INVALID_PAYLOAD` error response, unless flagged with [`SwbusMessageFlags::NO_RESPONSE_ON_ERROR`], and skipped.
+    pub async fn recv_typed<C: PayloadCodec<T>, T>(&self) -> Option<TypedIncomingMessage<T>> {
+        loop {
+            let msg = self.recv().await?;
+            let MessageBody::Request(request) = &msg.body else {
+                return Some(TypedIncomingMessage::Other(msg));
+            };
+            match request.decode::<C, T>() {
+                Ok(value) => {
+                    return Some(TypedIncomingMessage::Request {
+                        id: msg.id,
+                        source: msg.source,
+                        flags: msg.flags,
+                        value,
+                    })
+                }
+                Err(e) if msg.flags.contains(SwbusMessageFlags::NO_RESPONSE_ON_ERROR) => {
+                    warn!("Failed to decode message {} from {}: {}", msg.id, msg.source, e);
+                }
+                Err(e) => {
+                    let response = SwbusMessage::new(
+                        SwbusMessageHeader::new(self.source.clone(), msg.source, self.id_generator.generate()),
+                        Body::Response(RequestResponse::from_error(msg.id, &e)),
+                    );
+                    if let Err(e) = self.rt.send(response).await {
+                        warn!("Failed to refuse message {}: {}", msg.id, e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Split the client into a [`Stream`] of the received messages and a [`Sink`] of the messages to send, e.g. to use
+    /// them with `StreamExt` and `SinkExt` combinators, or in different tasks.
+    ///
+    /// The stream owns the receive queue, so receiving doesn't take any lock. The sink can be cloned, and the clones
+    /// send concurrently without locking each other.
+    pub fn split(mut self) -> (SwbusEdgeSink, SwbusEdgeStream) {
+        let (_, closed_rx) = channel(1);
+        let queue = std::mem::replace(self.handler_rx.get_mut(), ReceiveQueue::new(closed_rx));
+        let unclaimed = std::mem::take(self.unclaimed.get_mut().unwrap());
+        let client = Arc::new(self);
+        let sink = SwbusEdgeSink {
+            client: client.clone(),
+            sending: None,
+        };
+        let stream = SwbusEdgeStream {
+            client,
+            queue,
+            unclaimed,
+            processing: None,
+        };
+        (sink, stream)
+    }
+
+    /// Handle a received message, and return it if it is for the actor.
+    async fn process_received_message(&self, msg: SwbusMessage) -> Option<IncomingMessage> {
+        let (msg, reply) = self.sort_received_message(msg);
+        if let Some(reply) = reply {
+            self.send_reply(reply).await;
+        }
+        msg
+    }
+
+    /// Handle a received message without waiting. Returns the message if it is for the actor, and the acknowledgement
+    /// or automatic response to send, if any.
+    fn sort_received_message(&self, msg: SwbusMessage) -> (Option<IncomingMessage>, Option<SwbusMessage>) {
+        if msg.has_flags(SwbusMessageFlags::TRACE) {
+            info!(
+                message_id = msg.header.as_ref().map(|header| header.id),
+                destination = %self.source,
+                "Received traced message"
+            );
+        }
+        match self.handle_received_message(msg) {
+            HandleReceivedMessage::PassToActor(msg) => (Some(msg), None),
+            HandleReceivedMessage::PassToActorWithAck(msg, ack) => (Some(msg), Some(*ack)),
+            HandleReceivedMessage::Respond(msg) => (None, Some(msg)),
+            HandleReceivedMessage::Ignore => (None, None),
+        }
+    }
+
+    /// Send an acknowledgement or automatic response. They are best effort, e.g. they can't be sent while swbusd is
+    /// unreachable.
+    async fn send_reply(&self, reply: SwbusMessage) {
+        let destination = reply.header.as_ref().and_then(|header| header.destination.clone());
+        if let Err(e) = self.rt.send(reply).await {
+            match destination {
+                Some(destination) => warn!("Failed to reply to {}: {}", destination, e),
+                None => warn!("Failed to reply: {}", e),
+            }
+        }
+    }
+
+    /// Send a data request and wait for its response.
+    ///
+    /// The receiver answers with a [`MessageBody::Response`] whose `request_id` is the id of the request, e.g. a
+    /// [`RequestResponse::ok_with_payload`]. Error responses, including the ones from swbusd like `NO_ROUTE`, are
+    /// returned as the [`SwbusError`] they carry. If no response is received within `timeout`, a `TIMEOUT` error is
+    /// returned, and the request expires in swbusd and at the receiver as well.
+    ///
+    /// Any number of requests can be outstanding at once. The messages other than the responses received meanwhile
+    /// are kept for [`recv`](Self::recv), up to [`MAX_UNCLAIMED_MESSAGES`]. Once that many are waiting, the requests
+    /// stop receiving until `recv` takes them, and their responses only come in through `recv`. Dropping the returned
+    /// future cancels the request, and its response is ignored if it arrives later.
+    pub async fn request(
+        &self,
+        destination: ServicePath,
+        payload: Vec<u8>,
+        timeout: Duration,
+    ) -> Result<RequestResponse> {
+        let id = self.id_generator.generate();
+        let (response_tx, mut response_rx) = oneshot::channel();
+        self.pending_requests.lock().unwrap().insert(id, response_tx);
+        let _pending = PendingRequest { client: self, id };
+
+        let msg = OutgoingMessage {
+            destination,
+            flags: SwbusMessageFlags::empty(),
+            timeout: Some(timeout),
+            body: MessageBody::Request(DataRequest::new(payload)),
+        };
+        self.send_with_id(id, msg).await?;
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let msg = tokio::select! {
+                response = &mut response_rx => {
+                    let response = response.expect("pending request is removed before it is answered");
+                    return match SwbusError::try_from(response) {
+                        Ok(e) => Err(e),
+                        Err(response) => Ok(response),
+                    };
+                }
+                _ = tokio::time::sleep_until(deadline) => {
+                    return Err(SwbusError::from_code(
+                        SwbusErrorCode::Timeout,
+                        format!("No response to request {} within {:?}", id, timeout),
+                    ));
+                }
+                // Nobody may be receiving, so receive the messages here until the response is in.
+                msg = async { self.handler_rx.lock().await.recv().await },
+                    if self.unclaimed.lock().unwrap().len() < MAX_UNCLAIMED_MESSAGES => msg,
+            };
+            let Some(msg) = msg else {
+                return Err(SwbusError::connection(
+                    SwbusErrorCode::ConnectionError,
+                    io::Error::new(io::ErrorKind::ConnectionAborted, "Handler channel is closed"),
+                ));
+            };
+            // The message is kept for `recv` before the acknowledgement is sent, so cancelling the request doesn't
+            // lose it.
+            let (msg, reply) = self.sort_received_message(msg);
+            if let Some(msg) = msg {
+                self.unclaimed.lock().unwrap().push_back(msg);
+                self.unclaimed_added.notify_one();
+            }
+            if let Some(reply) = reply {
+                self.send_reply(reply).await;
             }
         }
     }
@@ -57,21 +329,121 @@ impl SimpleSwbusEdgeClient {
     fn handle_received_message(&self, msg: SwbusMessage) -> HandleReceivedMessage {
         let header = msg.header.unwrap();
         let id = header.id;
+        let flags = header.flags();
+        let expired = header.is_expired();
+        let compression = header.payload_compression();
         let source = header.source.unwrap();
         let destination = header.destination.unwrap();
         let body = msg.body.unwrap();
 
-        match body {
-            Body::DataRequest(req) => HandleReceivedMessage::PassToActor(IncomingMessage {
-                id,
-                source,
-                body: MessageBody::Request(req),
-            }),
-            Body::Response(resp) => HandleReceivedMessage::PassToActor(IncomingMessage {
+        // Stale requests are refused, so a command isn't executed long after its sender gave up on it.
+        if expired
+            && matches!(
+                body,
+                Body::DataRequest(_) | Body::DataFragment(_) | Body::PublishRequest(_)
+            )
+        {
+            // a fragmented data request is refused as a whole, once
+            let request_id = match &body {
+                Body::DataFragment(fragment) => {
+                    let mut reassembler = self.reassembler.lock().unwrap();
+                    if !reassembler.refuse(&source, fragment.message_id, Instant::now()) {
+                        return HandleReceivedMessage::Ignore;
+                    }
+                    fragment.message_id
+                }
+                _ => id,
+            };
+            if flags.contains(SwbusMessageFlags::NO_RESPONSE_ON_ERROR) {
+                return HandleReceivedMessage::Ignore;
+            }
+            return HandleReceivedMessage::Respond(SwbusMessage::new(
+                SwbusMessageHeader::new(destination, source, self.id_generator.generate()),
+                Body::Response(RequestResponse::infra_error(
+                    request_id,
+                    SwbusErrorCode::Timeout,
+                    "Message deadline exceeded",
+                )),
+            ));
+        }
+
+        let pass_to_actor = |id: MessageId, body: MessageBody| {
+            let ack_requested = flags.contains(SwbusMessageFlags::ACK_REQUESTED)
+                && !matches!(body, MessageBody::Response(_) | MessageBody::ReliableAck(_));
+            let msg = IncomingMessage {
                 id,
-                source,
-                body: MessageBody::Response(resp),
-            }),
+                source: source.clone(),
+                flags,
+                body,
+            };
+            match ack_requested {
+                true => HandleReceivedMessage::PassToActorWithAck(
+                    msg,
+                    Box::new(SwbusMessage::new(
+                        SwbusMessageHeader::new(destination.clone(), source.clone(), self.id_generator.generate()),
+                        Body::Response(RequestResponse::ok(id)),
+                    )),
+                ),
+                false => HandleReceivedMessage::PassToActor(msg),
+            }
+        };
+
+        // `id` is the ID of the refused request, which is not the ID of the message carrying it for fragments.
+        let refuse = |id: MessageId, e: SwbusError| {
+            if flags.contains(SwbusMessageFlags::NO_RESPONSE_ON_ERROR) {
+                warn!("Failed to receive message {} from {}: {}", id, source, e);
+                return HandleReceivedMessage::Ignore;
+            }
+            HandleReceivedMessage::Respond(SwbusMessage::new(
+                SwbusMessageHeader::new(destination.clone(), source.clone(), self.id_generator.generate()),
+                Body::Response(RequestResponse::from_error(id, &e)),
+            ))
+        };
+
+        match body {
+            Body::DataRequest(req) => match decompress_payload(compression, &req.payload) {
+                Ok(payload) => pass_to_actor(
+                    id,
+                    MessageBody::Request(DataRequest {
+                        payload,
+                        reliable: req.reliable,
+                        content_type: req.content_type,
+                    }),
+                ),
+                Err(e) => refuse(id, e),
+            },
+            Body::DataFragment(fragment) => {
+                let message_id = fragment.message_id;
+                let (reliable, content_type) = (fragment.reliable, fragment.content_type);
+                let reassembled = self
+                    .reassembler
+                    .lock()
+                    .unwrap()
+                    .add(&source, fragment, Instant::now())
+                    .and_then(|payload| payload.map(|p| decompress_payload(compression, &p)).transpose());
+                match reassembled {
+                    Ok(Some(payload)) => {
+                        let request = DataRequest {
+                            payload,
+                            reliable,
+                            content_type,
+                        };
+                        pass_to_actor(message_id, MessageBody::Request(request))
+                    }
+                    Ok(None) => HandleReceivedMessage::Ignore,
+                    Err(e) => refuse(message_id, e),
+                }
+            }
+            Body::Response(resp) => match self.pending_requests.lock().unwrap().remove(&resp.request_id) {
+                Some(response_tx) => {
+                    // The request may have been cancelled meanwhile.
+                    let _ = response_tx.send(resp);
+                    HandleReceivedMessage::Ignore
+                }
+                None => pass_to_actor(id, MessageBody::Response(resp)),
+            },
+            Body::PublishRequest(req) => pass_to_actor(id, MessageBody::Publish(req)),
+            Body::ReliableAck(ack) => pass_to_actor(id, MessageBody::ReliableAck(ack)),
             Body::PingRequest(_) => HandleReceivedMessage::Respond(SwbusMessage::new(
                 SwbusMessageHeader::new(destination, source, self.id_generator.generate()),
                 Body::Response(RequestResponse::ok(id)),
@@ -87,8 +459,139 @@ impl SimpleSwbusEdgeClient {
     }
 
     /// Send a message.
+    ///
+    /// Data requests with a payload larger than the maximum fragment size are sent as multiple fragments, which the
+    /// receiving [`SimpleSwbusEdgeClient`] reassembles. The payload is compressed before it is fragmented, see
+    /// [`with_payload_compression`](Self::with_payload_compression).
     pub async fn send(&self, msg: OutgoingMessage) -> Result<MessageId> {
-        let (id, msg) = self.outgoing_message_to_swbus_message(msg);
+        self.send_with_id(self.id_generator.generate(), msg).await
+    }
+
+    async fn send_with_id(&self, id: MessageId, mut msg: OutgoingMessage) -> Result<MessageId> {
+        if msg.flags.contains(SwbusMessageFlags::TRACE) {
+            info!(
+                source = %self.source,
+                destination = %msg.destination,
+                "Sending traced message"
+            );
+        }
+        let compression = self.compress_outgoing_payload(&mut msg)?;
+        match &msg.body {
+            MessageBody::Request(req) if req.payload.len() > self.max_fragment_size => {
+                self.send_fragmented(id, msg, compression).await
+            }
+            _ => {
+                let mut msg = self.to_swbus_message(id, msg);
+                if let Some(header) = msg.header.as_mut() {
+                    header.set_payload_compression(compression);
+                }
+                self.rt.send(msg).await?;
+                Ok(id)
+            }
+        }
+    }
+
+    /// Compress the payload of a data request if configured, and return the compression to mark in the header.
+    fn compress_outgoing_payload(&self, msg: &mut OutgoingMessage) -> Result<Compression> {
+        match &mut msg.body {
+            MessageBody::Request(req)
+                if self.payload_compression != Compression::None
+                    && req.payload.len() >= self.min_compressed_payload_size =>
+            {
+                let compressed = compress_payload(self.payload_compression, &req.payload)?;
+                if compressed.len() >= req.payload.len() {
+                    return Ok(Compression::None);
+                }
+                req.payload = compressed;
+                Ok(self.payload_compression)
+            }
+            _ => Ok(Compression::None),
+        }
+    }
+
+    async fn send_fragmented(
+        &self,
+        id: MessageId,
+        msg: OutgoingMessage,
+        compression: Compression,
+    ) -> Result<MessageId> {
+        let MessageBody::Request(req) = msg.body else {
+            unreachable!("only data requests are fragmented");
+        };
+        let header = self
+            .new_header(msg.destination, msg.flags, msg.timeout, id)
+            .with_payload_compression(compression);
+        if req.payload.len().div_ceil(self.max_fragment_size) > MAX_FRAGMENTS as usize {
+            return Err(SwbusError::input(
+                SwbusErrorCode::InvalidArgs,
+                format!(
+                    "Payload of {} bytes needs more than {} fragments of {} bytes",
+                    req.payload.len(),
+                    MAX_FRAGMENTS,
+                    self.max_fragment_size
+                ),
+            ));
+        }
+        let fragments = fragment_payload(id, &req, self.max_fragment_size);
+        debug!(
+            message_id = id,
+            fragments = fragments.len(),
+            "Sending fragmented data request"
+        );
+        for fragment in fragments {
+            let header = SwbusMessageHeader {
+                id: self.id_generator.generate(),
+                ..header.clone()
+            };
+            self.rt
+                .send(SwbusMessage::new(header, Body::DataFragment(fragment)))
+                .await?;
+        }
+        Ok(id)
+    }
+
+    /// Send `value` encoded by codec `C` in a data request, e.g.
+    /// `client.send_typed::<JsonCodec, _>(destination, &state)`. The codec is marked in the content type of the
+    /// request, so the receiver can check it, see [`recv_typed`](Self::recv_typed).
+    pub async fn send_typed<C: PayloadCodec<T>, T>(&self, destination: ServicePath, value: &T) -> Result<MessageId> {
+        self.send(OutgoingMessage {
+            destination,
+            flags: SwbusMessageFlags::empty(),
+            timeout: None,
+            body: MessageBody::Request(DataRequest::encode::<C, T>(value)?),
+        })
+        .await
+    }
+
+    /// Subscribe to the messages published to `topic`, or by the publishers matching the `publisher`
+    /// [`ServicePathPattern`](swbus_proto::service_path_pattern::ServicePathPattern). An empty `topic` or `publisher`
+    /// matches everything, but not both.
+    ///
+    /// Swbusd answers with a [`MessageBody::Response`], and delivers the published messages as
+    /// [`MessageBody::Publish`].
+    pub async fn subscribe(&self, topic: &str, publisher: &str) -> Result<MessageId> {
+        self.send_to_local_mgmt(Body::SubscribeRequest(SubscribeRequest::new(topic, publisher)))
+            .await
+    }
+
+    /// Remove a subscription added by [`subscribe`](Self::subscribe).
+    pub async fn unsubscribe(&self, topic: &str, publisher: &str) -> Result<MessageId> {
+        self.send_to_local_mgmt(Body::UnsubscribeRequest(UnsubscribeRequest::new(topic, publisher)))
+            .await
+    }
+
+    /// Publish a message to all subscribers of `topic`.
+    pub async fn publish(&self, topic: &str, payload: Vec<u8>) -> Result<MessageId> {
+        self.send_to_local_mgmt(Body::PublishRequest(PublishRequest::new(topic, payload)))
+            .await
+    }
+
+    async fn send_to_local_mgmt(&self, body: Body) -> Result<MessageId> {
+        let id = self.id_generator.generate();
+        let msg = SwbusMessage::new(
+            SwbusMessageHeader::new(self.source.clone(), self.source.clone_for_local_mgmt(), id),
+            body,
+        );
         self.rt.send(msg).await?;
         Ok(id)
     }
@@ -104,21 +607,186 @@ impl SimpleSwbusEdgeClient {
     }
 
     /// Compile an [`OutgoingMessage`] into an [`SwbusMessage`] for use with [`send_raw`](Self::send_raw).
+    /// The payload is never fragmented.
     pub fn outgoing_message_to_swbus_message(&self, msg: OutgoingMessage) -> (MessageId, SwbusMessage) {
         let id = self.id_generator.generate();
-        let msg = SwbusMessage {
-            header: Some(SwbusMessageHeader::new(self.source.clone(), msg.destination, id)),
+        (id, self.to_swbus_message(id, msg))
+    }
+
+    fn to_swbus_message(&self, id: MessageId, msg: OutgoingMessage) -> SwbusMessage {
+        SwbusMessage {
+            header: Some(self.new_header(msg.destination, msg.flags, msg.timeout, id)),
             body: Some(match msg.body {
                 MessageBody::Request(req) => Body::DataRequest(req),
                 MessageBody::Response(resp) => Body::Response(resp),
+                MessageBody::Publish(req) => Body::PublishRequest(req),
+                MessageBody::ReliableAck(ack) => Body::ReliableAck(ack),
             }),
-        };
-        (id, msg)
+        }
+    }
+
+    fn new_header(
+        &self,
+        destination: ServicePath,
+        flags: SwbusMessageFlags,
+        timeout: Option<Duration>,
+        id: MessageId,
+    ) -> SwbusMessageHeader {
+        let header = SwbusMessageHeader::new(self.source.clone(), destination, id).with_flags(flags);
+        match timeout {
+            Some(timeout) => header.with_timeout(timeout),
+            None => header,
+        }
+    }
+}
+
+/// Removes a request from the pending requests once `request` returns or is cancelled.
+struct PendingRequest<'a> {
+    client: &'a SimpleSwbusEdgeClient,
+    id: MessageId,
+}
+
+impl Drop for PendingRequest<'_> {
+    fn drop(&mut self) {
+        self.client.pending_requests.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Queue of the messages received by a client, which lets high priority messages overtake the normal priority ones.
+struct ReceiveQueue {
+    rx: Receiver<SwbusMessage>,
+    pending: VecDeque<SwbusMessage>,
+}
+
+impl ReceiveQueue {
+    fn new(rx: Receiver<SwbusMessage>) -> Self {
+        Self {
+            rx,
+            pending: VecDeque::new(),
+        }
+    }
+
+    async fn recv(&mut self) -> Option<SwbusMessage> {
+        std::future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<SwbusMessage>> {
+        // Move the messages already waiting in the channel to the pending queue, so a high priority one can be picked.
+        // The pending queue is bounded by the channel size to keep the backpressure on the sender.
+        while self.pending.len() < crate::edge_runtime::SWBUS_RECV_QUEUE_SIZE {
+            match self.rx.try_recv() {
+                Ok(msg) => self.pending.push_back(msg),
+                Err(_) => break,
+            }
+        }
+        let high_priority = self
+            .pending
+            .iter()
+            .position(|msg| msg.has_flags(SwbusMessageFlags::HIGH_PRIORITY));
+        match high_priority {
+            Some(position) => Poll::Ready(self.pending.remove(position)),
+            None => match self.pending.pop_front() {
+                Some(msg) => Poll::Ready(Some(msg)),
+                None => self.rx.poll_recv(cx),
+            },
+        }
+    }
+}
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+/// The receiving half of a [`SimpleSwbusEdgeClient`], see [`SimpleSwbusEdgeClient::split`]. It yields the messages
+/// [`SimpleSwbusEdgeClient::recv`] would return, and ends when no more messages will ever be received.
+pub struct SwbusEdgeStream {
+    client: Arc<SimpleSwbusEdgeClient>,
+    queue: ReceiveQueue,
+    unclaimed: VecDeque<IncomingMessage>,
+    /// The received message being handled, e.g. while its acknowledgement is sent.
+    processing: Option<BoxFuture<Option<IncomingMessage>>>,
+}
+
+impl Stream for SwbusEdgeStream {
+    type Item = IncomingMessage;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<IncomingMessage>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(processing) = this.processing.as_mut() {
+                let msg = ready!(processing.as_mut().poll(cx));
+                this.processing = None;
+                match msg {
+                    Some(msg) => return Poll::Ready(Some(msg)),
+                    None => continue,
+                }
+            }
+            if let Some(msg) = this.unclaimed.pop_front() {
+                return Poll::Ready(Some(msg));
+            }
+            let Some(msg) = ready!(this.queue.poll_recv(cx)) else {
+                return Poll::Ready(None);
+            };
+            let client = this.client.clone();
+            this.processing = Some(Box::pin(async move { client.process_received_message(msg).await }));
+        }
+    }
+}
+
+/// The sending half of a [`SimpleSwbusEdgeClient`], see [`SimpleSwbusEdgeClient::split`]. Messages are sent one at a
+/// time like with [`SimpleSwbusEdgeClient::send`], including fragmentation and compression. Clones of the sink send
+/// independently.
+pub struct SwbusEdgeSink {
+    client: Arc<SimpleSwbusEdgeClient>,
+    /// The message being sent.
+    sending: Option<BoxFuture<Result<MessageId>>>,
+}
+
+impl SwbusEdgeSink {
+    fn poll_sent(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if let Some(sending) = self.sending.as_mut() {
+            let result = ready!(sending.as_mut().poll(cx));
+            self.sending = None;
+            result?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Clone for SwbusEdgeSink {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            sending: None,
+        }
+    }
+}
+
+impl Sink<OutgoingMessage> for SwbusEdgeSink {
+    type Error = SwbusError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_sent(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, msg: OutgoingMessage) -> Result<()> {
+        let this = self.get_mut();
+        let client = this.client.clone();
+        this.sending = Some(Box::pin(async move { client.send(msg).await }));
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_sent(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_sent(cx)
     }
 }
 
 enum HandleReceivedMessage {
     PassToActor(IncomingMessage),
+    /// Pass the message to the actor after sending the acknowledgement requested by its sender.
+    PassToActorWithAck(IncomingMessage, Box<SwbusMessage>),
     Respond(SwbusMessage),
     Ignore,
 }
@@ -128,6 +796,11 @@ enum HandleReceivedMessage {
 pub enum MessageBody {
     Request(DataRequest),
     Response(RequestResponse),
+    /// A message published to a topic the client subscribed to.
+    Publish(PublishRequest),
+    /// Acknowledgement of data requests sent over a reliable channel, see
+    /// [`ReliableSwbusEdgeClient`](crate::reliable::ReliableSwbusEdgeClient).
+    ReliableAck(ReliableAck),
 }
 
 /// A message received from another Swbus client.
@@ -135,12 +808,716 @@ pub enum MessageBody {
 pub struct IncomingMessage {
     pub id: MessageId,
     pub source: ServicePath,
+    pub flags: SwbusMessageFlags,
     pub body: MessageBody,
 }
 
+/// A message received by [`SimpleSwbusEdgeClient::recv_typed`].
+#[derive(Debug, Clone)]
+pub enum TypedIncomingMessage<T> {
+    /// A data request, with its payload decoded.
+    Request {
+        id: MessageId,
+        source: ServicePath,
+        flags: SwbusMessageFlags,
+        value: T,
+    },
+    /// Any other message, as returned by [`SimpleSwbusEdgeClient::recv`].
+    Other(IncomingMessage),
+}
+
 /// A message to send to another Swbus client.
 #[derive(Debug, Clone)]
 pub struct OutgoingMessage {
     pub destination: ServicePath,
+    /// Flags of the message, see [`SwbusMessageFlags`] for their behaviours.
+    pub flags: SwbusMessageFlags,
+    /// How long the message stays valid after it is sent. Swbusd and the receiving client drop it after that.
+    pub timeout: Option<Duration>,
     pub body: MessageBody,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+    use swbus_proto::swbus::{PingRequest, SwbusMessageHeader};
+    use tokio::sync::mpsc::Sender;
+
+    fn sp(s: &str) -> ServicePath {
+        ServicePath::from_string(s).unwrap()
+    }
+
+    fn new_client() -> (SimpleSwbusEdgeClient, Sender<SwbusMessage>, Receiver<SwbusMessage>) {
+        SimpleSwbusEdgeClient::new_unconnected(sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0"))
+    }
+
+    fn data_request(id: u64, flags: SwbusMessageFlags) -> SwbusMessage {
+        let header = SwbusMessageHeader::new(
+            sp("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0"),
+            sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0"),
+            id,
+        );
+        SwbusMessage::new(
+            header.with_flags(flags),
+            Body::DataRequest(DataRequest::new(id.to_be_bytes().to_vec())),
+        )
+    }
+
+    #[tokio::test]
+    async fn outgoing_message_carries_flags() {
+        let (client, _handler_tx, mut sent_rx) = new_client();
+        let flags = SwbusMessageFlags::TRACE | SwbusMessageFlags::HOLD_IF_UNREACHABLE;
+        let id = client
+            .send(OutgoingMessage {
+                destination: sp("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0"),
+                flags,
+                timeout: Some(Duration::from_secs(60)),
+                body: MessageBody::Request(DataRequest::new(vec![])),
+            })
+            .await
+            .unwrap();
+
+        let sent = sent_rx.try_recv().unwrap();
+        assert_eq!(sent.header.as_ref().unwrap().id, id);
+        assert_eq!(sent.flags(), flags);
+        let deadline = sent.header.as_ref().unwrap().deadline().unwrap();
+        assert!(deadline > SystemTime::now() + Duration::from_secs(50));
+    }
+
+    #[tokio::test]
+    async fn stale_request_is_refused() {
+        let (client, handler_tx, mut sent_rx) = new_client();
+        let with_deadline = |mut message: SwbusMessage, deadline: SystemTime| {
+            let header = message.header.take().unwrap().with_deadline(deadline);
+            message.header = Some(header);
+            message
+        };
+
+        let stale = with_deadline(
+            data_request(1, SwbusMessageFlags::empty()),
+            SystemTime::now() - Duration::from_millis(1),
+        );
+        let stale_silent = with_deadline(
+            data_request(2, SwbusMessageFlags::NO_RESPONSE_ON_ERROR),
+            SystemTime::now() - Duration::from_millis(1),
+        );
+        let fresh = with_deadline(
+            data_request(3, SwbusMessageFlags::empty()),
+            SystemTime::now() + Duration::from_secs(60),
+        );
+        handler_tx.send(stale).await.unwrap();
+        handler_tx.send(stale_silent).await.unwrap();
+        handler_tx.send(fresh).await.unwrap();
+
+        assert_eq!(client.recv().await.unwrap().id, 3);
+        let response = sent_rx.try_recv().unwrap();
+        match response.body.unwrap() {
+            Body::Response(response) => {
+                assert_eq!(response.request_id, 1);
+                assert_eq!(response.error_code, SwbusErrorCode::Timeout as i32);
+            }
+            body => panic!("Expected response, got {:?}", body),
+        }
+        assert!(sent_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn message_with_ack_requested_is_acknowledged() {
+        let (client, handler_tx, mut sent_rx) = new_client();
+
+        handler_tx
+            .send(data_request(1, SwbusMessageFlags::empty()))
+            .await
+            .unwrap();
+        let received = client.recv().await.unwrap();
+        assert_eq!(received.id, 1);
+        assert!(sent_rx.try_recv().is_err());
+
+        handler_tx
+            .send(data_request(2, SwbusMessageFlags::ACK_REQUESTED))
+            .await
+            .unwrap();
+        let received = client.recv().await.unwrap();
+        assert_eq!(received.id, 2);
+        assert_eq!(received.flags, SwbusMessageFlags::ACK_REQUESTED);
+
+        let ack = sent_rx.try_recv().unwrap();
+        assert_eq!(
+            ack.header.as_ref().unwrap().destination,
+            Some(sp("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0"))
+        );
+        assert_eq!(ack.flags(), SwbusMessageFlags::empty());
+        match ack.body.unwrap() {
+            Body::Response(response) => {
+                assert_eq!(response.request_id, 2);
+                assert_eq!(response.error_code, SwbusErrorCode::Ok as i32);
+            }
+            body => panic!("Expected response, got {:?}", body),
+        }
+    }
+
+    #[tokio::test]
+    async fn messages_are_received_when_acks_and_responses_cannot_be_sent() {
+        let (client, handler_tx, sent_rx) = new_client();
+        // the runtime can't send anything anymore, like while swbusd is unreachable
+        drop(sent_rx);
+
+        let ping = SwbusMessage::new(
+            SwbusMessageHeader::new(
+                sp("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0"),
+                sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0"),
+                1,
+            ),
+            Body::PingRequest(PingRequest::new()),
+        );
+        handler_tx.send(ping).await.unwrap();
+        handler_tx
+            .send(data_request(2, SwbusMessageFlags::ACK_REQUESTED))
+            .await
+            .unwrap();
+        assert_eq!(client.recv().await.unwrap().id, 2);
+    }
+
+    #[tokio::test]
+    async fn high_priority_message_is_received_first() {
+        let (client, handler_tx, _sent_rx) = new_client();
+
+        handler_tx
+            .send(data_request(1, SwbusMessageFlags::empty()))
+            .await
+            .unwrap();
+        handler_tx
+            .send(data_request(2, SwbusMessageFlags::empty()))
+            .await
+            .unwrap();
+        handler_tx
+            .send(data_request(3, SwbusMessageFlags::HIGH_PRIORITY))
+            .await
+            .unwrap();
+        handler_tx
+            .send(data_request(4, SwbusMessageFlags::empty()))
+            .await
+            .unwrap();
+
+        let mut ids = Vec::new();
+        for _ in 0..4 {
+            ids.push(client.recv().await.unwrap().id);
+        }
+        assert_eq!(ids, vec![3, 1, 2, 4]);
+    }
+
+    #[tokio::test]
+    async fn payload_needing_too_many_fragments_is_rejected() {
+        let (sender, _, mut sent_rx) = new_client();
+        let sender = sender.with_max_fragment_size(1);
+
+        let error = sender
+            .send(OutgoingMessage {
+                destination: sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0"),
+                flags: SwbusMessageFlags::empty(),
+                timeout: None,
+                body: MessageBody::Request(DataRequest::new(vec![0; MAX_FRAGMENTS as usize + 1])),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), SwbusErrorCode::InvalidArgs);
+        assert!(sent_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn large_payload_is_fragmented_and_reassembled() {
+        let (sender, _, mut sent_rx) = new_client();
+        let sender = sender.with_max_fragment_size(100);
+        let (receiver, handler_tx, mut ack_rx) = new_client();
+
+        let payload: Vec<u8> = (0..=255).cycle().take(250).collect();
+        let id = sender
+            .send(OutgoingMessage {
+                destination: sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0"),
+                flags: SwbusMessageFlags::ACK_REQUESTED,
+                timeout: None,
+                body: MessageBody::Request(DataRequest::new(payload.clone())),
+            })
+            .await
+            .unwrap();
+
+        let mut header_ids = Vec::new();
+        while let Ok(fragment) = sent_rx.try_recv() {
+            assert!(matches!(fragment.body, Some(Body::DataFragment(_))));
+            header_ids.push(fragment.header.as_ref().unwrap().id);
+            handler_tx.send(fragment).await.unwrap();
+        }
+        assert_eq!(header_ids.len(), 3);
+        assert!(!header_ids.contains(&id));
+
+        let received = receiver.recv().await.unwrap();
+        assert_eq!(received.id, id);
+        match received.body {
+            MessageBody::Request(req) => assert_eq!(req.payload, payload),
+            body => panic!("Expected request, got {:?}", body),
+        }
+
+        // a single ack for the reassembled request
+        let ack = ack_rx.try_recv().unwrap();
+        match ack.body.unwrap() {
+            Body::Response(response) => assert_eq!(response.request_id, id),
+            body => panic!("Expected response, got {:?}", body),
+        }
+        assert!(ack_rx.try_recv().is_err());
+
+        // small payloads are sent as is
+        sender
+            .send(OutgoingMessage {
+                destination: sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0"),
+                flags: SwbusMessageFlags::empty(),
+                timeout: None,
+                body: MessageBody::Request(DataRequest::new(vec![0; 100])),
+            })
+            .await
+            .unwrap();
+        assert!(matches!(sent_rx.try_recv().unwrap().body, Some(Body::DataRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn payload_is_compressed_and_decompressed() {
+        let (sender, _, mut sent_rx) = new_client();
+        let sender = sender
+            .with_payload_compression(Compression::Zstd, 100)
+            .with_max_fragment_size(100);
+        let (receiver, handler_tx, mut error_rx) = new_client();
+
+        let request = |payload: Vec<u8>| OutgoingMessage {
+            destination: sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0"),
+            flags: SwbusMessageFlags::empty(),
+            timeout: None,
+            body: MessageBody::Request(DataRequest::new(payload)),
+        };
+        let received_payload = |received: IncomingMessage| match received.body {
+            MessageBody::Request(req) => req.payload,
+            body => panic!("Expected request, got {:?}", body),
+        };
+
+        // compressed below the fragment size, so it is sent in a single message
+        let payload = b"ha scope state ".repeat(100);
+        sender.send(request(payload.clone())).await.unwrap();
+        let sent = sent_rx.try_recv().unwrap();
+        assert_eq!(sent.header.as_ref().unwrap().payload_compression(), Compression::Zstd);
+        match sent.body.as_ref().unwrap() {
+            Body::DataRequest(req) => assert!(req.payload.len() < 100),
+            body => panic!("Expected data request, got {:?}", body),
+        }
+        handler_tx.send(sent).await.unwrap();
+        assert_eq!(received_payload(receiver.recv().await.unwrap()), payload);
+
+        // payloads that are too small or don't compress are sent as is, and fragmented after compression
+        sender.send(request(vec![7; 10])).await.unwrap();
+        let mut seed = 0x2545f4914f6cdd1du64;
+        let noise: Vec<u8> = (0..1000)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                seed as u8
+            })
+            .collect();
+        sender.send(request(noise.clone())).await.unwrap();
+        let mut fragments = 0;
+        while let Ok(sent) = sent_rx.try_recv() {
+            assert_eq!(sent.header.as_ref().unwrap().payload_compression(), Compression::None);
+            fragments += matches!(sent.body, Some(Body::DataFragment(_))) as usize;
+            handler_tx.send(sent).await.unwrap();
+        }
+        assert!(fragments > 1);
+        assert_eq!(received_payload(receiver.recv().await.unwrap()), vec![7; 10]);
+        assert_eq!(received_payload(receiver.recv().await.unwrap()), noise);
+
+        // payloads that can't be decompressed are refused
+        let mut invalid = data_request(10, SwbusMessageFlags::empty());
+        invalid
+            .header
+            .as_mut()
+            .unwrap()
+            .set_payload_compression(Compression::Gzip);
+        handler_tx.send(invalid).await.unwrap();
+        handler_tx
+            .send(data_request(11, SwbusMessageFlags::empty()))
+            .await
+            .unwrap();
+        assert_eq!(receiver.recv().await.unwrap().id, 11);
+        match error_rx.try_recv().unwrap().body.unwrap() {
+            Body::Response(response) => {
+                assert_eq!(response.request_id, 10);
+                assert_eq!(response.error_code(), SwbusErrorCode::InvalidPayload);
+            }
+            body => panic!("Expected response, got {:?}", body),
+        }
+    }
+
+    fn response_to(request: &SwbusMessage, response: RequestResponse) -> SwbusMessage {
+        let header = request.header.as_ref().unwrap();
+        SwbusMessage::new(
+            SwbusMessageHeader::new(
+                header.destination.clone().unwrap(),
+                header.source.clone().unwrap(),
+                header.id + 1000,
+            ),
+            Body::Response(response),
+        )
+    }
+
+    #[tokio::test]
+    async fn request_gets_its_response() {
+        let (client, handler_tx, mut sent_rx) = new_client();
+        let destination = sp("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0");
+        let timeout = Duration::from_secs(5);
+
+        // two outstanding requests answered in reverse order, with another message received in between
+        let responder = async {
+            let first = sent_rx.recv().await.unwrap();
+            let second = sent_rx.recv().await.unwrap();
+            let second_id = second.header.as_ref().unwrap().id;
+            handler_tx
+                .send(response_to(
+                    &second,
+                    RequestResponse::ok_with_payload(second_id, b"second".to_vec()),
+                ))
+                .await
+                .unwrap();
+            handler_tx
+                .send(data_request(7, SwbusMessageFlags::empty()))
+                .await
+                .unwrap();
+            let first_id = first.header.as_ref().unwrap().id;
+            handler_tx
+                .send(response_to(
+                    &first,
+                    RequestResponse::infra_error(first_id, SwbusErrorCode::NoRoute, "Route not found"),
+                ))
+                .await
+                .unwrap();
+        };
+        let (first, second, _) = tokio::join!(
+            client.request(destination.clone(), b"first".to_vec(), timeout),
+            client.request(destination.clone(), b"second".to_vec(), timeout),
+            responder
+        );
+
+        let error = first.unwrap_err();
+        assert_eq!(error.code(), SwbusErrorCode::NoRoute);
+        assert_eq!(error.detail(), "Route not found");
+        assert_eq!(
+            second.unwrap().response_body,
+            Some(swbus_proto::swbus::request_response::ResponseBody::Payload(
+                b"second".to_vec()
+            ))
+        );
+        assert_eq!(client.recv().await.unwrap().id, 7);
+        assert!(client.pending_requests.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn fragmented_request_gets_the_error_refusing_it_once() {
+        let (client, client_handler_tx, mut client_sent_rx) = new_client();
+        let client = client.with_max_fragment_size(100);
+        let (peer, peer_handler_tx, mut peer_sent_rx) = new_client();
+        let destination = sp("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0");
+        let peer_responses = async |peer_sent_rx: &mut Receiver<SwbusMessage>| {
+            // the peer only refuses, so nothing is received
+            assert!(tokio::time::timeout(Duration::from_millis(100), peer.recv())
+                .await
+                .is_err());
+            let mut responses = Vec::new();
+            while let Ok(message) = peer_sent_rx.try_recv() {
+                responses.push(message);
+            }
+            responses
+        };
+
+        // the second fragment doesn't match the first one, and the third one is ignored
+        let responder = async {
+            for index in 0..3 {
+                let mut fragment = client_sent_rx.recv().await.unwrap();
+                if let (1, Some(Body::DataFragment(fragment))) = (index, fragment.body.as_mut()) {
+                    fragment.count += 1;
+                }
+                peer_handler_tx.send(fragment).await.unwrap();
+            }
+            let responses = peer_responses(&mut peer_sent_rx).await;
+            assert_eq!(responses.len(), 1);
+            client_handler_tx.send(responses[0].clone()).await.unwrap();
+        };
+        let (result, _) = tokio::join!(
+            client.request(destination.clone(), vec![0; 250], Duration::from_secs(5)),
+            responder
+        );
+        assert_eq!(result.unwrap_err().code(), SwbusErrorCode::InvalidPayload);
+
+        // expired fragments are refused once, for the request they carry
+        let id = client
+            .send(OutgoingMessage {
+                destination,
+                flags: SwbusMessageFlags::empty(),
+                timeout: None,
+                body: MessageBody::Request(DataRequest::new(vec![0; 250])),
+            })
+            .await
+            .unwrap();
+        while let Ok(mut fragment) = client_sent_rx.try_recv() {
+            let header = fragment.header.take().unwrap();
+            fragment.header = Some(header.with_deadline(SystemTime::now() - Duration::from_millis(1)));
+            peer_handler_tx.send(fragment).await.unwrap();
+        }
+        let responses = peer_responses(&mut peer_sent_rx).await;
+        assert_eq!(responses.len(), 1);
+        match responses[0].body.as_ref().unwrap() {
+            Body::Response(response) => {
+                assert_eq!(response.request_id, id);
+                assert_eq!(response.error_code, SwbusErrorCode::Timeout as i32);
+            }
+            body => panic!("Expected response, got {:?}", body),
+        }
+    }
+
+    #[tokio::test]
+    async fn recv_is_woken_when_request_receives_a_message() {
+        let (client, handler_tx, mut sent_rx) = new_client();
+        let destination = sp("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0");
+
+        // the request takes the receive queue first, so recv waits for it
+        let mut request = Box::pin(client.request(destination, vec![], Duration::from_secs(5)));
+        assert!(tokio::time::timeout(Duration::from_millis(10), &mut request)
+            .await
+            .is_err());
+        let recv = client.recv();
+        handler_tx
+            .send(data_request(7, SwbusMessageFlags::empty()))
+            .await
+            .unwrap();
+        let (received, _) = tokio::join!(tokio::time::timeout(Duration::from_secs(1), recv), async {
+            tokio::time::timeout(Duration::from_millis(100), &mut request).await
+        });
+        assert_eq!(received.unwrap().unwrap().id, 7);
+
+        let sent = sent_rx.recv().await.unwrap();
+        let id = sent.header.as_ref().unwrap().id;
+        handler_tx
+            .send(response_to(&sent, RequestResponse::ok(id)))
+            .await
+            .unwrap();
+        request.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn message_is_kept_when_request_is_cancelled_while_acknowledging_it() {
+        let (client, handler_tx, mut sent_rx) = new_client();
+        let destination = sp("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0");
+
+        let mut request = Box::pin(client.request(destination.clone(), vec![], Duration::from_secs(5)));
+        assert!(tokio::time::timeout(Duration::from_millis(10), &mut request)
+            .await
+            .is_err());
+
+        // the send queue is full, so the acknowledgement can't be sent and the request is cancelled meanwhile
+        let ping = SwbusMessage::new(
+            SwbusMessageHeader::new(sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0"), destination, 1),
+            Body::PingRequest(PingRequest::new()),
+        );
+        while tokio::time::timeout(Duration::from_millis(1), client.rt.send(ping.clone()))
+            .await
+            .is_ok()
+        {}
+        handler_tx
+            .send(data_request(7, SwbusMessageFlags::ACK_REQUESTED))
+            .await
+            .unwrap();
+        assert!(tokio::time::timeout(Duration::from_millis(50), &mut request)
+            .await
+            .is_err());
+        drop(request);
+
+        while sent_rx.try_recv().is_ok() {}
+        let received = tokio::time::timeout(Duration::from_secs(1), client.recv())
+            .await
+            .unwrap();
+        assert_eq!(received.unwrap().id, 7);
+    }
+
+    #[tokio::test]
+    async fn request_stops_receiving_when_too_many_messages_are_unclaimed() {
+        let (client, handler_tx, mut sent_rx) = new_client();
+        let unclaimed = IncomingMessage {
+            id: 1,
+            source: sp("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0"),
+            flags: SwbusMessageFlags::empty(),
+            body: MessageBody::Request(DataRequest::new(vec![])),
+        };
+        client
+            .unclaimed
+            .lock()
+            .unwrap()
+            .extend(std::iter::repeat_n(unclaimed, MAX_UNCLAIMED_MESSAGES));
+
+        // the response waits in the receive queue until recv takes the unclaimed messages
+        let request = client.request(
+            sp("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0"),
+            vec![],
+            Duration::from_secs(5),
+        );
+        let responder = async {
+            let sent = sent_rx.recv().await.unwrap();
+            let id = sent.header.as_ref().unwrap().id;
+            handler_tx
+                .send(response_to(&sent, RequestResponse::ok(id)))
+                .await
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            assert_eq!(client.unclaimed.lock().unwrap().len(), MAX_UNCLAIMED_MESSAGES);
+            for _ in 0..MAX_UNCLAIMED_MESSAGES {
+                client.recv().await;
+            }
+            // recv hands the response over to the request
+            let _ = tokio::time::timeout(Duration::from_millis(100), client.recv()).await;
+        };
+        let (response, _) = tokio::join!(request, tokio::time::timeout(Duration::from_secs(5), responder));
+        response.unwrap();
+    }
+
+    #[tokio::test]
+    async fn request_times_out() {
+        let (client, handler_tx, mut sent_rx) = new_client();
+        let error = client
+            .request(
+                sp("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0"),
+                vec![],
+                Duration::from_millis(50),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), SwbusErrorCode::Timeout);
+        assert!(client.pending_requests.lock().unwrap().is_empty());
+
+        // the request expires at the receiver as well, and a late response goes to the actor
+        let sent = sent_rx.try_recv().unwrap();
+        assert!(sent.header.as_ref().unwrap().deadline().is_some());
+        let id = sent.header.as_ref().unwrap().id;
+        handler_tx
+            .send(response_to(&sent, RequestResponse::ok(id)))
+            .await
+            .unwrap();
+        assert!(matches!(client.recv().await.unwrap().body, MessageBody::Response(_)));
+    }
+
+    #[tokio::test]
+    async fn typed_payload_is_decoded_or_refused() {
+        use swbus_proto::codec::{BincodeCodec, JsonCodec};
+
+        let (client, handler_tx, mut sent_rx) = new_client();
+        let client = client.with_max_fragment_size(8);
+        let destination = sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0");
+        let value = ("eni0".to_string(), 7u64);
+
+        // the content type survives fragmentation
+        client
+            .send_typed::<JsonCodec, _>(destination.clone(), &value)
+            .await
+            .unwrap();
+        while let Ok(sent) = sent_rx.try_recv() {
+            handler_tx.send(sent).await.unwrap();
+        }
+        match client.recv_typed::<JsonCodec, (String, u64)>().await.unwrap() {
+            TypedIncomingMessage::Request { value: received, .. } => assert_eq!(received, value),
+            msg => panic!("Expected typed request, got {:?}", msg),
+        }
+
+        // a payload of another codec is refused, and other messages are passed through
+        let id = client
+            .send_typed::<BincodeCodec, _>(destination.clone(), &value)
+            .await
+            .unwrap();
+        while let Ok(sent) = sent_rx.try_recv() {
+            handler_tx.send(sent).await.unwrap();
+        }
+        handler_tx
+            .send(SwbusMessage::new(
+                SwbusMessageHeader::new(destination.clone(), destination.clone(), 100),
+                Body::Response(RequestResponse::ok(1)),
+            ))
+            .await
+            .unwrap();
+        assert!(matches!(
+            client.recv_typed::<JsonCodec, (String, u64)>().await.unwrap(),
+            TypedIncomingMessage::Other(IncomingMessage {
+                body: MessageBody::Response(_),
+                ..
+            })
+        ));
+        match sent_rx.try_recv().unwrap().body {
+            Some(Body::Response(response)) => {
+                assert_eq!(response.request_id, id);
+                assert_eq!(response.error_code, SwbusErrorCode::InvalidPayload as i32);
+            }
+            body => panic!("Expected error response, got {:?}", body),
+        }
+    }
+
+    #[tokio::test]
+    async fn split_client_streams_and_sinks_messages() {
+        use futures_util::{SinkExt, StreamExt};
+
+        let (client, handler_tx, mut sent_rx) = new_client();
+        let (sink, mut stream) = client.split();
+
+        // clones of the sink send concurrently
+        let senders: Vec<_> = (0..4)
+            .map(|sender| {
+                let mut sink = sink.clone();
+                tokio::spawn(async move {
+                    for i in 0..10u8 {
+                        let msg = OutgoingMessage {
+                            destination: sp("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0"),
+                            flags: SwbusMessageFlags::empty(),
+                            timeout: None,
+                            body: MessageBody::Request(DataRequest::new(vec![sender, i])),
+                        };
+                        sink.send(msg).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        for sender in senders {
+            sender.await.unwrap();
+        }
+        let mut sent = Vec::new();
+        while let Ok(msg) = sent_rx.try_recv() {
+            sent.push(msg);
+        }
+        assert_eq!(sent.len(), 40);
+
+        // the stream acknowledges and yields the received messages, high priority first
+        handler_tx
+            .send(data_request(1, SwbusMessageFlags::ACK_REQUESTED))
+            .await
+            .unwrap();
+        handler_tx
+            .send(data_request(2, SwbusMessageFlags::HIGH_PRIORITY))
+            .await
+            .unwrap();
+        let ids: Vec<MessageId> = stream.by_ref().take(2).map(|msg| msg.id).collect().await;
+        assert_eq!(ids, vec![2, 1]);
+        match sent_rx.try_recv().unwrap().body {
+            Some(Body::Response(response)) => assert_eq!(response.request_id, 1),
+            body => panic!("Expected ack, got {:?}", body),
+        }
+
+        // the stream composes with select!
+        handler_tx
+            .send(data_request(3, SwbusMessageFlags::empty()))
+            .await
+            .unwrap();
+        tokio::select! {
+            msg = stream.next() => assert_eq!(msg.unwrap().id, 3),
+            _ = tokio::time::sleep(Duration::from_secs(1)) => panic!("No message received"),
+        }
+    }
+}
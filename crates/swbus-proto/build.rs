@@ -4,14 +4,40 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .enum_attribute("swbus.SwbusErrorCode", "#[derive(strum::Display)]")
         .enum_attribute("swbus.RouteScope", "#[derive(strum::Display)]")
         .enum_attribute("swbus.ConnectionType", "#[derive(strum::Display)]")
+        .enum_attribute("swbus.Compression", "#[derive(strum::Display)]")
+        .enum_attribute("swbus.ContentType", "#[derive(strum::Display)]")
         .message_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
         .enum_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
         .message_attribute("swbus.ServicePath", "#[derive(Eq, Hash, Ord, PartialOrd)]")
         .field_attribute("swbus.SwbusMessageHeader.id", "#[serde(default, skip_serializing)]")
+        .field_attribute("swbus.SwbusMessageHeader.deadline_ms", "#[serde(default)]")
+        .field_attribute("swbus.SwbusMessageHeader.payload_compression", "#[serde(default)]")
+        .field_attribute(
+            "swbus.RequestResponse.error_details",
+            "#[serde(default, skip_serializing_if = \"::std::collections::HashMap::is_empty\")]",
+        )
+        .field_attribute(
+            "swbus.DataRequest.reliable",
+            "#[serde(default, skip_serializing_if = \"Option::is_none\")]",
+        )
+        .field_attribute(
+            "swbus.DataFragment.reliable",
+            "#[serde(default, skip_serializing_if = \"Option::is_none\")]",
+        )
+        .field_attribute("swbus.DataRequest.content_type", "#[serde(default)]")
+        .field_attribute("swbus.DataFragment.content_type", "#[serde(default)]")
         .field_attribute(
             "swbus.RouteQueryResultEntry.nh_id",
             "#[serde(default, skip_serializing)]",
         )
+        .field_attribute(
+            "swbus.RegistrationEntry.conn_id",
+            "#[serde(default, skip_serializing)]",
+        )
+        .field_attribute(
+            "swbus.RegistrationQueryResponse.entries",
+            "#[serde(serialize_with = \"sorted_vec_serializer\")]",
+        )
         .field_attribute(
             "swbus.RouteQueryResult.entries",
             "#[serde(serialize_with = \"sorted_vec_serializer\")]",
@@ -24,6 +50,26 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             "swbus.SwbusMessageHeader.destination",
             "#[serde(serialize_with = \"serialize_service_path_opt\",deserialize_with = \"deserialize_service_path_opt\")]",
         )
+        .field_attribute(
+            "swbus.RegistrationEntry.service_path",
+            "#[serde(serialize_with = \"serialize_service_path_opt\",deserialize_with = \"deserialize_service_path_opt\")]",
+        )
+        .field_attribute(
+            "swbus.RegistrationEntry.edge_service_path",
+            "#[serde(serialize_with = \"serialize_service_path_opt\",deserialize_with = \"deserialize_service_path_opt\")]",
+        )
+        .field_attribute(
+            "swbus.RegistrationRequest.register",
+            "#[serde(default, serialize_with = \"serialize_service_path_vec\",deserialize_with = \"deserialize_service_path_vec\")]",
+        )
+        .field_attribute(
+            "swbus.RegistrationRequest.unregister",
+            "#[serde(default, serialize_with = \"serialize_service_path_vec\",deserialize_with = \"deserialize_service_path_vec\")]",
+        )
+        .field_attribute(
+            "swbus.RegistrationRequest.replace",
+            "#[serde(default)]",
+        )
         .field_attribute(
             "swbus.RouteQueryResultEntry.service_path",
             "#[serde(serialize_with = \"serialize_service_path_opt\",deserialize_with = \"deserialize_service_path_opt\")]",
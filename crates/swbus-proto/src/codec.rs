@@ -0,0 +1,157 @@
+use crate::result::*;
+use crate::swbus::{ContentType, DataRequest, SwbusErrorCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Encoding of typed values into data request payloads. The content type is carried in the data request, so the
+/// receiver can tell which codec the payload was encoded with.
+pub trait PayloadCodec<T> {
+    const CONTENT_TYPE: ContentType;
+
+    fn encode(value: &T) -> Result<Vec<u8>>;
+
+    fn decode(payload: &[u8]) -> Result<T>;
+}
+
+/// Encodes serde values as JSON.
+pub struct JsonCodec;
+
+impl<T: Serialize + DeserializeOwned> PayloadCodec<T> for JsonCodec {
+    const CONTENT_TYPE: ContentType = ContentType::Json;
+
+    fn encode(value: &T) -> Result<Vec<u8>> {
+        serde_json::to_vec(value).map_err(|e| encode_error(ContentType::Json, e))
+    }
+
+    fn decode(payload: &[u8]) -> Result<T> {
+        serde_json::from_slice(payload).map_err(|e| decode_error(ContentType::Json, e))
+    }
+}
+
+/// Encodes protobuf messages with prost.
+pub struct ProstCodec;
+
+impl<T: prost::Message + Default> PayloadCodec<T> for ProstCodec {
+    const CONTENT_TYPE: ContentType = ContentType::Protobuf;
+
+    fn encode(value: &T) -> Result<Vec<u8>> {
+        Ok(value.encode_to_vec())
+    }
+
+    fn decode(payload: &[u8]) -> Result<T> {
+        T::decode(payload).map_err(|e| decode_error(ContentType::Protobuf, e))
+    }
+}
+
+/// Encodes serde values with bincode, which is more compact and faster than JSON.
+pub struct BincodeCodec;
+
+impl<T: Serialize + DeserializeOwned> PayloadCodec<T> for BincodeCodec {
+    const CONTENT_TYPE: ContentType = ContentType::Bincode;
+
+    fn encode(value: &T) -> Result<Vec<u8>> {
+        bincode::serialize(value).map_err(|e| encode_error(ContentType::Bincode, e))
+    }
+
+    fn decode(payload: &[u8]) -> Result<T> {
+        bincode::deserialize(payload).map_err(|e| decode_error(ContentType::Bincode, e))
+    }
+}
+
+fn encode_error(content_type: ContentType, e: impl std::fmt::Display) -> SwbusError {
+    SwbusError::input(
+        SwbusErrorCode::InvalidArgs,
+        format!("Failed to encode payload as {}: {}", content_type, e),
+    )
+}
+
+fn decode_error(content_type: ContentType, e: impl std::fmt::Display) -> SwbusError {
+    SwbusError::input(
+        SwbusErrorCode::InvalidPayload,
+        format!("Failed to decode payload as {}: {}", content_type, e),
+    )
+}
+
+impl DataRequest {
+    /// Create a data request with `value` encoded by codec `C`.
+    pub fn encode<C: PayloadCodec<T>, T>(value: &T) -> Result<Self> {
+        Ok(DataRequest {
+            content_type: C::CONTENT_TYPE as i32,
+            ..DataRequest::new(C::encode(value)?)
+        })
+    }
+
+    /// Decode the payload with codec `C`. Payloads of another content type are refused, while payloads without
+    /// content type are decoded as is, as they come from senders that don't set it.
+    pub fn decode<C: PayloadCodec<T>, T>(&self) -> Result<T> {
+        let content_type = self.content_type();
+        if content_type != ContentType::Unspecified && content_type != C::CONTENT_TYPE {
+            return Err(SwbusError::input(
+                SwbusErrorCode::InvalidPayload,
+                format!("Expected {} payload, got {}", C::CONTENT_TYPE, content_type),
+            ));
+        }
+        C::decode(&self.payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::swbus::ServicePath;
+    use pretty_assertions::assert_eq;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct HaScopeState {
+        name: String,
+        term: u64,
+        active: bool,
+    }
+
+    fn state() -> HaScopeState {
+        HaScopeState {
+            name: "eni0".to_string(),
+            term: 7,
+            active: true,
+        }
+    }
+
+    #[test]
+    fn serde_values_can_be_encoded_and_decoded() {
+        let request = DataRequest::encode::<JsonCodec, _>(&state()).unwrap();
+        assert_eq!(request.content_type(), ContentType::Json);
+        assert_eq!(request.decode::<JsonCodec, HaScopeState>().unwrap(), state());
+
+        let request = DataRequest::encode::<BincodeCodec, _>(&state()).unwrap();
+        assert_eq!(request.content_type(), ContentType::Bincode);
+        assert_eq!(request.decode::<BincodeCodec, HaScopeState>().unwrap(), state());
+    }
+
+    #[test]
+    fn protobuf_messages_can_be_encoded_and_decoded() {
+        let sp = ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0").unwrap();
+        let request = DataRequest::encode::<ProstCodec, _>(&sp).unwrap();
+        assert_eq!(request.content_type(), ContentType::Protobuf);
+        assert_eq!(request.decode::<ProstCodec, ServicePath>().unwrap(), sp);
+    }
+
+    #[test]
+    fn mismatched_or_invalid_payload_is_refused() {
+        let request = DataRequest::encode::<JsonCodec, _>(&state()).unwrap();
+        let error = request.decode::<BincodeCodec, HaScopeState>().unwrap_err();
+        assert_eq!(error.code(), SwbusErrorCode::InvalidPayload);
+
+        let error = DataRequest::new(b"{}".to_vec())
+            .decode::<JsonCodec, HaScopeState>()
+            .unwrap_err();
+        assert_eq!(error.code(), SwbusErrorCode::InvalidPayload);
+
+        // payloads without content type are decoded as is
+        let payload = serde_json::to_vec(&state()).unwrap();
+        assert_eq!(
+            DataRequest::new(payload).decode::<JsonCodec, HaScopeState>().unwrap(),
+            state()
+        );
+    }
+}
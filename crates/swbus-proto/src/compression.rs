@@ -0,0 +1,108 @@
+use crate::result::*;
+use crate::swbus::{Compression, SwbusErrorCode, SwbusMessageHeader};
+use flate2::read::{GzDecoder, GzEncoder};
+use std::io::Read;
+
+/// Maximum size of a decompressed payload, to protect the receiver from compression bombs.
+pub const MAX_DECOMPRESSED_PAYLOAD_SIZE: usize = 256 * 1024 * 1024;
+
+/// Compress a payload with the given algorithm. `Compression::None` returns the payload as is.
+pub fn compress_payload(compression: Compression, payload: &[u8]) -> Result<Vec<u8>> {
+    let mut compressed = Vec::new();
+    match compression {
+        Compression::None => return Ok(payload.to_vec()),
+        Compression::Gzip => GzEncoder::new(payload, flate2::Compression::default())
+            .read_to_end(&mut compressed)
+            .map(|_| ()),
+        Compression::Zstd => zstd::stream::copy_encode(payload, &mut compressed, zstd::DEFAULT_COMPRESSION_LEVEL),
+    }
+    .map_err(|e| {
+        SwbusError::internal(
+            SwbusErrorCode::Fail,
+            format!("Failed to compress payload with {}: {}", compression, e),
+        )
+    })?;
+    Ok(compressed)
+}
+
+/// Decompress a payload compressed by [`compress_payload`].
+pub fn decompress_payload(compression: Compression, payload: &[u8]) -> Result<Vec<u8>> {
+    let reader: Box<dyn Read> = match compression {
+        Compression::None => return Ok(payload.to_vec()),
+        Compression::Gzip => Box::new(GzDecoder::new(payload)),
+        Compression::Zstd => {
+            Box::new(zstd::stream::read::Decoder::new(payload).map_err(|e| invalid_payload(compression, e))?)
+        }
+    };
+
+    // Read one byte more than the limit to tell a payload at the limit from a larger one.
+    let mut decompressed = Vec::new();
+    reader
+        .take(MAX_DECOMPRESSED_PAYLOAD_SIZE as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|e| invalid_payload(compression, e))?;
+    if decompressed.len() > MAX_DECOMPRESSED_PAYLOAD_SIZE {
+        return Err(SwbusError::input(
+            SwbusErrorCode::InvalidPayload,
+            format!(
+                "Decompressed payload is larger than {} bytes",
+                MAX_DECOMPRESSED_PAYLOAD_SIZE
+            ),
+        ));
+    }
+    Ok(decompressed)
+}
+
+fn invalid_payload(compression: Compression, e: std::io::Error) -> SwbusError {
+    SwbusError::input(
+        SwbusErrorCode::InvalidPayload,
+        format!("Failed to decompress payload with {}: {}", compression, e),
+    )
+}
+
+impl SwbusMessageHeader {
+    /// Mark the payload of the message as compressed with `compression`.
+    pub fn with_payload_compression(mut self, compression: Compression) -> Self {
+        self.set_payload_compression(compression);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::swbus::ServicePath;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn payload_can_be_compressed_and_decompressed() {
+        let payload = b"ha scope state ".repeat(1000);
+        for compression in [Compression::None, Compression::Gzip, Compression::Zstd] {
+            let compressed = compress_payload(compression, &payload).unwrap();
+            if compression != Compression::None {
+                assert!(compressed.len() < payload.len() / 10, "{} didn't compress", compression);
+            }
+            assert_eq!(decompress_payload(compression, &compressed).unwrap(), payload);
+        }
+    }
+
+    #[test]
+    fn invalid_compressed_payload_is_rejected() {
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let error = decompress_payload(compression, b"not compressed").unwrap_err();
+            assert_eq!(error.code(), SwbusErrorCode::InvalidPayload);
+        }
+    }
+
+    #[test]
+    fn payload_compression_is_marked_in_header() {
+        let header = SwbusMessageHeader::new(
+            ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0").unwrap(),
+            ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0").unwrap(),
+            1,
+        );
+        assert_eq!(header.payload_compression(), Compression::None);
+        let header = header.with_payload_compression(Compression::Zstd);
+        assert_eq!(header.payload_compression(), Compression::Zstd);
+    }
+}
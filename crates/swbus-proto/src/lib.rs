@@ -1,3 +1,8 @@
+pub mod codec;
+pub mod compression;
+pub mod message_flags;
 pub mod message_id_generator;
+pub mod protocol;
 pub mod result;
+pub mod service_path_pattern;
 pub mod swbus;
@@ -0,0 +1,93 @@
+use crate::swbus::{SwbusMessage, SwbusMessageHeader};
+use bitflags::bitflags;
+
+bitflags! {
+    /// Flags carried in [`SwbusMessageHeader::flag`]. Responses generated by swbusd or the edge never inherit the
+    /// flags of the request.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct SwbusMessageFlags: u32 {
+        /// Swbusd drops the message silently instead of answering with an error response, e.g. NO_ROUTE or TTL
+        /// expired.
+        const NO_RESPONSE_ON_ERROR = 1 << 0;
+        /// The receiver acknowledges the message with an OK response once it is delivered. Requests that already
+        /// get a response from swbusd, such as ping, are not acknowledged twice.
+        const ACK_REQUESTED = 1 << 1;
+        /// Every swbusd on the way and the edge clients log the message at info level.
+        const TRACE = 1 << 2;
+        /// Swbusd holds the message instead of answering NO_ROUTE when it has no route to the destination, and
+        /// forwards it once a route shows up.
+        const HOLD_IF_UNREACHABLE = 1 << 3;
+        /// Swbusd keeps part of the send queue of every connection for high priority messages, so they still get
+        /// through when normal priority messages are refused for a congested connection. The edge client receives the
+        /// message before the normal priority messages waiting in its queue. Swbusd never waits for room in a full
+        /// send queue, so the message is dropped like any other once the reserved room is used up too.
+        const HIGH_PRIORITY = 1 << 4;
+    }
+}
+
+impl SwbusMessageHeader {
+    /// The typed flags of the message. Unknown bits are kept, so messages from newer peers are forwarded unchanged.
+    pub fn flags(&self) -> SwbusMessageFlags {
+        SwbusMessageFlags::from_bits_retain(self.flag)
+    }
+
+    pub fn set_flags(&mut self, flags: SwbusMessageFlags) {
+        self.flag = flags.bits();
+    }
+
+    pub fn with_flags(mut self, flags: SwbusMessageFlags) -> Self {
+        self.set_flags(flags);
+        self
+    }
+
+    pub fn has_flags(&self, flags: SwbusMessageFlags) -> bool {
+        self.flags().contains(flags)
+    }
+}
+
+impl SwbusMessage {
+    /// The flags of the message, or empty if it has no header.
+    pub fn flags(&self) -> SwbusMessageFlags {
+        self.header.as_ref().map(|header| header.flags()).unwrap_or_default()
+    }
+
+    pub fn has_flags(&self, flags: SwbusMessageFlags) -> bool {
+        self.flags().contains(flags)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::swbus::{swbus_message, PingRequest, ServicePath};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn message_flags_can_be_set_and_read() {
+        let header = SwbusMessageHeader::new(
+            ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0").unwrap(),
+            ServicePath::from_string("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0").unwrap(),
+            1,
+        );
+        assert_eq!(header.flags(), SwbusMessageFlags::empty());
+
+        let header = header.with_flags(SwbusMessageFlags::ACK_REQUESTED | SwbusMessageFlags::TRACE);
+        assert_eq!(header.flag, 0b110);
+        assert!(header.has_flags(SwbusMessageFlags::TRACE));
+        assert!(!header.has_flags(SwbusMessageFlags::TRACE | SwbusMessageFlags::HIGH_PRIORITY));
+
+        let mut message = SwbusMessage::new(header, swbus_message::Body::PingRequest(PingRequest::new()));
+        assert!(message.has_flags(SwbusMessageFlags::ACK_REQUESTED));
+
+        // unknown bits are preserved
+        message.header.as_mut().unwrap().flag |= 1 << 31;
+        let flags = message.flags();
+        assert_eq!(flags.bits(), (1 << 31) | 0b110);
+        message.header.as_mut().unwrap().set_flags(flags);
+        assert_eq!(message.header.as_ref().unwrap().flag, (1 << 31) | 0b110);
+
+        // responses don't inherit the flags
+        let response = SwbusMessage::new_response(&message, None, crate::swbus::SwbusErrorCode::Ok, "", 2, None);
+        assert_eq!(response.flags(), SwbusMessageFlags::empty());
+    }
+}
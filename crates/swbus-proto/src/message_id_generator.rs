@@ -1,29 +1,286 @@
-use std::{
-    sync::atomic::{AtomicU64, Ordering},
-    time::SystemTime,
-};
-
-/// Swbus message IDs are defined as the startup time of the service
-/// in Epoch nanoseconds plus the number of messages sent since it started.
-/// This generator struct generates IDs following that scheme.
+use crate::result::*;
+use crate::swbus::SwbusErrorCode;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{error, warn};
+
+/// How far ahead of the issued IDs the high-water mark is persisted. As IDs follow the clock in nanoseconds, the
+/// store is written about once per this duration.
+pub const DEFAULT_ID_RESERVATION: Duration = Duration::from_secs(60);
+
+/// How long to wait before saving again after the store failed.
+const SAVE_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// State persisted by a [`MessageIdGenerator`] to keep its IDs unique across restarts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageIdState {
+    /// No ID at or above this mark has been issued.
+    pub high_water_mark: u64,
+    /// The ID clock, in epoch nanoseconds, when the state was saved. A system clock behind it at startup has
+    /// stepped backwards.
+    pub clock: u64,
+}
+
+/// Storage of the [`MessageIdState`], e.g. a local file or a Redis key.
+pub trait MessageIdStore: Send + Sync {
+    /// Load the saved state, or `None` if nothing was saved yet.
+    fn load(&self) -> Result<Option<MessageIdState>>;
+
+    fn save(&self, state: &MessageIdState) -> Result<()>;
+}
+
+/// Stores the [`MessageIdState`] as JSON in a local file. The file is replaced atomically, and synced to disk before
+/// `save` returns.
+pub struct FileMessageIdStore {
+    path: PathBuf,
+}
+
+impl FileMessageIdStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl MessageIdStore for FileMessageIdStore {
+    fn load(&self) -> Result<Option<MessageIdState>> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(state_file_error("read", &self.path, e)),
+        };
+        serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|e| state_file_error("parse", &self.path, e))
+    }
+
+    fn save(&self, state: &MessageIdState) -> Result<()> {
+        let content = serde_json::to_string(state).map_err(|e| state_file_error("serialize", &self.path, e))?;
+        let tmp_path = self.path.with_extension("tmp");
+        fs::File::create(&tmp_path)
+            .and_then(|mut file| {
+                file.write_all(content.as_bytes())?;
+                file.sync_all()
+            })
+            .map_err(|e| state_file_error("write", &tmp_path, e))?;
+        fs::rename(&tmp_path, &self.path).map_err(|e| state_file_error("replace", &self.path, e))?;
+
+        // the rename is only durable once the directory is synced
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        fs::File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .map_err(|e| state_file_error("sync directory of", &self.path, e))
+    }
+}
+
+fn state_file_error(action: &str, path: &Path, e: impl std::fmt::Display) -> SwbusError {
+    SwbusError::internal(
+        SwbusErrorCode::Fail,
+        format!("Failed to {} message id state file {}: {}", action, path.display(), e),
+    )
+}
+
+struct Persistence {
+    store: Box<dyn MessageIdStore>,
+    reservation: u64,
+    /// The persisted high-water mark. IDs below it can be issued without touching the store.
+    reserved: AtomicU64,
+    lock: Mutex<()>,
+    /// A background thread is saving a new high-water mark.
+    saving: AtomicBool,
+    failing: AtomicBool,
+    overrun: AtomicBool,
+}
+
+impl Persistence {
+    fn save(&self, state: &MessageIdState) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        self.store.save(state)?;
+        self.reserved.fetch_max(state.high_water_mark, Ordering::AcqRel);
+        Ok(())
+    }
+}
+
+/// Swbus message IDs are defined as the startup time of the service in Epoch nanoseconds plus the time elapsed since
+/// then, measured with the monotonic clock. An ID is always greater than the previous one, even when several are
+/// generated within the same nanosecond, so the IDs stay unique while the service runs whatever the system clock does.
+///
+/// Across restarts, the IDs are only unique if the system clock didn't step backwards. To guarantee it, create the
+/// generator with a [`MessageIdStore`]: it persists a high-water mark ahead of the issued IDs, and the next run starts
+/// above it if the clock is behind. The mark is saved from a background thread once half of the reservation is used,
+/// so generating an ID never waits for the store.
 pub struct MessageIdGenerator {
-    next_id: AtomicU64,
+    base: u64,
+    /// How far the ID clock was ahead of the system clock at startup.
+    wall_clock_offset: u64,
+    started: Instant,
+    last_id: AtomicU64,
+    clock_regression: Option<Duration>,
+    persistence: Option<Arc<Persistence>>,
 }
 
 impl MessageIdGenerator {
     pub fn new() -> Self {
-        let startup_epoch_nanos: u64 = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_nanos() as u64;
+        let wall_clock = epoch_nanos(SystemTime::now());
+        Self::start(wall_clock, wall_clock, None, None)
+    }
+
+    /// Create a generator that keeps its IDs unique across restarts with the state saved in `store`.
+    pub fn with_store(store: impl MessageIdStore + 'static) -> Result<Self> {
+        Self::with_store_at(Box::new(store), epoch_nanos(SystemTime::now()), DEFAULT_ID_RESERVATION)
+    }
+
+    fn with_store_at(store: Box<dyn MessageIdStore>, wall_clock: u64, reservation: Duration) -> Result<Self> {
+        let state = store.load()?;
+        let mut base = wall_clock;
+        let mut clock_regression = None;
+        if let Some(state) = state {
+            if wall_clock < state.clock {
+                let regression = Duration::from_nanos(state.clock - wall_clock);
+                warn!(
+                    "System clock is {:?} behind the message id clock of the previous run",
+                    regression
+                );
+                clock_regression = Some(regression);
+            }
+            base = base.max(state.high_water_mark);
+        }
+
+        // Reserve the first IDs before issuing any.
+        let reservation = reservation.as_nanos() as u64;
+        let reserved = base.saturating_add(reservation);
+        store.save(&MessageIdState {
+            high_water_mark: reserved,
+            clock: base,
+        })?;
+
+        let persistence = Persistence {
+            store,
+            reservation,
+            reserved: AtomicU64::new(reserved),
+            lock: Mutex::new(()),
+            saving: AtomicBool::new(false),
+            failing: AtomicBool::new(false),
+            overrun: AtomicBool::new(false),
+        };
+        Ok(Self::start(
+            base,
+            wall_clock,
+            clock_regression,
+            Some(Arc::new(persistence)),
+        ))
+    }
 
+    fn start(
+        base: u64,
+        wall_clock: u64,
+        clock_regression: Option<Duration>,
+        persistence: Option<Arc<Persistence>>,
+    ) -> Self {
         Self {
-            next_id: AtomicU64::new(startup_epoch_nanos),
+            base,
+            wall_clock_offset: base.saturating_sub(wall_clock),
+            started: Instant::now(),
+            last_id: AtomicU64::new(base.saturating_sub(1)),
+            clock_regression,
+            persistence,
         }
     }
 
+    /// How far the system clock was behind the clock of the previous run at startup, if it stepped backwards.
+    pub fn clock_regression(&self) -> Option<Duration> {
+        self.clock_regression
+    }
+
     pub fn generate(&self) -> u64 {
-        self.next_id.fetch_add(1, Ordering::Relaxed)
+        let now = self.clock();
+        let next = |last: u64| last.saturating_add(1).max(now);
+        let last = self
+            .last_id
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| Some(next(last)))
+            .unwrap();
+        let id = next(last);
+        if let Some(persistence) = &self.persistence {
+            let reserved = persistence.reserved.load(Ordering::Acquire);
+            if id >= reserved && !persistence.overrun.swap(true, Ordering::Relaxed) {
+                warn!("Message ids passed the saved high-water mark, they may repeat after a restart");
+            }
+            if id.saturating_add(persistence.reservation / 2) >= reserved {
+                self.reserve(persistence, id);
+            }
+        }
+        id
+    }
+
+    fn clock(&self) -> u64 {
+        self.base.saturating_add(self.started.elapsed().as_nanos() as u64)
+    }
+
+    /// Persist a new high-water mark above `id` in the background, unless a save is already in progress.
+    fn reserve(&self, persistence: &Arc<Persistence>, id: u64) {
+        if persistence.saving.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let clock = self.clock();
+        let wall_clock = epoch_nanos(SystemTime::now());
+        let expected_clock = wall_clock.saturating_add(self.wall_clock_offset);
+        if expected_clock.saturating_add(Duration::from_secs(1).as_nanos() as u64) < clock {
+            warn!(
+                "System clock stepped {:?} backwards since startup",
+                Duration::from_nanos(clock - expected_clock)
+            );
+        }
+
+        let state = MessageIdState {
+            high_water_mark: id.saturating_add(persistence.reservation),
+            clock,
+        };
+        let background = persistence.clone();
+        let spawned = std::thread::Builder::new()
+            .name("message-id-store".into())
+            .spawn(move || {
+                match background.save(&state) {
+                    Ok(()) => {
+                        background.failing.store(false, Ordering::Relaxed);
+                        background.overrun.store(false, Ordering::Relaxed);
+                    }
+                    // Retried on a later ID. The IDs stay unique as long as this process runs.
+                    Err(e) => {
+                        if !background.failing.swap(true, Ordering::Relaxed) {
+                            error!("Failed to save message id high-water mark: {}", e);
+                        }
+                        std::thread::sleep(SAVE_RETRY_INTERVAL);
+                    }
+                }
+                background.saving.store(false, Ordering::Release);
+            });
+        if let Err(e) = spawned {
+            error!("Failed to start saving message id high-water mark: {}", e);
+            persistence.saving.store(false, Ordering::Release);
+        }
+    }
+}
+
+/// Save the exact high-water mark on shutdown, so the next run doesn't start ahead of the clock by the reservation.
+impl Drop for MessageIdGenerator {
+    fn drop(&mut self) {
+        if let Some(persistence) = &self.persistence {
+            let state = MessageIdState {
+                high_water_mark: self.last_id.load(Ordering::Relaxed).saturating_add(1),
+                clock: self.clock(),
+            };
+            if let Err(e) = persistence.save(&state) {
+                error!("Failed to save message id high-water mark: {}", e);
+            }
+        }
     }
 }
 
@@ -32,3 +289,182 @@ impl Default for MessageIdGenerator {
         Self::new()
     }
 }
+
+fn epoch_nanos(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::collections::HashSet;
+    use std::sync::mpsc;
+
+    #[derive(Clone, Default)]
+    struct MemoryStore(Arc<Mutex<Option<MessageIdState>>>);
+
+    impl MessageIdStore for MemoryStore {
+        fn load(&self) -> Result<Option<MessageIdState>> {
+            Ok(*self.0.lock().unwrap())
+        }
+
+        fn save(&self, state: &MessageIdState) -> Result<()> {
+            *self.0.lock().unwrap() = Some(*state);
+            Ok(())
+        }
+    }
+
+    /// Blocks saving while the test holds the gate.
+    struct GatedStore {
+        state: MemoryStore,
+        gate: Arc<Mutex<()>>,
+    }
+
+    impl MessageIdStore for GatedStore {
+        fn load(&self) -> Result<Option<MessageIdState>> {
+            self.state.load()
+        }
+
+        fn save(&self, state: &MessageIdState) -> Result<()> {
+            let _gate = self.gate.lock().unwrap();
+            self.state.save(state)
+        }
+    }
+
+    /// Wait for the background thread to save a high-water mark of at least `mark`.
+    fn wait_for_high_water_mark(store: &MemoryStore, mark: u64) {
+        for _ in 0..1000 {
+            let high_water_mark = store.load().unwrap().unwrap().high_water_mark;
+            if high_water_mark >= mark {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        panic!("high-water mark {} wasn't saved", mark);
+    }
+
+    const HOUR: u64 = 3600 * 1_000_000_000;
+
+    #[test]
+    fn ids_are_unique_and_increasing() {
+        let generator = Arc::new(MessageIdGenerator::new());
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let generator = generator.clone();
+                std::thread::spawn(move || (0..10000).map(|_| generator.generate()).collect::<Vec<_>>())
+            })
+            .collect();
+
+        let mut all = HashSet::new();
+        for thread in threads {
+            let ids = thread.join().unwrap();
+            assert!(ids.windows(2).all(|w| w[0] < w[1]));
+            all.extend(ids);
+        }
+        assert_eq!(all.len(), 40000);
+    }
+
+    #[test]
+    fn ids_stay_unique_across_restarts_when_clock_goes_back() {
+        let store = MemoryStore::default();
+        let start = epoch_nanos(SystemTime::now());
+
+        let mut issued = HashSet::new();
+        let mut max_id = 0;
+        for restart in 0..3u64 {
+            // every restart happens with the clock one hour further in the past
+            let wall_clock = start - restart * HOUR;
+            let generator =
+                MessageIdGenerator::with_store_at(Box::new(store.clone()), wall_clock, DEFAULT_ID_RESERVATION).unwrap();
+            match restart {
+                0 => assert_eq!(generator.clock_regression(), None),
+                _ => assert!(generator.clock_regression().unwrap() >= Duration::from_nanos(HOUR)),
+            }
+
+            for _ in 0..1000 {
+                let id = generator.generate();
+                assert!(id > max_id);
+                assert!(issued.insert(id));
+                max_id = id;
+            }
+        }
+    }
+
+    #[test]
+    fn high_water_mark_is_saved_ahead_of_the_ids() {
+        let store = MemoryStore::default();
+        let reservation = Duration::from_millis(10);
+        let generator = MessageIdGenerator::with_store_at(Box::new(store.clone()), 1000, reservation).unwrap();
+        assert_eq!(store.load().unwrap().unwrap().high_water_mark, 1000 + 10_000_000);
+
+        // half of the reservation is used, a new mark is saved a reservation ahead
+        std::thread::sleep(reservation / 2);
+        let id = generator.generate();
+        wait_for_high_water_mark(&store, id + 10_000_000);
+
+        // a crash skips saving the exact mark, and a restart with the clock far behind starts above the reserved one
+        std::mem::forget(generator);
+        let high_water_mark = store.load().unwrap().unwrap().high_water_mark;
+        let generator = MessageIdGenerator::with_store_at(Box::new(store.clone()), 0, DEFAULT_ID_RESERVATION).unwrap();
+        assert!(generator.generate() >= high_water_mark);
+        assert!(generator.clock_regression().is_some());
+
+        // a clean shutdown saves the exact mark
+        let last_id = generator.generate();
+        drop(generator);
+        assert_eq!(store.load().unwrap().unwrap().high_water_mark, last_id + 1);
+    }
+
+    #[test]
+    fn generating_ids_does_not_wait_for_the_store() {
+        let store = MemoryStore::default();
+        let gate = Arc::new(Mutex::new(()));
+        let generator = MessageIdGenerator::with_store_at(
+            Box::new(GatedStore {
+                state: store.clone(),
+                gate: gate.clone(),
+            }),
+            1000,
+            Duration::from_millis(10),
+        )
+        .unwrap();
+
+        // the store hangs while the ids pass the reserved mark
+        let gate_guard = gate.lock().unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        let (ids_tx, ids_rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let ids: Vec<u64> = (0..100).map(|_| generator.generate()).collect();
+            ids_tx.send(ids).unwrap();
+            std::mem::forget(generator);
+        });
+        let ids = ids_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        drop(gate_guard);
+        wait_for_high_water_mark(&store, ids[0] + 10_000_000);
+    }
+
+    #[test]
+    fn state_can_be_saved_to_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("message_id.json");
+        let store = FileMessageIdStore::new(&path);
+        assert_eq!(store.load().unwrap(), None);
+
+        let state = MessageIdState {
+            high_water_mark: 2000,
+            clock: 1000,
+        };
+        store.save(&state).unwrap();
+        assert_eq!(FileMessageIdStore::new(&path).load().unwrap(), Some(state));
+
+        let generator = MessageIdGenerator::with_store(FileMessageIdStore::new(&path)).unwrap();
+        assert!(generator.generate() > 2000);
+
+        fs::write(&path, "garbage").unwrap();
+        assert_eq!(store.load().unwrap_err().code(), SwbusErrorCode::Fail);
+    }
+}
@@ -0,0 +1,283 @@
+use crate::result::*;
+use crate::swbus::{swbus_message, SwbusErrorCode, SwbusMessage};
+use std::fmt;
+
+/// Lowest protocol version this build can talk.
+pub const SWBUS_MIN_PROTOCOL_VERSION: u32 = 1;
+/// Highest protocol version this build can talk. New headers carry it in `SwbusMessageHeader.version`, and are
+/// stamped with the negotiated version when they are sent over a connection, see
+/// [`SwbusNegotiatedProtocol::prepare_outgoing`].
+pub const SWBUS_MAX_PROTOCOL_VERSION: u32 = 1;
+
+/// Messages can be sent to group destinations, see [`ServicePath::is_group`](crate::swbus::ServicePath::is_group).
+pub const SWBUS_FEATURE_GROUP_DELIVERY: u64 = 1 << 0;
+/// Publish/subscribe requests are handled by swbusd.
+pub const SWBUS_FEATURE_PUBSUB: u64 = 1 << 1;
+/// All features supported by this build.
+pub const SWBUS_SUPPORTED_FEATURES: u64 = SWBUS_FEATURE_GROUP_DELIVERY | SWBUS_FEATURE_PUBSUB;
+
+/// Protocol versions and features supported by one end of a connection.
+///
+/// The client sends its support in the `x-swbus-protocol-version` (`min-max`) and `x-swbus-features` (decimal bits)
+/// gRPC metadata when it opens the message stream. The server answers with the negotiated version and features in
+/// the same metadata of the response. Peers that don't send the metadata predate the negotiation and only talk
+/// version 1 without any feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SwbusProtocolSupport {
+    pub min_version: u32,
+    pub max_version: u32,
+    pub features: u64,
+}
+
+/// Protocol version and features agreed by both ends of a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SwbusNegotiatedProtocol {
+    pub version: u32,
+    pub features: u64,
+}
+
+impl SwbusProtocolSupport {
+    /// Protocol support of this build.
+    pub fn current() -> Self {
+        SwbusProtocolSupport {
+            min_version: SWBUS_MIN_PROTOCOL_VERSION,
+            max_version: SWBUS_MAX_PROTOCOL_VERSION,
+            features: SWBUS_SUPPORTED_FEATURES,
+        }
+    }
+
+    /// Protocol support of a peer that doesn't send the negotiation metadata.
+    pub fn legacy() -> Self {
+        SwbusProtocolSupport {
+            min_version: 1,
+            max_version: 1,
+            features: 0,
+        }
+    }
+
+    /// Parse the protocol support from the metadata values. A single version `N` is the same as `N-N`.
+    pub fn from_metadata(version: Option<&str>, features: Option<&str>) -> Result<Self> {
+        let Some(version) = version else {
+            return Ok(Self::legacy());
+        };
+        let invalid = |detail: String| SwbusError::input(SwbusErrorCode::InvalidArgs, detail);
+        let parse_version = |v: &str| {
+            v.trim()
+                .parse::<u32>()
+                .map_err(|e| invalid(format!("Invalid protocol version \"{}\": {}", version, e)))
+        };
+        let (min_version, max_version) = match version.split_once('-') {
+            Some((min, max)) => (parse_version(min)?, parse_version(max)?),
+            None => {
+                let v = parse_version(version)?;
+                (v, v)
+            }
+        };
+        if min_version == 0 || min_version > max_version {
+            return Err(invalid(format!("Invalid protocol version range \"{}\"", version)));
+        }
+        let features = match features {
+            Some(features) => features
+                .trim()
+                .parse::<u64>()
+                .map_err(|e| invalid(format!("Invalid protocol features \"{}\": {}", features, e)))?,
+            None => 0,
+        };
+        Ok(SwbusProtocolSupport {
+            min_version,
+            max_version,
+            features,
+        })
+    }
+
+    /// Value of the `x-swbus-protocol-version` metadata.
+    pub fn version_metadata(&self) -> String {
+        format!("{}-{}", self.min_version, self.max_version)
+    }
+
+    /// Value of the `x-swbus-features` metadata.
+    pub fn features_metadata(&self) -> String {
+        self.features.to_string()
+    }
+
+    /// Pick the highest version and the features supported by both ends. Fails with `InvalidArgs` if the version
+    /// ranges don't overlap.
+    pub fn negotiate(&self, remote: &SwbusProtocolSupport) -> Result<SwbusNegotiatedProtocol> {
+        let version = self.max_version.min(remote.max_version);
+        if version < self.min_version.max(remote.min_version) {
+            return Err(SwbusError::input(
+                SwbusErrorCode::InvalidArgs,
+                format!(
+                    "Incompatible protocol versions: local supports {}, remote supports {}",
+                    self, remote
+                ),
+            ));
+        }
+        Ok(SwbusNegotiatedProtocol {
+            version,
+            features: self.features & remote.features,
+        })
+    }
+}
+
+impl fmt::Display for SwbusProtocolSupport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "versions {}-{} with features {:#x}",
+            self.min_version, self.max_version, self.features
+        )
+    }
+}
+
+impl SwbusNegotiatedProtocol {
+    pub fn has_feature(&self, feature: u64) -> bool {
+        self.features & feature == feature
+    }
+
+    /// Prepare a message to be sent over the connection: its header is stamped with the negotiated version. Fails with
+    /// `NO_ROUTE` if the message needs a feature the peer doesn't support, see [`required_features`].
+    pub fn prepare_outgoing(&self, message: &mut SwbusMessage) -> Result<()> {
+        let missing = required_features(message) & !self.features;
+        if missing != 0 {
+            return Err(SwbusError::route(
+                SwbusErrorCode::NoRoute,
+                format!("The peer doesn't support the features {:#x} of the message", missing),
+            ));
+        }
+        if let Some(header) = message.header.as_mut() {
+            header.version = self.version;
+        }
+        Ok(())
+    }
+
+    /// The negotiated version as the protocol support of the other end, e.g. for a client to validate the server's
+    /// answer.
+    pub fn as_support(&self) -> SwbusProtocolSupport {
+        SwbusProtocolSupport {
+            min_version: self.version,
+            max_version: self.version,
+            features: self.features,
+        }
+    }
+}
+
+/// The features a peer must support to receive the message: group delivery for group destinations, and pub/sub for
+/// publish and (un)subscribe requests.
+pub fn required_features(message: &SwbusMessage) -> u64 {
+    let mut features = 0;
+    let destination = message.header.as_ref().and_then(|header| header.destination.as_ref());
+    if destination.is_some_and(|destination| destination.is_group()) {
+        features |= SWBUS_FEATURE_GROUP_DELIVERY;
+    }
+    if matches!(
+        message.body,
+        Some(
+            swbus_message::Body::PublishRequest(_)
+                | swbus_message::Body::SubscribeRequest(_)
+                | swbus_message::Body::UnsubscribeRequest(_)
+        )
+    ) {
+        features |= SWBUS_FEATURE_PUBSUB;
+    }
+    features
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::swbus::{PingRequest, PublishRequest, ServicePath, SwbusMessageHeader};
+    use pretty_assertions::assert_eq;
+
+    fn support(min_version: u32, max_version: u32, features: u64) -> SwbusProtocolSupport {
+        SwbusProtocolSupport {
+            min_version,
+            max_version,
+            features,
+        }
+    }
+
+    #[test]
+    fn protocol_support_can_be_parsed_from_metadata() {
+        let current = SwbusProtocolSupport::current();
+        assert_eq!(
+            SwbusProtocolSupport::from_metadata(Some(&current.version_metadata()), Some(&current.features_metadata()))
+                .unwrap(),
+            current
+        );
+        assert_eq!(
+            SwbusProtocolSupport::from_metadata(Some("2"), None).unwrap(),
+            support(2, 2, 0)
+        );
+        assert_eq!(
+            SwbusProtocolSupport::from_metadata(None, Some("3")).unwrap(),
+            SwbusProtocolSupport::legacy()
+        );
+
+        for (version, features) in [("0-1", "0"), ("3-2", "0"), ("a-2", "0"), ("1", "-1"), ("", "0")] {
+            assert!(SwbusProtocolSupport::from_metadata(Some(version), Some(features)).is_err());
+        }
+    }
+
+    #[test]
+    fn protocol_can_be_negotiated() {
+        let negotiated = support(1, 3, 0b111).negotiate(&support(2, 5, 0b101)).unwrap();
+        assert_eq!(
+            negotiated,
+            SwbusNegotiatedProtocol {
+                version: 3,
+                features: 0b101
+            }
+        );
+        assert!(negotiated.has_feature(0b001));
+        assert!(!negotiated.has_feature(0b010));
+
+        // a newer peer is downgraded to the highest common version
+        let negotiated = SwbusProtocolSupport::current()
+            .negotiate(&support(1, SWBUS_MAX_PROTOCOL_VERSION + 1, 0))
+            .unwrap();
+        assert_eq!(negotiated.version, SWBUS_MAX_PROTOCOL_VERSION);
+
+        let err = support(1, 2, 0).negotiate(&support(3, 4, 0)).unwrap_err();
+        assert_eq!(err.code(), SwbusErrorCode::InvalidArgs);
+        assert!(err.to_string().contains("Incompatible protocol versions"));
+    }
+
+    #[test]
+    fn outgoing_messages_follow_negotiated_protocol() {
+        let sp = |s: &str| ServicePath::from_string(s).unwrap();
+        let message = |destination: &str, body| {
+            let mut header =
+                SwbusMessageHeader::new(sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0"), sp(destination), 1);
+            header.version = 5;
+            SwbusMessage::new(header, body)
+        };
+        let ping = || swbus_message::Body::PingRequest(PingRequest::new());
+        let legacy = SwbusNegotiatedProtocol {
+            version: 1,
+            features: 0,
+        };
+
+        let mut unicast = message("region-a.cluster-a.10.0.0.2-dpu0/hamgrd/0", ping());
+        assert_eq!(required_features(&unicast), 0);
+        legacy.prepare_outgoing(&mut unicast).unwrap();
+        assert_eq!(unicast.header.unwrap().version, 1);
+
+        let mut group = message("region-a.cluster-a.*/hamgrd/0", ping());
+        assert_eq!(required_features(&group), SWBUS_FEATURE_GROUP_DELIVERY);
+        let err = legacy.prepare_outgoing(&mut group).unwrap_err();
+        assert_eq!(err.code(), SwbusErrorCode::NoRoute);
+        let current = SwbusNegotiatedProtocol {
+            version: SWBUS_MAX_PROTOCOL_VERSION,
+            features: SWBUS_SUPPORTED_FEATURES,
+        };
+        current.prepare_outgoing(&mut group).unwrap();
+
+        let mut publish = message(
+            "region-a.cluster-a.10.0.0.2-dpu0/local-mgmt/0",
+            swbus_message::Body::PublishRequest(PublishRequest::new("topic", vec![])),
+        );
+        assert_eq!(required_features(&publish), SWBUS_FEATURE_PUBSUB);
+        assert!(legacy.prepare_outgoing(&mut publish).is_err());
+    }
+}
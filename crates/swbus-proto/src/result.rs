@@ -1,42 +1,232 @@
-use crate::swbus::SwbusErrorCode;
+use crate::swbus::{RequestResponse, SwbusErrorCode, SWBUS_ERROR_CODE};
 use contracts::requires;
+use prost::Message;
+use std::collections::HashMap;
 use std::io;
 use thiserror::Error;
+use tonic::metadata::MetadataValue;
+use tonic::{Code, Status};
+
+/// Key of the error details that records the gRPC status code an error was converted from.
+pub const GRPC_CODE_DETAIL: &str = "grpc_code";
 
 #[derive(Error, Debug)]
 pub enum SwbusError {
     #[error("Connection:{code:?} - {detail}")]
-    ConnectionError { code: SwbusErrorCode, detail: io::Error },
+    ConnectionError {
+        code: SwbusErrorCode,
+        detail: io::Error,
+        details: HashMap<String, String>,
+    },
 
     #[error("Input:{code:?} - {detail}")]
-    InputError { code: SwbusErrorCode, detail: String },
+    InputError {
+        code: SwbusErrorCode,
+        detail: String,
+        details: HashMap<String, String>,
+    },
 
     #[error("Route:{code:?} - {detail}")]
-    RouteError { code: SwbusErrorCode, detail: String },
+    RouteError {
+        code: SwbusErrorCode,
+        detail: String,
+        details: HashMap<String, String>,
+    },
 
     #[error("Internal:{code:?} - {detail}")]
-    InternalError { code: SwbusErrorCode, detail: String },
+    InternalError {
+        code: SwbusErrorCode,
+        detail: String,
+        details: HashMap<String, String>,
+    },
 }
 
 impl SwbusError {
     #[requires(code > SwbusErrorCode::ConnectionErrorMin && code < SwbusErrorCode::ConnectionErrorMax)]
     pub fn connection(code: SwbusErrorCode, detail: io::Error) -> Self {
-        SwbusError::ConnectionError { code, detail }
+        SwbusError::ConnectionError {
+            code,
+            detail,
+            details: HashMap::new(),
+        }
     }
 
     #[requires(code > SwbusErrorCode::InputErrorMin && code < SwbusErrorCode::InputErrorMax)]
     pub fn input(code: SwbusErrorCode, detail: String) -> Self {
-        SwbusError::InputError { code, detail }
+        SwbusError::InputError {
+            code,
+            detail,
+            details: HashMap::new(),
+        }
     }
 
     #[requires(code > SwbusErrorCode::RouteErrorMin && code < SwbusErrorCode::RouteErrorMax)]
     pub fn route(code: SwbusErrorCode, detail: String) -> Self {
-        SwbusError::RouteError { code, detail }
+        SwbusError::RouteError {
+            code,
+            detail,
+            details: HashMap::new(),
+        }
     }
 
     #[requires(code > SwbusErrorCode::InternalErrorMin && code < SwbusErrorCode::InternalErrorMax)]
     pub fn internal(code: SwbusErrorCode, detail: String) -> Self {
-        SwbusError::InternalError { code, detail }
+        SwbusError::InternalError {
+            code,
+            detail,
+            details: HashMap::new(),
+        }
+    }
+
+    /// Create an error of the category that `code` belongs to. Codes outside of any category, such as
+    /// `UnknownError`, are internal errors.
+    pub fn from_code(code: SwbusErrorCode, detail: String) -> Self {
+        let details = HashMap::new();
+        if code > SwbusErrorCode::ConnectionErrorMin && code < SwbusErrorCode::ConnectionErrorMax {
+            SwbusError::ConnectionError {
+                code,
+                detail: io::Error::other(detail),
+                details,
+            }
+        } else if code > SwbusErrorCode::InputErrorMin && code < SwbusErrorCode::InputErrorMax {
+            SwbusError::InputError { code, detail, details }
+        } else if code > SwbusErrorCode::RouteErrorMin && code < SwbusErrorCode::RouteErrorMax {
+            SwbusError::RouteError { code, detail, details }
+        } else {
+            SwbusError::InternalError { code, detail, details }
+        }
+    }
+
+    pub fn code(&self) -> SwbusErrorCode {
+        match self {
+            SwbusError::ConnectionError { code, .. }
+            | SwbusError::InputError { code, .. }
+            | SwbusError::RouteError { code, .. }
+            | SwbusError::InternalError { code, .. } => *code,
+        }
+    }
+
+    /// The error message, without the category and code prefix of the `Display` output.
+    pub fn detail(&self) -> String {
+        match self {
+            SwbusError::ConnectionError { detail, .. } => detail.to_string(),
+            SwbusError::InputError { detail, .. }
+            | SwbusError::RouteError { detail, .. }
+            | SwbusError::InternalError { detail, .. } => detail.clone(),
+        }
+    }
+
+    /// Structured context of the error.
+    pub fn details(&self) -> &HashMap<String, String> {
+        match self {
+            SwbusError::ConnectionError { details, .. }
+            | SwbusError::InputError { details, .. }
+            | SwbusError::RouteError { details, .. }
+            | SwbusError::InternalError { details, .. } => details,
+        }
+    }
+
+    fn details_mut(&mut self) -> &mut HashMap<String, String> {
+        match self {
+            SwbusError::ConnectionError { details, .. }
+            | SwbusError::InputError { details, .. }
+            | SwbusError::RouteError { details, .. }
+            | SwbusError::InternalError { details, .. } => details,
+        }
+    }
+
+    /// Add a structured detail to the error.
+    pub fn with_detail(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.details_mut().insert(key.into(), value.into());
+        self
+    }
+}
+
+impl From<SwbusErrorCode> for Code {
+    fn from(code: SwbusErrorCode) -> Self {
+        match code {
+            SwbusErrorCode::Ok => Code::Ok,
+            SwbusErrorCode::ConnectionError | SwbusErrorCode::Unreachable => Code::Unavailable,
+            SwbusErrorCode::Timeout => Code::DeadlineExceeded,
+            SwbusErrorCode::InvalidArgs
+            | SwbusErrorCode::InvalidDestination
+            | SwbusErrorCode::InvalidSource
+            | SwbusErrorCode::InvalidHeader
+            | SwbusErrorCode::InvalidPayload => Code::InvalidArgument,
+            SwbusErrorCode::NoRoute | SwbusErrorCode::ServiceNotFound | SwbusErrorCode::ResourceNotFound => {
+                Code::NotFound
+            }
+            SwbusErrorCode::QueueFull => Code::ResourceExhausted,
+            SwbusErrorCode::Fail => Code::Internal,
+            _ => Code::Unknown,
+        }
+    }
+}
+
+impl From<Code> for SwbusErrorCode {
+    fn from(code: Code) -> Self {
+        match code {
+            Code::Ok => SwbusErrorCode::Ok,
+            Code::Cancelled | Code::Unavailable | Code::Aborted => SwbusErrorCode::ConnectionError,
+            Code::DeadlineExceeded => SwbusErrorCode::Timeout,
+            Code::InvalidArgument | Code::OutOfRange | Code::FailedPrecondition => SwbusErrorCode::InvalidArgs,
+            Code::NotFound => SwbusErrorCode::ResourceNotFound,
+            Code::ResourceExhausted => SwbusErrorCode::QueueFull,
+            _ => SwbusErrorCode::Fail,
+        }
+    }
+}
+
+/// Errors are sent as a status with the gRPC code mapped from the error code. The exact error is encoded as a
+/// [`RequestResponse`] in the status details, and marked with the `x-swbus-error-code` metadata.
+impl From<SwbusError> for Status {
+    fn from(error: SwbusError) -> Self {
+        let grpc_code = error
+            .details()
+            .get(GRPC_CODE_DETAIL)
+            .and_then(|code| code.parse::<i32>().ok())
+            .map(Code::from_i32)
+            .unwrap_or_else(|| error.code().into());
+        let response = RequestResponse::from_error(0, &error);
+        let mut metadata = tonic::metadata::MetadataMap::new();
+        metadata.insert(SWBUS_ERROR_CODE, MetadataValue::from(response.error_code));
+        Status::with_details_and_metadata(
+            grpc_code,
+            response.error_message.clone(),
+            response.encode_to_vec().into(),
+            metadata,
+        )
+    }
+}
+
+/// Statuses created from a [`SwbusError`] are converted back to the same error. Other statuses are mapped by their
+/// gRPC code, which is kept in the [`GRPC_CODE_DETAIL`] detail.
+impl From<Status> for SwbusError {
+    fn from(status: Status) -> Self {
+        if status.metadata().contains_key(SWBUS_ERROR_CODE) {
+            if let Ok(error) = RequestResponse::decode(status.details())
+                .map_err(|_| ())
+                .and_then(|response| SwbusError::try_from(response).map_err(|_| ()))
+            {
+                return error;
+            }
+        }
+        SwbusError::from_code(status.code().into(), status.message().to_string())
+            .with_detail(GRPC_CODE_DETAIL, (status.code() as i32).to_string())
+    }
+}
+
+/// Error responses are converted to the error they carry. OK responses are given back as the error.
+impl TryFrom<RequestResponse> for SwbusError {
+    type Error = RequestResponse;
+
+    fn try_from(response: RequestResponse) -> core::result::Result<Self, Self::Error> {
+        if response.error_code() == SwbusErrorCode::Ok {
+            return Err(response);
+        }
+        let mut error = SwbusError::from_code(response.error_code(), response.error_message);
+        *error.details_mut() = response.error_details;
+        Ok(error)
     }
 }
 
@@ -62,5 +252,82 @@ mod tests {
 
         let error = SwbusError::internal(SwbusErrorCode::Fail, "Internal error".to_string());
         assert_eq!(error.to_string(), "Internal:Fail - Internal error");
+        assert_eq!(error.code(), SwbusErrorCode::Fail);
+    }
+
+    #[test]
+    fn swbus_error_can_be_converted_to_status_and_back() {
+        let error =
+            SwbusError::route(SwbusErrorCode::QueueFull, "Queue is full".to_string()).with_detail("queue", "hamgrd");
+        let status = Status::from(error);
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert_eq!(status.message(), "Queue is full");
+
+        let error = SwbusError::from(status);
+        assert!(matches!(error, SwbusError::RouteError { .. }));
+        assert_eq!(error.code(), SwbusErrorCode::QueueFull);
+        assert_eq!(error.detail(), "Queue is full");
+        assert_eq!(
+            error.details(),
+            &HashMap::from([("queue".to_string(), "hamgrd".to_string())])
+        );
+
+        let error = SwbusError::connection(
+            SwbusErrorCode::Timeout,
+            io::Error::new(io::ErrorKind::TimedOut, "Timed out"),
+        );
+        let error = SwbusError::from(Status::from(error));
+        assert!(matches!(error, SwbusError::ConnectionError { .. }));
+        assert_eq!(error.to_string(), "Connection:Timeout - Timed out");
+    }
+
+    #[test]
+    fn status_can_be_converted_to_swbus_error_and_back() {
+        let error = SwbusError::from(Status::unavailable("Connection refused"));
+        assert_eq!(error.code(), SwbusErrorCode::ConnectionError);
+        assert_eq!(error.detail(), "Connection refused");
+        assert_eq!(
+            error.details()[GRPC_CODE_DETAIL],
+            (Code::Unavailable as i32).to_string()
+        );
+
+        // the gRPC code is kept, even if it maps to a more generic error code
+        let error = SwbusError::from(Status::permission_denied("Not allowed"));
+        assert_eq!(error.code(), SwbusErrorCode::Fail);
+        let status = Status::from(error);
+        assert_eq!(status.code(), Code::PermissionDenied);
+        assert_eq!(status.message(), "Not allowed");
+    }
+
+    #[test]
+    fn request_response_can_be_converted_to_swbus_error_and_back() {
+        let error = SwbusError::input(SwbusErrorCode::InvalidPayload, "Bad payload".to_string())
+            .with_detail("field", "payload");
+        let response = RequestResponse::from_error(7, &error);
+        assert_eq!(response.request_id, 7);
+        assert_eq!(response.error_code(), SwbusErrorCode::InvalidPayload);
+        assert_eq!(response.error_message, "Bad payload");
+        assert_eq!(response.error_details["field"], "payload");
+
+        let error = SwbusError::try_from(response).unwrap();
+        assert!(matches!(error, SwbusError::InputError { .. }));
+        assert_eq!(error.code(), SwbusErrorCode::InvalidPayload);
+        assert_eq!(error.details()["field"], "payload");
+
+        let ok = RequestResponse::ok(8);
+        assert_eq!(SwbusError::try_from(ok.clone()).unwrap_err(), ok);
+
+        // codes outside of any category are kept as internal errors
+        let error = SwbusError::try_from(RequestResponse::infra_error(9, SwbusErrorCode::UnknownError, "?")).unwrap();
+        assert!(matches!(error, SwbusError::InternalError { .. }));
+        assert_eq!(error.code(), SwbusErrorCode::UnknownError);
+    }
+
+    #[test]
+    fn error_codes_can_be_converted_to_grpc_codes() {
+        assert_eq!(Code::from(SwbusErrorCode::NoRoute), Code::NotFound);
+        assert_eq!(Code::from(SwbusErrorCode::Timeout), Code::DeadlineExceeded);
+        assert_eq!(SwbusErrorCode::from(Code::DeadlineExceeded), SwbusErrorCode::Timeout);
+        assert_eq!(SwbusErrorCode::from(Code::Internal), SwbusErrorCode::Fail);
     }
 }
@@ -0,0 +1,429 @@
+use crate::result::*;
+use crate::swbus::{
+    escape_component, split_unescaped, unescape_component, ServicePath, SwbusErrorCode, SERVICE_PATH_WILDCARD,
+};
+use std::cmp::Ordering;
+use std::fmt;
+
+const COMPONENT_NAMES: [&str; 7] = [
+    "region_id",
+    "cluster_id",
+    "node_id",
+    "service_type",
+    "service_id",
+    "resource_type",
+    "resource_id",
+];
+
+/// Index of the first service component (`service_type`) in the component list.
+const SERVICE_START: usize = 3;
+
+/// Pattern of a single service path component.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ComponentPattern {
+    /// Matches the component with exactly this value. An empty value matches an absent component.
+    Exact(String),
+    /// `*`, matches any single non-empty component.
+    Any,
+}
+
+impl ComponentPattern {
+    fn matches(&self, component: &str) -> bool {
+        match self {
+            ComponentPattern::Exact(value) => value == component,
+            ComponentPattern::Any => !component.is_empty(),
+        }
+    }
+}
+
+/// A pattern that matches a family of service paths.
+///
+/// Patterns use the same grammar as [`ServicePath::from_string`], with two wildcards that can be used in place of
+/// a component:
+///
+/// - `*` matches any single non-empty component, e.g. `region-a.*.*/hamgrd/*/hascope/*`.
+/// - `**` matches all remaining components, including absent ones. It must be the last component of the pattern,
+///   e.g. `region-a.cluster-a.**` or `region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/**`.
+///
+/// Components that are not specified in a pattern without `**` must be absent in the service path, the same way
+/// they are in [`ServicePath`]. As in service paths, `*` is never escaped: it is a wildcard only as a whole component,
+/// and it is kept literally inside a component, e.g. `dpu*`. `\*` is rejected. Converting a group destination
+/// (see [`ServicePath::is_group`]) into a pattern turns its `*` components into wildcards, so every pattern without
+/// `**` can be written as a service path and back.
+///
+/// Patterns select the handlers of the edge message router, the members of group destinations and the publishers
+/// of subscriptions. swbusd has no ACL or capture filters to use them in.
+///
+/// When several patterns match the same service path, the one with the highest [`specificity`](Self::specificity)
+/// should win.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ServicePathPattern {
+    components: [ComponentPattern; 7],
+    /// Index of the component where `**` starts, if any.
+    suffix_start: Option<usize>,
+}
+
+/// How specific a [`ServicePathPattern`] is. A greater value means a more specific pattern.
+///
+/// Patterns are ordered by the number of literal components first, then patterns without `**` win over patterns
+/// with it, then fewer `*` wins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Specificity {
+    literals: usize,
+    exact_length: bool,
+    wildcards: std::cmp::Reverse<usize>,
+}
+
+impl ServicePathPattern {
+    /// Parse a pattern from a string. See [`ServicePathPattern`] for the grammar.
+    pub fn from_string(pattern: &str) -> Result<Self> {
+        let invalid = |detail: String| {
+            SwbusError::input(
+                SwbusErrorCode::InvalidArgs,
+                format!("Invalid service path pattern \"{}\": {}", pattern, detail),
+            )
+        };
+
+        if pattern.is_empty() {
+            return Err(invalid("pattern is empty".to_string()));
+        }
+
+        let parts = split_unescaped(pattern, '/', usize::MAX);
+        let ends_with_suffix = parts.last() == Some(&"**");
+        if parts.len() > 5 || (!ends_with_suffix && parts.len().is_multiple_of(2)) {
+            return Err(invalid(format!(
+                "expected location[/service_type/service_id[/resource_type/resource_id]], but found {} '/' separated parts",
+                parts.len()
+            )));
+        }
+
+        // The location can be omitted only when the service is specified.
+        let loc_parts = match parts[0].is_empty() && parts.len() > 1 {
+            true => vec![],
+            false => split_unescaped(parts[0], '.', 3),
+        };
+        if parts.len() > 1 && loc_parts.last() == Some(&"**") {
+            return Err(invalid("\"**\" must be the last component".to_string()));
+        }
+
+        let present: Vec<(usize, &str)> = loc_parts
+            .into_iter()
+            .enumerate()
+            .chain(
+                parts[1..]
+                    .iter()
+                    .enumerate()
+                    .map(|(i, part)| (i + SERVICE_START, *part)),
+            )
+            .collect();
+
+        let mut components: [ComponentPattern; 7] = Default::default();
+        let mut suffix_start = None;
+        for (position, &(index, raw)) in present.iter().enumerate() {
+            let name = COMPONENT_NAMES[index];
+            components[index] = match raw {
+                "" => return Err(invalid(format!("{} is empty", name))),
+                "*" => ComponentPattern::Any,
+                "**" if position + 1 == present.len() => {
+                    suffix_start = Some(index);
+                    ComponentPattern::Any
+                }
+                "**" => return Err(invalid("\"**\" must be the last component".to_string())),
+                _ => {
+                    let component = unescape_component(raw).map_err(|e| invalid(format!("{}: {}", name, e)))?;
+                    if let Some(c) = component.chars().find(|c| c.is_whitespace() || c.is_control()) {
+                        return Err(invalid(format!("{} contains invalid character {:?}", name, c)));
+                    }
+                    ComponentPattern::Exact(component)
+                }
+            };
+        }
+
+        Ok(ServicePathPattern {
+            components,
+            suffix_start,
+        })
+    }
+
+    /// Check if the service path matches this pattern.
+    pub fn matches(&self, service_path: &ServicePath) -> bool {
+        let end = self.suffix_start.unwrap_or(self.components.len());
+        self.components[..end]
+            .iter()
+            .zip(Self::service_path_components(service_path))
+            .all(|(pattern, component)| pattern.matches(component))
+    }
+
+    /// Check if a service path under the prefix can match this pattern, e.g. `region-a.*.*/hamgrd/*` can match
+    /// services under the route prefix `region-a.cluster-a`. Only the non-empty components of the prefix are checked.
+    pub fn matches_prefix(&self, prefix: &ServicePath) -> bool {
+        let end = self.suffix_start.unwrap_or(self.components.len());
+        self.components[..end]
+            .iter()
+            .zip(Self::service_path_components(prefix))
+            .all(|(pattern, component)| component.is_empty() || pattern.matches(component))
+    }
+
+    fn service_path_components(service_path: &ServicePath) -> [&String; 7] {
+        [
+            &service_path.region_id,
+            &service_path.cluster_id,
+            &service_path.node_id,
+            &service_path.service_type,
+            &service_path.service_id,
+            &service_path.resource_type,
+            &service_path.resource_id,
+        ]
+    }
+
+    /// Return the exact service path if the pattern has no wildcard.
+    pub fn as_exact(&self) -> Option<ServicePath> {
+        if self.suffix_start.is_some() {
+            return None;
+        }
+        let mut components = Vec::with_capacity(self.components.len());
+        for component in &self.components {
+            match component {
+                ComponentPattern::Exact(value) => components.push(value.as_str()),
+                ComponentPattern::Any => return None,
+            }
+        }
+        Some(ServicePath::with_node(
+            components[0],
+            components[1],
+            components[2],
+            components[3],
+            components[4],
+            components[5],
+            components[6],
+        ))
+    }
+
+    pub fn specificity(&self) -> Specificity {
+        let end = self.suffix_start.unwrap_or(self.components.len());
+        let constrained = &self.components[..end];
+        Specificity {
+            literals: constrained
+                .iter()
+                .filter(|c| matches!(c, ComponentPattern::Exact(value) if !value.is_empty()))
+                .count(),
+            exact_length: self.suffix_start.is_none(),
+            wildcards: std::cmp::Reverse(constrained.iter().filter(|c| **c == ComponentPattern::Any).count()),
+        }
+    }
+
+    /// Compare the specificity of two patterns. Patterns with the same specificity are ordered by their components,
+    /// so the result is deterministic.
+    pub fn cmp_specificity(&self, other: &Self) -> Ordering {
+        self.specificity()
+            .cmp(&other.specificity())
+            .then_with(|| other.cmp(self))
+    }
+
+    fn format_component(component: &ComponentPattern, escape_dot: bool) -> String {
+        match component {
+            ComponentPattern::Exact(value) => escape_component(value, escape_dot),
+            ComponentPattern::Any => "*".to_string(),
+        }
+    }
+}
+
+impl From<ServicePath> for ServicePathPattern {
+    fn from(service_path: ServicePath) -> Self {
+        let to_pattern = |component: String| match component == SERVICE_PATH_WILDCARD {
+            true => ComponentPattern::Any,
+            false => ComponentPattern::Exact(component),
+        };
+        ServicePathPattern {
+            components: [
+                to_pattern(service_path.region_id),
+                to_pattern(service_path.cluster_id),
+                to_pattern(service_path.node_id),
+                to_pattern(service_path.service_type),
+                to_pattern(service_path.service_id),
+                to_pattern(service_path.resource_type),
+                to_pattern(service_path.resource_id),
+            ],
+            suffix_start: None,
+        }
+    }
+}
+
+impl Default for ComponentPattern {
+    fn default() -> Self {
+        ComponentPattern::Exact(String::new())
+    }
+}
+
+impl fmt::Display for ServicePathPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let is_absent = |c: &ComponentPattern| *c == ComponentPattern::default();
+        let end = self.suffix_start.unwrap_or(self.components.len());
+
+        let loc_end = end.min(SERVICE_START);
+        let loc = &self.components[..loc_end];
+        let loc_len = loc.iter().rposition(|c| !is_absent(c)).map_or(0, |i| i + 1);
+        let mut loc_parts: Vec<String> = loc[..loc_len]
+            .iter()
+            .enumerate()
+            .map(|(i, c)| Self::format_component(c, i + 1 < SERVICE_START))
+            .collect();
+        if self.suffix_start.is_some_and(|s| s < SERVICE_START) {
+            loc_parts.push("**".to_string());
+            return f.write_str(&loc_parts.join("."));
+        }
+        f.write_str(&loc_parts.join("."))?;
+
+        let rsc = &self.components[SERVICE_START..end];
+        let rsc_len = match self.suffix_start {
+            Some(_) => rsc.len(),
+            None => rsc
+                .iter()
+                .rposition(|c| !is_absent(c))
+                .map_or(0, |i| (i + 1).next_multiple_of(2)),
+        };
+        for component in &rsc[..rsc_len] {
+            write!(f, "/{}", Self::format_component(component, false))?;
+        }
+        if self.suffix_start.is_some() {
+            f.write_str("/**")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn sp(s: &str) -> ServicePath {
+        ServicePath::from_string(s).unwrap()
+    }
+
+    fn pattern(s: &str) -> ServicePathPattern {
+        ServicePathPattern::from_string(s).unwrap()
+    }
+
+    #[test]
+    fn pattern_can_be_parsed_and_displayed() {
+        for s in [
+            "region-a.*.*/hamgrd/*/hascope/*",
+            "region-a.cluster-a.10.0.0.1-dpu0",
+            "region-a.cluster-a.**",
+            "**",
+            "region-a.cluster-a.10.0.0.1-dpu0/**",
+            "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/**",
+            "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/**",
+            "/hamgrd/*",
+            "/**",
+            "region\\.a.*.node*",
+            "region-a.cluster-a.node/hamgrd/dpu*",
+        ] {
+            assert_eq!(pattern(s).to_string(), s);
+        }
+    }
+
+    #[test]
+    fn invalid_pattern_is_rejected() {
+        for s in [
+            "",
+            "region-a..*",
+            "region-a.**.node",
+            "region-a.**/hamgrd/0",
+            "region-a.cluster-a.node/**/0",
+            "region-a.cluster-a.node/hamgrd",
+            "region-a.cluster-a.node/hamgrd/0/hascope/dpu/**",
+            "region-a.cluster a.node",
+            "region-a.cluster-a.node/hamgrd/\\*",
+            "region-a.cluster-a.node/hamgrd/dpu\\*",
+        ] {
+            match ServicePathPattern::from_string(s) {
+                Err(SwbusError::InputError { code, .. }) => assert_eq!(code, SwbusErrorCode::InvalidArgs, "{}", s),
+                other => panic!("Expected InvalidArgs for {:?}, got {:?}", s, other),
+            }
+        }
+    }
+
+    #[test]
+    fn pattern_matches_service_paths() {
+        let family = pattern("region-a.*.*/hamgrd/*/hascope/*");
+        assert!(family.matches(&sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni0")));
+        assert!(family.matches(&sp("region-a.cluster-b.10.0.0.2-dpu0/hamgrd/1/hascope/eni1")));
+        assert!(!family.matches(&sp("region-b.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni0")));
+        assert!(!family.matches(&sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0")));
+        assert!(!family.matches(&sp("region-a.cluster-a.10.0.0.1-dpu0/swss/0/hascope/eni0")));
+
+        let cluster = pattern("region-a.cluster-a.**");
+        assert!(cluster.matches(&sp("region-a.cluster-a")));
+        assert!(cluster.matches(&sp("region-a.cluster-a.10.0.0.1-dpu0")));
+        assert!(cluster.matches(&sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni0")));
+        assert!(!cluster.matches(&sp("region-a.cluster-b.10.0.0.1-dpu0")));
+
+        let exact = pattern("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0");
+        assert!(exact.matches(&sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0")));
+        assert!(!exact.matches(&sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni0")));
+
+        assert!(pattern("**").matches(&sp("region-a")));
+        assert!(!pattern("*").matches(&sp("/hamgrd/0")));
+        assert!(pattern("region*").matches(&sp("region*")));
+        assert!(!pattern("region*").matches(&sp("region-a")));
+        // the node id is the last location component, so this is the literal node id "node.**"
+        assert!(pattern("region-a.cluster-a.node.**").matches(&sp("region-a.cluster-a.node.**")));
+        assert!(!pattern("region-a.cluster-a.node.**").matches(&sp("region-a.cluster-a.node/hamgrd/0")));
+    }
+
+    #[test]
+    fn pattern_can_be_created_from_service_path() {
+        let service_path = sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/dpu*");
+        let exact = ServicePathPattern::from(service_path.clone());
+        assert!(exact.matches(&service_path));
+        assert_eq!(exact.as_exact(), Some(service_path.clone()));
+        assert_eq!(
+            exact.to_string(),
+            "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/dpu*"
+        );
+        assert_eq!(pattern("region-a.*").as_exact(), None);
+        assert_eq!(pattern("region-a.**").as_exact(), None);
+
+        let group = ServicePathPattern::from(sp("region-a.cluster-a.*/hamgrd/*"));
+        assert_eq!(group, pattern("region-a.cluster-a.*/hamgrd/*"));
+        assert_eq!(group.as_exact(), None);
+    }
+
+    #[test]
+    fn pattern_matches_route_prefixes() {
+        let group = pattern("region-a.*.*/hamgrd/*");
+        assert!(group.matches_prefix(&sp("region-a")));
+        assert!(group.matches_prefix(&sp("region-a.cluster-b")));
+        assert!(group.matches_prefix(&sp("region-a.cluster-a.10.0.0.1-dpu0")));
+        assert!(group.matches_prefix(&sp("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0")));
+        assert!(!group.matches_prefix(&sp("region-b.cluster-a")));
+        assert!(!group.matches_prefix(&sp("region-a.cluster-a.10.0.0.1-dpu0/local-mgmt/0")));
+    }
+
+    #[test]
+    fn more_specific_pattern_wins() {
+        let mut patterns = [
+            pattern("**"),
+            pattern("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni0"),
+            pattern("region-a.*.*/hamgrd/*/hascope/*"),
+            pattern("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/**"),
+            pattern("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/*/hascope/*"),
+            pattern("region-a.cluster-a.**"),
+        ];
+        patterns.sort_by(|a, b| b.cmp_specificity(a));
+        let ordered: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+        assert_eq!(
+            ordered,
+            vec![
+                "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni0",
+                "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/*/hascope/*",
+                "region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/**",
+                "region-a.*.*/hamgrd/*/hascope/*",
+                "region-a.cluster-a.**",
+                "**",
+            ]
+        );
+    }
+}
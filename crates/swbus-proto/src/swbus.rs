@@ -1,7 +1,9 @@
 use super::result::*;
 use serde::ser::SerializeSeq;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
 use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 tonic::include_proto!("swbus");
 use crate::swbus::request_response::ResponseBody;
 
@@ -9,6 +11,14 @@ use crate::swbus::request_response::ResponseBody;
 pub const SWBUS_CLIENT_SERVICE_PATH: &str = "x-swbus-service-path";
 /// Service path scope of the connection
 pub const SWBUS_CONNECTION_TYPE: &str = "x-swbus-connection-type";
+/// Supported protocol version range in the request, negotiated version in the response
+pub const SWBUS_PROTOCOL_VERSION: &str = "x-swbus-protocol-version";
+/// Supported feature bits in the request, negotiated feature bits in the response
+pub const SWBUS_PROTOCOL_FEATURES: &str = "x-swbus-features";
+/// Swbus error code of a gRPC status converted from a `SwbusError`
+pub const SWBUS_ERROR_CODE: &str = "x-swbus-error-code";
+/// Component value that matches any single component of a group destination.
+pub const SERVICE_PATH_WILDCARD: &str = "*";
 
 impl ServicePath {
     /// Create a new region level service path.
@@ -70,42 +80,112 @@ impl ServicePath {
             resource_id: resource_id.to_string(),
         }
     }
-    /// Create a new service path from a string. Service path must be in below format
-    ///   [region_id[.cluster_id[.node_id]][/service_type/service_id[/resource_type/resource_id]]
+    /// Create a new service path from a string. Service path must be in below format:
+    ///
+    /// ```text
+    /// service_path := location ["/" service_type "/" service_id ["/" resource_type "/" resource_id]]
+    /// location     := "" | region_id ["." cluster_id ["." node_id]]
+    /// ```
+    ///
+    /// Every component that is present must be non-empty and must not contain whitespace or control characters.
+    /// `.`, `/` and `\` inside a component are escaped with a backslash, e.g. `\.`. The node id is the last
+    /// location component, so dots in it don't need to be escaped (e.g. `region-a.cluster-a.10.0.0.1-dpu0`).
+    /// A component that is exactly `*` is a wildcard, see [`is_group`](Self::is_group). `*` is never escaped in a
+    /// service path, so a literal `*` component can't be expressed, and `\*` is rejected.
+    ///
+    /// The output of `Display` and [`to_longest_path`](Self::to_longest_path) can always be parsed back into the
+    /// same service path.
     pub fn from_string(service_path: &str) -> Result<Self> {
-        let mut parts: Vec<&str> = service_path.split('/').collect();
-        // fill up service and resource with empty string
-        parts.resize(5, "");
-        // parts[0] is locator region.cluster.node
-        let mut loc_parts: Vec<&str> = parts[0].splitn(3, '.').collect();
-        loc_parts.resize(3, "");
+        let invalid = |detail: String| {
+            SwbusError::input(
+                SwbusErrorCode::InvalidArgs,
+                format!("Invalid service path \"{}\": {}", service_path, detail),
+            )
+        };
+
+        if service_path.is_empty() {
+            return Err(invalid("service path is empty".to_string()));
+        }
+
+        let parts = split_unescaped(service_path, '/', usize::MAX);
+        if parts.len() != 1 && parts.len() != 3 && parts.len() != 5 {
+            return Err(invalid(format!(
+                "expected location[/service_type/service_id[/resource_type/resource_id]], but found {} '/' separated parts",
+                parts.len()
+            )));
+        }
+
+        // The location can be omitted only when the service is specified.
+        let loc_parts = match parts[0].is_empty() && parts.len() > 1 {
+            true => vec![],
+            false => split_unescaped(parts[0], '.', 3),
+        };
+
+        const NAMES: [&str; 7] = [
+            "region_id",
+            "cluster_id",
+            "node_id",
+            "service_type",
+            "service_id",
+            "resource_type",
+            "resource_id",
+        ];
+        let present = loc_parts
+            .into_iter()
+            .enumerate()
+            .chain(parts[1..].iter().enumerate().map(|(i, part)| (i + 3, *part)));
+        let mut components: [String; 7] = Default::default();
+        for (index, raw) in present {
+            let name = NAMES[index];
+            if raw.is_empty() {
+                return Err(invalid(format!("{} is empty", name)));
+            }
+            let component = unescape_component(raw).map_err(|e| invalid(format!("{}: {}", name, e)))?;
+            if let Some(c) = component.chars().find(|c| c.is_whitespace() || c.is_control()) {
+                return Err(invalid(format!("{} contains invalid character {:?}", name, c)));
+            }
+            components[index] = component;
+        }
+
+        let [region_id, cluster_id, node_id, service_type, service_id, resource_type, resource_id] = components;
         Ok(ServicePath {
-            region_id: loc_parts[0].to_string(),
-            cluster_id: loc_parts[1].to_string(),
-            node_id: loc_parts[2].to_string(),
-            service_type: parts[1].to_string(),
-            service_id: parts[2].to_string(),
-            resource_type: parts[3].to_string(),
-            resource_id: parts[4].to_string(),
+            region_id,
+            cluster_id,
+            node_id,
+            service_type,
+            service_id,
+            resource_type,
+            resource_id,
         })
     }
 
     pub fn to_regional_prefix(&self) -> String {
-        self.region_id.clone()
+        escape_component(&self.region_id, true)
     }
 
     pub fn to_cluster_prefix(&self) -> String {
-        format!("{}.{}", self.region_id, self.cluster_id)
+        format!(
+            "{}.{}",
+            escape_component(&self.region_id, true),
+            escape_component(&self.cluster_id, true)
+        )
     }
 
     pub fn to_node_prefix(&self) -> String {
-        format!("{}.{}.{}", self.region_id, self.cluster_id, self.node_id)
+        format!(
+            "{}.{}.{}",
+            escape_component(&self.region_id, true),
+            escape_component(&self.cluster_id, true),
+            escape_component(&self.node_id, false)
+        )
     }
 
     pub fn to_service_prefix(&self) -> String {
         format!(
-            "{}.{}.{}/{}/{}",
-            self.region_id, self.cluster_id, self.node_id, self.service_type, self.service_id
+            "{}/{}/{}",
+            self.to_node_prefix(),
+            escape_component(&self.service_type, false),
+            escape_component(&self.service_id, false)
         )
     }
 
@@ -121,28 +201,56 @@ impl ServicePath {
         }
     }
 
+    /// Format the service path in the canonical format accepted by [`from_string`](Self::from_string),
+    /// omitting the empty trailing components. This is the same as `Display`.
     pub fn to_longest_path(&self) -> String {
-        let loc_str = vec![self.region_id.as_str(), self.cluster_id.as_str(), self.node_id.as_str()]
-            .into_iter()
-            .take_while(|x| !x.is_empty())
-            .collect::<Vec<&str>>()
+        let loc = [&self.region_id, &self.cluster_id, &self.node_id];
+        let loc_len = loc.iter().rposition(|x| !x.is_empty()).map_or(0, |i| i + 1);
+        let loc_str = loc[..loc_len]
+            .iter()
+            .enumerate()
+            .map(|(i, x)| escape_component(x, i < 2))
+            .collect::<Vec<String>>()
             .join(".");
-        let rsc_str = vec![
-            self.service_type.as_str(),
-            self.service_id.as_str(),
-            self.resource_type.as_str(),
-            self.resource_id.as_str(),
-        ]
-        .into_iter()
-        .take_while(|x| !x.is_empty())
-        .collect::<Vec<&str>>()
-        .join("/");
-        match rsc_str.is_empty() {
-            true => loc_str.to_string(),
-            false => format!("{}/{}", loc_str, rsc_str),
+
+        let rsc = [
+            &self.service_type,
+            &self.service_id,
+            &self.resource_type,
+            &self.resource_id,
+        ];
+        let rsc_len = rsc.iter().rposition(|x| !x.is_empty()).map_or(0, |i| i + 1);
+        match rsc_len {
+            0 => loc_str,
+            _ => {
+                // service and resource components always come in pairs
+                let rsc_str = rsc[..rsc_len.next_multiple_of(2)]
+                    .iter()
+                    .map(|x| escape_component(x, false))
+                    .collect::<Vec<String>>()
+                    .join("/");
+                format!("{}/{}", loc_str, rsc_str)
+            }
         }
     }
 
+    /// Check if this is a group destination, i.e. some of its location or service components are the wildcard
+    /// `*`, e.g. `region-a.cluster-a.*/hamgrd/*` for every hamgrd in a cluster. Messages sent to a group destination
+    /// are delivered to every service matching it.
+    pub fn is_group(&self) -> bool {
+        [
+            &self.region_id,
+            &self.cluster_id,
+            &self.node_id,
+            &self.service_type,
+            &self.service_id,
+            &self.resource_type,
+            &self.resource_id,
+        ]
+        .iter()
+        .any(|component| component.as_str() == SERVICE_PATH_WILDCARD)
+    }
+
     pub fn route_scope(&self) -> RouteScope {
         if self.cluster_id.is_empty() {
             return RouteScope::Global;
@@ -159,37 +267,58 @@ impl ServicePath {
 
 impl fmt::Display for ServicePath {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if !self.node_id.is_empty() {
-            write!(
-                f,
-                "{}.{}.{}/{}/{}/{}/{}",
-                self.region_id,
-                self.cluster_id,
-                self.node_id,
-                self.service_type,
-                self.service_id,
-                self.resource_type,
-                self.resource_id
-            )
-        } else if !self.cluster_id.is_empty() {
-            write!(
-                f,
-                "{}.{}/{}/{}/{}/{}",
-                self.region_id,
-                self.cluster_id,
-                self.service_type,
-                self.service_id,
-                self.resource_type,
-                self.resource_id
-            )
-        } else {
-            write!(
-                f,
-                "{}/{}/{}/{}/{}",
-                self.region_id, self.service_type, self.service_id, self.resource_type, self.resource_id
-            )
+        f.write_str(&self.to_longest_path())
+    }
+}
+
+/// Escape `\`, `/` and optionally `.` in a service path component.
+pub(crate) fn escape_component(component: &str, escape_dot: bool) -> String {
+    let mut escaped = String::with_capacity(component.len());
+    for c in component.chars() {
+        if c == '\\' || c == '/' || (escape_dot && c == '.') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Resolve the escape sequences in a service path component.
+pub(crate) fn unescape_component(component: &str) -> std::result::Result<String, String> {
+    let mut unescaped = String::with_capacity(component.len());
+    let mut chars = component.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(escaped @ ('\\' | '/' | '.')) => unescaped.push(escaped),
+            Some(other) => return Err(format!("invalid escape sequence \"\\{}\"", other)),
+            None => return Err("dangling escape character at the end".to_string()),
+        }
+    }
+    Ok(unescaped)
+}
+
+/// Split a string on the separators that are not escaped, into at most `max_parts` parts.
+/// Escape sequences are kept as is in the returned parts.
+pub(crate) fn split_unescaped(s: &str, separator: char, max_parts: usize) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == separator && parts.len() + 1 < max_parts {
+            parts.push(&s[start..i]);
+            start = i + c.len_utf8();
         }
     }
+    parts.push(&s[start..]);
+    parts
 }
 
 // Custom serializer
@@ -210,7 +339,10 @@ pub fn deserialize_service_path_opt<'de, D>(deserializer: D) -> Result<Option<Se
 where
     D: Deserializer<'de>,
 {
-    let s = String::deserialize(deserializer)?;
+    // `serialize_service_path_opt` writes null for None
+    let Some(s) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
 
     match ServicePath::from_string(&s) {
         Ok(sp) => Ok(Some(sp)),
@@ -221,6 +353,26 @@ where
     }
 }
 
+pub fn serialize_service_path_vec<S>(sps: &[ServicePath], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_seq(sps.iter().map(|sp| sp.to_longest_path()))
+}
+
+pub fn deserialize_service_path_vec<'de, D>(deserializer: D) -> Result<Vec<ServicePath>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| {
+            ServicePath::from_string(s)
+                .map_err(|_| serde::de::Error::custom(format!("Failed to parse service path from string: {}", s)))
+        })
+        .collect()
+}
+
 // Custom deserializer
 pub fn deserialize_service_path<'de, D>(deserializer: D) -> Result<ServicePath, D::Error>
 where
@@ -237,6 +389,15 @@ where
     }
 }
 
+impl PartialOrd for RegistrationEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        match self.service_path.partial_cmp(&other.service_path) {
+            Some(std::cmp::Ordering::Equal) => self.edge_service_path.partial_cmp(&other.edge_service_path),
+            x => x,
+        }
+    }
+}
+
 impl PartialOrd for RouteQueryResultEntry {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         match self.service_path.partial_cmp(&other.service_path) {
@@ -282,14 +443,57 @@ impl SwbusMessageHeader {
     /// See [`SwbusMessageHeader::id`] for notes on what a message ID should be.
     pub fn new(source: ServicePath, destination: ServicePath, id: u64) -> Self {
         SwbusMessageHeader {
-            version: 1,
+            version: crate::protocol::SWBUS_MAX_PROTOCOL_VERSION,
             id,
             flag: 0,
             ttl: 64,
+            deadline_ms: 0,
+            payload_compression: Compression::None as i32,
             source: Some(source),
             destination: Some(destination),
         }
     }
+
+    /// Set the deadline of the message to `timeout` from now.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        self.with_deadline(SystemTime::now() + timeout)
+    }
+
+    /// Set the absolute deadline of the message, see [`SwbusMessageHeader::deadline_ms`].
+    pub fn with_deadline(mut self, deadline: SystemTime) -> Self {
+        // 0 means no deadline, so a deadline at the epoch is moved by 1ms and still expired.
+        self.deadline_ms = epoch_millis(deadline).max(1);
+        self
+    }
+
+    /// The deadline of the message, or `None` if it never expires.
+    pub fn deadline(&self) -> Option<SystemTime> {
+        match self.deadline_ms {
+            0 => None,
+            ms => Some(UNIX_EPOCH + Duration::from_millis(ms)),
+        }
+    }
+
+    /// Check if the deadline of the message has passed.
+    pub fn is_expired(&self) -> bool {
+        self.is_expired_at(SystemTime::now())
+    }
+
+    pub fn is_expired_at(&self, now: SystemTime) -> bool {
+        self.deadline_ms != 0 && self.deadline_ms <= epoch_millis(now)
+    }
+}
+
+impl SwbusMessage {
+    /// Check if the deadline of the message has passed. Messages without header never expire.
+    pub fn is_expired(&self) -> bool {
+        self.header.as_ref().is_some_and(|header| header.is_expired())
+    }
+}
+
+fn epoch_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64)
 }
 
 impl RequestResponse {
@@ -299,19 +503,37 @@ impl RequestResponse {
             request_id,
             error_code: SwbusErrorCode::Ok as i32,
             error_message: "".to_string(),
+            error_details: HashMap::new(),
             response_body: None,
         }
     }
 
+    /// Create a new OK response carrying the result of a data request.
+    pub fn ok_with_payload(request_id: u64, payload: Vec<u8>) -> Self {
+        RequestResponse {
+            response_body: Some(request_response::ResponseBody::Payload(payload)),
+            ..RequestResponse::ok(request_id)
+        }
+    }
+
     /// Create a new infra error response.
     pub fn infra_error(request_id: u64, error_code: SwbusErrorCode, error_message: &str) -> Self {
         RequestResponse {
             request_id,
             error_code: error_code as i32,
             error_message: error_message.to_string(),
+            error_details: HashMap::new(),
             response_body: None,
         }
     }
+
+    /// Create an infra error response carrying `error`, with its code, message and details.
+    pub fn from_error(request_id: u64, error: &SwbusError) -> Self {
+        RequestResponse {
+            error_details: error.details().clone(),
+            ..RequestResponse::infra_error(request_id, error.code(), &error.detail())
+        }
+    }
 }
 
 impl SwbusMessage {
@@ -390,6 +612,38 @@ impl TraceRouteResponse {
     }
 }
 
+impl RegistrationRequest {
+    /// Register `services` in addition to the ones registered before.
+    pub fn register(services: Vec<ServicePath>) -> Self {
+        RegistrationRequest {
+            register: services,
+            ..Default::default()
+        }
+    }
+
+    pub fn unregister(services: Vec<ServicePath>) -> Self {
+        RegistrationRequest {
+            unregister: services,
+            ..Default::default()
+        }
+    }
+
+    /// Register `services` in place of the ones registered before.
+    pub fn replace(services: Vec<ServicePath>) -> Self {
+        RegistrationRequest {
+            register: services,
+            unregister: Vec::new(),
+            replace: true,
+        }
+    }
+}
+
+impl RegistrationQueryResponse {
+    pub fn new(request_id: u64, entries: Vec<RegistrationEntry>) -> Self {
+        RegistrationQueryResponse { request_id, entries }
+    }
+}
+
 impl ManagementRequest {
     pub fn new(request: &str) -> Self {
         ManagementRequest {
@@ -401,7 +655,38 @@ impl ManagementRequest {
 
 impl DataRequest {
     pub fn new(payload: Vec<u8>) -> Self {
-        DataRequest { payload }
+        DataRequest {
+            payload,
+            reliable: None,
+            content_type: ContentType::Unspecified as i32,
+        }
+    }
+}
+
+impl SubscribeRequest {
+    pub fn new(topic: &str, publisher: &str) -> Self {
+        SubscribeRequest {
+            topic: topic.to_string(),
+            publisher: publisher.to_string(),
+        }
+    }
+}
+
+impl UnsubscribeRequest {
+    pub fn new(topic: &str, publisher: &str) -> Self {
+        UnsubscribeRequest {
+            topic: topic.to_string(),
+            publisher: publisher.to_string(),
+        }
+    }
+}
+
+impl PublishRequest {
+    pub fn new(topic: &str, payload: Vec<u8>) -> Self {
+        PublishRequest {
+            topic: topic.to_string(),
+            payload,
+        }
     }
 }
 
@@ -411,6 +696,7 @@ mod tests {
 
     use super::*;
     use pretty_assertions::assert_eq;
+    use proptest::prelude::*;
 
     #[test]
     fn service_path_can_be_converted_to_string() {
@@ -469,13 +755,22 @@ mod tests {
 
     #[test]
     fn registration_query_request_can_be_created() {
-        let request = RegistrationQueryRequest {};
+        let request = RegistrationQueryRequest::default();
         test_packing_with_swbus_message(swbus_message::Body::RegistrationQueryRequest(request));
     }
 
     #[test]
     fn registration_query_response_can_be_created() {
-        let response = RegistrationQueryResponse {};
+        let response = RegistrationQueryResponse::new(
+            create_mock_message_id(),
+            vec![RegistrationEntry {
+                service_path: Some(
+                    ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/eni0").unwrap(),
+                ),
+                edge_service_path: Some(ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0").unwrap()),
+                conn_id: "swbs-from://127.0.0.1:61000".to_string(),
+            }],
+        );
         test_packing_with_swbus_message(swbus_message::Body::RegistrationQueryResponse(response));
     }
 
@@ -491,6 +786,30 @@ mod tests {
         test_packing_with_swbus_message(swbus_message::Body::TraceRouteRequest(request));
     }
 
+    #[test]
+    fn pubsub_requests_can_be_created() {
+        let request = SubscribeRequest::new("ha-scope-state", "region-a.cluster-a.*/hamgrd/**");
+        test_packing_with_swbus_message(swbus_message::Body::SubscribeRequest(request));
+
+        let request = UnsubscribeRequest::new("ha-scope-state", "");
+        test_packing_with_swbus_message(swbus_message::Body::UnsubscribeRequest(request));
+
+        let request = PublishRequest::new("ha-scope-state", vec![1, 2, 3]);
+        test_packing_with_swbus_message(swbus_message::Body::PublishRequest(request));
+    }
+
+    #[test]
+    fn service_path_can_be_a_group() {
+        assert!(!ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0")
+            .unwrap()
+            .is_group());
+        assert!(ServicePath::from_string("region-a.cluster-a.*/hamgrd/*")
+            .unwrap()
+            .is_group());
+        assert!(ServicePath::from_string("region-a.*").unwrap().is_group());
+        assert!(!ServicePath::from_string("region-a.cluster-a.dpu*").unwrap().is_group());
+    }
+
     #[test]
     fn trace_route_response_can_be_created() {
         let response = TraceRouteResponse::new("mock-trace-id");
@@ -567,6 +886,117 @@ mod tests {
         assert_eq!(ServicePath::from_string(sp_str.as_str()).unwrap(), service_path);
     }
 
+    #[test]
+    fn test_service_path_escaping() {
+        let service_path = ServicePath::with_node(
+            "region.a",
+            "cluster/a",
+            "10.0.0.1-dpu0",
+            "hamgrd",
+            "0",
+            "hascope",
+            "eni\\0/1",
+        );
+        let sp_str = service_path.to_string();
+        assert_eq!(
+            sp_str,
+            "region\\.a.cluster\\/a.10.0.0.1-dpu0/hamgrd/0/hascope/eni\\\\0\\/1"
+        );
+        assert_eq!(sp_str, service_path.to_longest_path());
+        assert_eq!(ServicePath::from_string(&sp_str).unwrap(), service_path);
+
+        // '*' is kept literally inside a component, only a whole '*' component is a wildcard
+        let service_path = ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0/hamgrd/0/hascope/dpu*").unwrap();
+        assert_eq!(service_path.resource_id, "dpu*");
+        assert!(!service_path.is_group());
+        assert_eq!(
+            ServicePath::from_string(&service_path.to_string()).unwrap(),
+            service_path
+        );
+        let group = ServicePath::from_string("region-a.cluster-a.*/hamgrd/0").unwrap();
+        assert!(group.is_group());
+        assert_eq!(ServicePath::from_string(&group.to_string()).unwrap(), group);
+
+        // dots in node id may be escaped as well
+        assert_eq!(
+            ServicePath::from_string("region-a.cluster-a.10\\.0.0.1-dpu0").unwrap(),
+            ServicePath::from_string("region-a.cluster-a.10.0.0.1-dpu0").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_service_path_rejects_invalid_strings() {
+        let invalid_paths = [
+            "",
+            "region..node",
+            "region.cluster.",
+            ".cluster.node",
+            "region.cluster.node/hamgrd",
+            "region.cluster.node/hamgrd/0/hascope",
+            "region.cluster.node/hamgrd/0/hascope/dpu/extra",
+            "region.cluster.node//0",
+            "region.cluster.node/hamgrd/0//dpu",
+            "region.cluster.node/",
+            "region cluster",
+            "region.cluster.node/hamgrd/\t",
+            "region\\x.cluster",
+            "region.cluster\\",
+            "region.cluster.node/hamgrd/\\*",
+            "region.cluster.node/hamgrd/dpu\\*",
+        ];
+        for sp_str in invalid_paths {
+            match ServicePath::from_string(sp_str) {
+                Err(SwbusError::InputError { code, .. }) => assert_eq!(code, SwbusErrorCode::InvalidArgs, "{}", sp_str),
+                other => panic!("Expected InvalidArgs for {:?}, got {:?}", sp_str, other),
+            }
+        }
+    }
+
+    fn service_path_component() -> impl Strategy<Value = String> {
+        "[a-zA-Z0-9./*\\\\:_-]{1,8}"
+    }
+
+    prop_compose! {
+        fn valid_service_path()(
+            loc_len in 0..=3usize,
+            rsc_len in prop_oneof![Just(0usize), Just(2), Just(4)],
+            components in proptest::collection::vec(service_path_component(), 7),
+        ) -> ServicePath {
+            // location can only be omitted when service is present
+            let loc_len = if rsc_len == 0 { loc_len.max(1) } else { loc_len };
+            let pick = |i: usize, len: usize, base: usize| match i - base < len {
+                true => components[i].clone(),
+                false => String::new(),
+            };
+            ServicePath {
+                region_id: pick(0, loc_len, 0),
+                cluster_id: pick(1, loc_len, 0),
+                node_id: pick(2, loc_len, 0),
+                service_type: pick(3, rsc_len, 3),
+                service_id: pick(4, rsc_len, 3),
+                resource_type: pick(5, rsc_len, 3),
+                resource_id: pick(6, rsc_len, 3),
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn service_path_display_and_parse_round_trip(service_path in valid_service_path()) {
+            let sp_str = service_path.to_string();
+            prop_assert_eq!(&sp_str, &service_path.to_longest_path());
+            prop_assert_eq!(ServicePath::from_string(&sp_str).unwrap(), service_path);
+        }
+
+        #[test]
+        fn service_path_parse_and_display_round_trip(sp_str in "[a-z0-9./\\\\]{0,24}") {
+            // Any string that parses successfully must be re-formatted into an equivalent string.
+            if let Ok(service_path) = ServicePath::from_string(&sp_str) {
+                prop_assert_eq!(ServicePath::from_string(&service_path.to_string()).unwrap(), service_path);
+            }
+        }
+    }
+
     #[test]
     fn test_swbus_message_new_response() {
         let request = SwbusMessage::new(
@@ -594,4 +1024,28 @@ mod tests {
 
         // assert_eq!(response.body.as_ref().unwrap().request_, true);
     }
+
+    #[test]
+    fn test_swbus_message_header_deadline() {
+        let header = create_mock_swbus_message_header();
+        assert_eq!(header.deadline(), None);
+        assert!(!header.is_expired());
+
+        let deadline = UNIX_EPOCH + Duration::from_millis(1_700_000_000_000);
+        let header = header.with_deadline(deadline);
+        assert_eq!(header.deadline_ms, 1_700_000_000_000);
+        assert_eq!(header.deadline(), Some(deadline));
+        assert!(!header.is_expired_at(deadline - Duration::from_millis(1)));
+        assert!(header.is_expired_at(deadline));
+        assert!(header.is_expired());
+
+        let header = header.with_timeout(Duration::from_secs(60));
+        assert!(!header.is_expired());
+        assert!(header.is_expired_at(SystemTime::now() + Duration::from_secs(61)));
+
+        // responses don't inherit the deadline of the request
+        let request = SwbusMessage::new(header, swbus_message::Body::PingRequest(PingRequest::new()));
+        let response = SwbusMessage::new_response(&request, None, SwbusErrorCode::Ok, "", 1, None);
+        assert_eq!(response.header.as_ref().unwrap().deadline_ms, 0);
+    }
 }
@@ -1,8 +1,12 @@
 use clap::Parser;
 use sonic_common::log;
-use swbus_core::mux::route_config::RoutesConfig;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use swbus_core::mux::route_config::{ConfigIssue, RoutesConfig};
 use swbus_core::mux::service::SwbusServiceHost;
-use tracing::info;
+use swbus_proto::message_id_generator::{FileMessageIdStore, MessageIdGenerator};
+use tracing::{error, info};
 #[derive(Parser, Debug)]
 #[command(name = "swbusd")]
 struct Args {
@@ -12,17 +16,103 @@ struct Args {
     /// The initial routes of swbusd in yaml file
     #[arg(short = 'r', long)]
     route_config: String,
+    /// The address to serve Prometheus metrics at, e.g. 127.0.0.1:9090. Disabled if not set.
+    #[arg(short = 'm', long)]
+    metrics_address: Option<SocketAddr>,
+    /// The number of configured peers that must be connected before the gRPC health service reports SERVING.
+    #[arg(long, default_value_t = 0)]
+    min_established_peers: usize,
+    /// The file to save the message id high-water mark in, which keeps message ids unique across restarts even if
+    /// the clock steps backwards. Message ids only follow the clock if not set.
+    #[arg(long)]
+    message_id_state_file: Option<PathBuf>,
+    /// Validate the address and route config, print any problem found and exit without starting swbusd
+    #[arg(long)]
+    check: bool,
+}
+
+/// Load and validate the route config, including the checks that depend on the command line arguments.
+fn load_config(args: &Args) -> Result<RoutesConfig, Vec<ConfigIssue>> {
+    let route_config = RoutesConfig::load_from_yaml(args.route_config.clone())
+        .map_err(|e| vec![ConfigIssue::new(&args.route_config, format!("failed to load: {}", e))])?;
+
+    let mut issues = route_config.validate().err().unwrap_or_default();
+    match args.address.parse::<SocketAddr>() {
+        Ok(address) => {
+            for (i, peer) in route_config.peers.iter().enumerate() {
+                if peer.endpoint == address {
+                    issues.push(ConfigIssue::new(
+                        format!("peers[{}].endpoint", i),
+                        format!("endpoint {} is the address swbusd listens on", address),
+                    ));
+                }
+            }
+        }
+        Err(e) => issues.push(ConfigIssue::new(
+            "--address",
+            format!("\"{}\" is not a valid socket address: {}", args.address, e),
+        )),
+    }
+
+    match issues.is_empty() {
+        true => Ok(route_config),
+        false => Err(issues),
+    }
 }
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     let args = Args::parse();
 
+    if args.check {
+        return match load_config(&args) {
+            Ok(_) => {
+                println!("{}: configuration is valid", args.route_config);
+                ExitCode::SUCCESS
+            }
+            Err(issues) => {
+                for issue in &issues {
+                    eprintln!("error: {}", issue);
+                }
+                eprintln!("{}: found {} problem(s)", args.route_config, issues.len());
+                ExitCode::FAILURE
+            }
+        };
+    }
+
     if let Err(e) = log::init("swbusd") {
         eprintln!("Failed to initialize logging: {}", e);
     }
     info!("Starting swbusd");
-    let route_config = RoutesConfig::load_from_yaml(args.route_config).unwrap();
-    let server = SwbusServiceHost::new(args.address);
-    server.start(route_config).await.unwrap();
+    let route_config = match load_config(&args) {
+        Ok(route_config) => route_config,
+        Err(issues) => {
+            for issue in &issues {
+                error!("Invalid configuration: {}", issue);
+                eprintln!("error: {}", issue);
+            }
+            return ExitCode::FAILURE;
+        }
+    };
+    let id_generator = match args.message_id_state_file {
+        Some(state_file) => match MessageIdGenerator::with_store(FileMessageIdStore::new(state_file)) {
+            Ok(id_generator) => id_generator,
+            Err(e) => {
+                error!("Failed to load message id state: {}", e);
+                eprintln!("error: {}", e);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => MessageIdGenerator::new(),
+    };
+    let mut server = SwbusServiceHost::with_message_id_generator(args.address, id_generator)
+        .with_min_established_peers(args.min_established_peers);
+    if let Some(metrics_address) = args.metrics_address {
+        server = server.with_metrics_addr(metrics_address);
+    }
+    if let Err(e) = server.start(route_config).await {
+        error!("swbusd stopped: {}", e);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
 }